@@ -1,4 +1,8 @@
-use rust_chat::net::server::Server;
+use rust_chat::net::{
+    config::{ServerConfig, TlsConfig},
+    server::Server,
+};
+use std::{env, path::PathBuf, process};
 
 const THREADS_AMOUNT: usize = 4;
 
@@ -7,7 +11,42 @@ fn main() {
         .parse()
         .expect("Error while parsing address!");
 
-    let server = Server::new(THREADS_AMOUNT, address);
+    // rust_chat [--tls <cert.pem> <key.pem>] [--operator <name>]...
+    let mut tls = None;
+    let mut operators = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--tls", Some(cert_path)) => match args.next() {
+                Some(key_path) => {
+                    tls = Some(TlsConfig {
+                        cert_path: PathBuf::from(cert_path),
+                        key_path: PathBuf::from(key_path),
+                    })
+                }
+                None => usage(),
+            },
+            ("--operator", Some(user_name)) => operators.push(user_name),
+            _ => usage(),
+        }
+    }
+
+    let server = Server::bind(ServerConfig {
+        address,
+        connection_thread_amount: THREADS_AMOUNT,
+        credentials_path: Some(PathBuf::from("accounts.jsonl")),
+        tls,
+        operators,
+        bans_path: Some(PathBuf::from("bans.jsonl")),
+        rooms_path: Some(PathBuf::from("rooms.jsonl")),
+        history_path: Some(PathBuf::from("history.jsonl")),
+        offline_messages_path: Some(PathBuf::from("offline_messages.jsonl")),
+        ..ServerConfig::default()
+    })
+    .unwrap_or_else(|err| {
+        eprintln!("Error while starting server: {}", err);
+        process::exit(1);
+    });
     let server_stop = server.get_server_stop();
 
     ctrlc::set_handler(move || {
@@ -18,3 +57,8 @@ fn main() {
 
     server.join();
 }
+
+fn usage() -> ! {
+    eprintln!("Usage: rust_chat [--tls <cert.pem> <key.pem>] [--operator <name>]...");
+    process::exit(1);
+}
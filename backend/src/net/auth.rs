@@ -0,0 +1,355 @@
+use crate::net::{config::ServerConfig, error::ServerError, record_file::RecordFile};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::{rngs::OsRng, RngCore};
+use rust_chat_protocol::messages::{Auth, LoginError, MIN_PASSWORD_LENGTH};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::IpAddr,
+    sync::{
+        mpsc::{channel, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Random bytes of a session token, sent hex encoded.
+const SESSION_TOKEN_SIZE: usize = 32;
+
+struct Session {
+    user_name: String,
+    expires: Instant,
+}
+
+/// One line of the accounts file.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    user_name: String,
+    /// In the PHC string format.
+    password_hash: String,
+}
+
+/// Attempts of an address in the current window of a rate limit.
+struct Attempts {
+    since: Instant,
+    amount: usize,
+}
+
+type PasswordJob = Box<dyn FnOnce() + Send>;
+
+/// Accounts with salted password hashes, session tokens and the rate limits of failed logins
+/// and new accounts, shared by all threads.
+pub struct Authenticator {
+    config: Arc<ServerConfig>,
+    /// User name -> password hash in the PHC string format.
+    accounts: Mutex<HashMap<String, String>>,
+    /// Accounts are only added, the file is never compacted.
+    account_file: Mutex<RecordFile>,
+    /// Session token -> user name.
+    sessions: Mutex<HashMap<String, Session>>,
+    failed_logins: Mutex<HashMap<IpAddr, Attempts>>,
+    account_creations: Mutex<HashMap<IpAddr, Attempts>>,
+    /// Runs the jobs of `check_password_later` one after another.
+    password_job_sender: Sender<PasswordJob>,
+}
+
+impl Authenticator {
+    pub fn load(config: Arc<ServerConfig>) -> Result<Self, ServerError> {
+        let mut accounts = HashMap::new();
+        let account_file = RecordFile::open(
+            "accounts",
+            config.credentials_path.as_deref(),
+            |record: Record| {
+                accounts.insert(record.user_name, record.password_hash);
+            },
+        )
+        .map_err(ServerError::CredentialStore)?;
+
+        // the thread ends with the authenticator
+        let (password_job_sender, password_job_receiver) = channel::<PasswordJob>();
+        thread::Builder::new()
+            .name("Passwords".to_string())
+            .spawn(move || {
+                for job in password_job_receiver {
+                    job();
+                }
+            })
+            .map_err(|err| ServerError::SpawnThread("Passwords".to_string(), err))?;
+
+        Ok(Self {
+            config,
+            accounts: Mutex::new(accounts),
+            account_file: Mutex::new(account_file),
+            sessions: Mutex::new(HashMap::new()),
+            failed_logins: Mutex::new(HashMap::new()),
+            account_creations: Mutex::new(HashMap::new()),
+            password_job_sender,
+        })
+    }
+
+    /// Runs `job` on the password thread, argon2 takes too long for the event loops. The job
+    /// calls `authenticate` and reports the result back to the connection.
+    pub fn check_password_later<F: FnOnce() + Send + 'static>(&self, job: F) {
+        if self.password_job_sender.send(Box::new(job)).is_err() {
+            eprintln!("Error while checking password: password thread stopped");
+        }
+    }
+
+    pub fn has_account(&self, user_name: &str) -> bool {
+        self.accounts.lock().unwrap().contains_key(user_name)
+    }
+
+    /// Returns the session token for logins with credentials, `None` for guests. Logins with
+    /// password are slow, see `check_password_later`.
+    pub fn authenticate(
+        &self,
+        user_name: &str,
+        auth: Option<Auth>,
+        address: IpAddr,
+    ) -> Result<Option<String>, LoginError> {
+        if is_rate_limited(
+            &self.failed_logins,
+            address,
+            self.config.max_failed_logins,
+            self.config.failed_login_window,
+        ) {
+            return Err(LoginError::TooManyAttempts);
+        }
+
+        let result = match auth {
+            None => {
+                if !self.config.allow_guests
+                    || self.accounts.lock().unwrap().contains_key(user_name)
+                {
+                    Err(LoginError::AuthRequired)
+                } else {
+                    Ok(None)
+                }
+            }
+            Some(Auth::Password(password)) => self
+                .check_password(user_name, &password, address)
+                .map(|_| Some(self.new_session(user_name))),
+            Some(Auth::Token(token)) => self.check_token(user_name, token).map(Some),
+        };
+
+        if result == Err(LoginError::InvalidCredentials) {
+            attempt(
+                &self.failed_logins,
+                address,
+                self.config.failed_login_window,
+            );
+        }
+
+        result
+    }
+
+    /// Creates the account if the user has none.
+    fn check_password(
+        &self,
+        user_name: &str,
+        password: &str,
+        address: IpAddr,
+    ) -> Result<(), LoginError> {
+        // hashing is slow, other logins should not wait for it
+        let password_hash = self.accounts.lock().unwrap().get(user_name).cloned();
+
+        match password_hash {
+            Some(password_hash) => {
+                let password_hash = PasswordHash::new(&password_hash)
+                    .map_err(|_| LoginError::InvalidCredentials)?;
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &password_hash)
+                    .map_err(|_| LoginError::InvalidCredentials)
+            }
+            None => {
+                if password.chars().count() < MIN_PASSWORD_LENGTH {
+                    return Err(LoginError::WeakPassword);
+                }
+                if is_rate_limited(
+                    &self.account_creations,
+                    address,
+                    self.config.max_account_creations,
+                    self.config.account_creation_window,
+                ) {
+                    return Err(LoginError::TooManyAttempts);
+                }
+
+                let salt = SaltString::generate(&mut OsRng);
+                let password_hash = Argon2::default()
+                    .hash_password(password.as_bytes(), &salt)
+                    .map_err(|_| LoginError::InvalidCredentials)?
+                    .to_string();
+
+                // another login may have created the account in the meantime
+                let created = match self.accounts.lock().unwrap().entry(user_name.to_string()) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(password_hash.clone());
+                        true
+                    }
+                    Entry::Occupied(_) => false,
+                };
+                if !created {
+                    return self.check_password(user_name, password, address);
+                }
+
+                attempt(
+                    &self.account_creations,
+                    address,
+                    self.config.account_creation_window,
+                );
+                self.account_file.lock().unwrap().append(&Record {
+                    user_name: user_name.to_string(),
+                    password_hash,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    fn check_token(&self, user_name: &str, token: String) -> Result<String, LoginError> {
+        match self.sessions.lock().unwrap().get(&token) {
+            Some(session) if session.user_name == user_name && session.expires > Instant::now() => {
+                Ok(token)
+            }
+            _ => Err(LoginError::InvalidCredentials),
+        }
+    }
+
+    fn new_session(&self, user_name: &str) -> String {
+        let mut bytes = [0; SESSION_TOKEN_SIZE];
+        OsRng.fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(
+            token.clone(),
+            Session {
+                user_name: user_name.to_string(),
+                expires: now + self.config.session_duration,
+            },
+        );
+
+        token
+    }
+}
+
+fn is_rate_limited(
+    attempts: &Mutex<HashMap<IpAddr, Attempts>>,
+    address: IpAddr,
+    max_attempts: usize,
+    window: Duration,
+) -> bool {
+    match attempts.lock().unwrap().get(&address) {
+        Some(attempts) => attempts.since.elapsed() < window && attempts.amount >= max_attempts,
+        None => false,
+    }
+}
+
+fn attempt(attempts: &Mutex<HashMap<IpAddr, Attempts>>, address: IpAddr, window: Duration) {
+    let mut attempts = attempts.lock().unwrap();
+    // a new window starts after the old one, the addresses of ended windows are forgotten
+    attempts.retain(|_, attempts| attempts.since.elapsed() < window);
+    attempts
+        .entry(address)
+        .or_insert(Attempts {
+            since: Instant::now(),
+            amount: 0,
+        })
+        .amount += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::record_file::test_path;
+    use std::{fs, net::Ipv4Addr};
+
+    const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    const OTHER_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+    fn password(password: &str) -> Option<Auth> {
+        Some(Auth::Password(password.to_string()))
+    }
+
+    #[test]
+    fn attempts_are_limited_per_window() {
+        let attempts = Mutex::new(HashMap::new());
+        let window = Duration::from_millis(50);
+        for _ in 0..3 {
+            assert!(!is_rate_limited(&attempts, ADDRESS, 3, window));
+            attempt(&attempts, ADDRESS, window);
+        }
+        assert!(is_rate_limited(&attempts, ADDRESS, 3, window));
+        assert!(!is_rate_limited(&attempts, OTHER_ADDRESS, 3, window));
+
+        // the ended window is removed with the next attempt of any address
+        thread::sleep(window);
+        assert!(!is_rate_limited(&attempts, ADDRESS, 3, window));
+        attempt(&attempts, OTHER_ADDRESS, window);
+        let attempts = attempts.lock().unwrap();
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[&OTHER_ADDRESS].amount, 1);
+    }
+
+    #[test]
+    fn accounts_and_sessions() {
+        let path = test_path("accounts");
+        let config = Arc::new(ServerConfig {
+            credentials_path: Some(path.clone()),
+            max_failed_logins: 1,
+            max_account_creations: 1,
+            ..ServerConfig::default()
+        });
+        let authenticator = Authenticator::load(Arc::clone(&config)).unwrap();
+
+        // guests only without account
+        assert_eq!(authenticator.authenticate("alice", None, ADDRESS), Ok(None));
+        assert_eq!(
+            authenticator.authenticate("alice", password("short"), ADDRESS),
+            Err(LoginError::WeakPassword)
+        );
+        let token = authenticator
+            .authenticate("alice", password("correct horse"), ADDRESS)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            authenticator.authenticate("alice", None, ADDRESS),
+            Err(LoginError::AuthRequired)
+        );
+        assert_eq!(
+            authenticator.authenticate("bob", password("correct horse"), ADDRESS),
+            Err(LoginError::TooManyAttempts)
+        );
+
+        // a session token is only valid for its user
+        assert!(authenticator
+            .authenticate("alice", Some(Auth::Token(token.clone())), OTHER_ADDRESS)
+            .is_ok());
+        assert_eq!(
+            authenticator.authenticate("bob", Some(Auth::Token(token)), OTHER_ADDRESS),
+            Err(LoginError::InvalidCredentials)
+        );
+        assert_eq!(
+            authenticator.authenticate("alice", password("correct horse"), OTHER_ADDRESS),
+            Err(LoginError::TooManyAttempts)
+        );
+        drop(authenticator);
+
+        let authenticator = Authenticator::load(config).unwrap();
+        assert!(authenticator.has_account("alice"));
+        assert_eq!(
+            authenticator.authenticate("alice", password("wrong horse"), ADDRESS),
+            Err(LoginError::InvalidCredentials)
+        );
+        assert!(authenticator
+            .authenticate("alice", password("correct horse"), OTHER_ADDRESS)
+            .is_ok());
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,124 @@
+use rust_chat_protocol::codec::Codec;
+use rust_chat_protocol::compression::{Compression, DEFAULT_COMPRESSION_THRESHOLD};
+use rust_chat_protocol::framing::DEFAULT_MAX_MESSAGE_SIZE;
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
+
+/// What happens if the outbound queue of a connection exceeds its limits.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SlowConsumerPolicy {
+    /// Drop the oldest queued messages.
+    DropOldest,
+    /// Drop the oldest non-critical messages, disconnect if only critical ones are left.
+    DropNonCritical,
+    /// Disconnect with a `ErrorCode::SlowConsumer` error.
+    Disconnect,
+}
+
+/// PEM files of the server certificate chain and its private key.
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+pub struct ServerConfig {
+    pub address: SocketAddr,
+    pub connection_thread_amount: usize,
+    /// Maximum payload size of a message, fragmented messages are reassembled up to this size.
+    pub max_message_size: usize,
+    /// Payload codecs a client can choose, `Codec::Json` is always accepted.
+    pub codecs: Vec<Codec>,
+    /// Compressions a client can choose, only used with the binary framing.
+    pub compressions: Vec<Compression>,
+    /// Payloads of at least this size are compressed.
+    pub compression_threshold: usize,
+    /// Limits of the outbound queue of a connection, reading from the connection pauses at
+    /// half of them.
+    pub max_queued_messages: usize,
+    pub max_queued_bytes: usize,
+    pub slow_consumer_policy: SlowConsumerPolicy,
+    /// Names no user can log in with, compared case insensitive.
+    pub reserved_user_names: Vec<String>,
+    /// File of the accounts, `None` keeps them in memory only.
+    pub credentials_path: Option<PathBuf>,
+    /// Users without account can log in without credentials.
+    pub allow_guests: bool,
+    pub session_duration: Duration,
+    /// Logins with wrong credentials allowed per address in `failed_login_window`.
+    pub max_failed_logins: usize,
+    pub failed_login_window: Duration,
+    /// Accounts created per address in `account_creation_window`.
+    pub max_account_creations: usize,
+    pub account_creation_window: Duration,
+    /// Clients have to connect with TLS if set.
+    pub tls: Option<TlsConfig>,
+    /// Rooms a user can be member of at the same time.
+    pub max_rooms_per_user: usize,
+    /// User names with the global operator role, they have to log in with credentials.
+    pub operators: Vec<String>,
+    /// File of the bans and mutes, `None` keeps them in memory only.
+    pub bans_path: Option<PathBuf>,
+    /// File of the roles and topics of rooms, `None` keeps them in memory only.
+    pub rooms_path: Option<PathBuf>,
+    /// Typing indicators of a user are forwarded at most once per this duration.
+    pub typing_interval: Duration,
+    /// File of the chat history, `None` keeps it in memory only.
+    pub history_path: Option<PathBuf>,
+    /// Messages per chat that can be fetched, older ones are only kept in the file.
+    pub history_size: usize,
+    /// Most messages of a `HistoryMessage`.
+    pub max_history_fetch: usize,
+    /// Newest global messages sent after a login.
+    pub history_replay: usize,
+    /// File of the private messages for offline users, `None` keeps them in memory only.
+    pub offline_messages_path: Option<PathBuf>,
+    /// Private messages queued per offline user, more are rejected.
+    pub max_offline_messages: usize,
+    /// Queued private messages are dropped after this duration.
+    pub offline_message_lifetime: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from((Ipv4Addr::LOCALHOST, 4444)),
+            connection_thread_amount: 4,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            codecs: vec![Codec::Json, Codec::MessagePack, Codec::Cbor],
+            compressions: vec![Compression::Zstd, Compression::Deflate],
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            max_queued_messages: 200_000,
+            max_queued_bytes: 16 * 1024 * 1024,
+            slow_consumer_policy: SlowConsumerPolicy::DropNonCritical,
+            reserved_user_names: vec![
+                "admin".to_string(),
+                "server".to_string(),
+                "system".to_string(),
+                "me".to_string(),
+            ],
+            credentials_path: None,
+            allow_guests: true,
+            session_duration: Duration::from_secs(24 * 60 * 60),
+            max_failed_logins: 5,
+            failed_login_window: Duration::from_secs(60),
+            max_account_creations: 5,
+            account_creation_window: Duration::from_secs(60 * 60),
+            tls: None,
+            max_rooms_per_user: 32,
+            operators: Vec::new(),
+            bans_path: None,
+            rooms_path: None,
+            typing_interval: Duration::from_secs(3),
+            history_path: None,
+            history_size: 1000,
+            max_history_fetch: 100,
+            history_replay: 20,
+            offline_messages_path: None,
+            max_offline_messages: 100,
+            offline_message_lifetime: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
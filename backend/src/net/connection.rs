@@ -1,303 +1,531 @@
+use crate::net::config::{ServerConfig, SlowConsumerPolicy};
+use crate::net::error::ServerError;
 use crate::net::event::EventHandler;
 use crate::net::monitoring::MonitoringStats;
-use crate::net::msg::message::{Message, MessageTrait};
-use crate::net::msg::messages::{
-    GlobalChatMessage, LoginMessage, PingMessage, PrivateChatMessage, PublishGlobalChatMessage,
-    PublishPrivateChatMessage,
+use crate::net::msg::message::MessageTrait;
+use crate::net::msg::messages::complete_login;
+use crate::net::state::ServerState;
+use crate::net::transport::Transport;
+use crate::net::user_directory::ConnectionId;
+use mio::{net::TcpStream, Interest, Registry};
+use rust_chat_protocol::codec::Codec;
+use rust_chat_protocol::error::{DecodeError, EncodeError};
+use rust_chat_protocol::frame::Frame;
+use rust_chat_protocol::framing::{Decoder, Encoder};
+use rust_chat_protocol::message::Message;
+use rust_chat_protocol::messages::{
+    Capability, ErrorCode, ErrorMessage, LoginError, ModeratedMessage, ModerationAction,
+    PrivateChatMessage, RoomError, UserJoinedMessage, UserLeftMessage, WelcomeMessage,
 };
-use mio::{net::TcpStream, Interest, Registry, Token};
 use std::rc::Rc;
 use std::sync::Arc;
 use std::{
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
     io::{self, Read, Write},
-    str::from_utf8,
+    net::IpAddr,
+    time::Instant,
 };
 
-/*
-    Message Header:
-    bytes   name               description
-    8       magic number       Magic number to identify the application
-    3       message number     Message number to identify the message type
-    5       payload size       Payload size, maximum is MAX_PAYLOAD
-    total size: 16 bytes
-*/
-
-const MAGIC: &[u8; 8] = b"RustChat";
-const HEADER_SIZE: usize = 16;
-const MAX_PAYLOAD: usize = 1024;
-const MAX_PACKET_SIZE: usize = HEADER_SIZE + MAX_PAYLOAD;
-
-macro_rules! ProcessMessage {
-    ($message_struct:ident, $utf8_payload:expr,  $connection:expr) => {
-        match serde_json::from_str::<$message_struct>($utf8_payload) {
-            Ok(message) => {
-                $connection.monitoring_stats.message_received();
-                message.process($connection)
-            }
-            Err(_) => return false,
-        }
-    };
-}
-
-#[derive(PartialEq, Eq)]
-enum MessageDecodeState {
-    WaitingForHeader,
-    HeaderSuccessfulRead,
-    WaitingForPayload,
-    PayloadSuccessfulRead,
-}
+/// Queued messages are coalesced into one write of up to this many bytes.
+const MAX_COALESCED_SIZE: usize = 64 * 1024;
+/// Received private messages the user can send a read receipt for, older ones are forgotten.
+const MAX_UNREAD_MESSAGES: usize = 256;
 
 pub struct Connection {
-    tcp_stream: TcpStream,
+    transport: Transport,
     pub server_event_handler: EventHandler,
     monitoring_stats: Arc<MonitoringStats>,
+    pub config: Arc<ServerConfig>,
     registry: Rc<Registry>,
-    token: Token,
-    message_queue: VecDeque<Message>, // has no limit!!!
-    out_buffer: Box<[u8; MAX_PAYLOAD]>,
+    id: ConnectionId,
+    /// Address of the client, taken when the connection was accepted.
+    address: IpAddr,
+    pub state: Arc<ServerState>,
+    message_queue: VecDeque<Message>,
+    /// Payload bytes of all messages in `message_queue`.
+    queued_bytes: usize,
+    out_buffer: Vec<u8>,
     out_buffer_pos: usize,
-    out_buffer_size: usize,
-    in_buffer: Box<[u8; MAX_PACKET_SIZE]>,
-    in_buffer_pos: usize,
+    /// Amount of messages in `out_buffer`.
+    out_buffer_messages: usize,
+    encoder: Encoder,
+    decoder: Decoder,
+    codec: Codec,
     send_interest: bool,
-    // current message decode data/state begin
-    state: MessageDecodeState,
-    message_number: usize,
-    payload_size: usize,
-    // current message decode data/state end
+    /// A `HelloMessage` is only accepted as the first frame.
+    pub hello_allowed: bool,
+    /// Capabilities negotiated with the `HelloMessage`, none for clients without hello.
+    pub capabilities: Vec<Capability>,
+    closing: bool,
+    /// The socket failed, the connection is removed without flushing the queue.
+    removed: bool,
+    /// Reading stopped because of too many queued messages, see `resume_read`.
+    read_paused: bool,
+    /// A login with password is checked on the password thread, the following frames wait for
+    /// `finish_login`.
+    pub login_pending: bool,
     pub user_name: Option<String>,
+    /// Logged in with credentials. Roles are only granted to authenticated users, a guest could
+    /// use the name of an operator or room owner without account.
+    pub authenticated: bool,
+    pub global_operator: bool,
+    /// Rooms joined by the user.
+    pub rooms: HashSet<String>,
+    /// Last forwarded typing indicator, see `typing_allowed`.
+    last_typing: Option<Instant>,
+    /// Id and author of received private messages without read receipt, the oldest first.
+    unread_messages: VecDeque<(u64, String)>,
 }
 
 impl Connection {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        tcp_stream: TcpStream,
+        transport: Transport,
         server_event_handler: EventHandler,
         monitoring_stats: Arc<MonitoringStats>,
+        config: Arc<ServerConfig>,
         registry: Rc<Registry>,
-        token: Token,
+        id: ConnectionId,
+        address: IpAddr,
+        state: Arc<ServerState>,
     ) -> Self {
         Self {
-            tcp_stream,
+            transport,
             server_event_handler,
             monitoring_stats,
+            encoder: Encoder::new(config.max_message_size),
+            decoder: Decoder::new(config.max_message_size),
+            codec: Codec::Json,
+            config,
             registry,
-            token,
+            id,
+            address,
+            state,
             message_queue: VecDeque::new(),
-            out_buffer: Box::new([0; MAX_PAYLOAD]),
+            queued_bytes: 0,
+            out_buffer: Vec::new(),
             out_buffer_pos: 0,
-            out_buffer_size: 0,
-            in_buffer: Box::new([0; MAX_PACKET_SIZE]),
-            in_buffer_pos: 0,
+            out_buffer_messages: 0,
             send_interest: false,
-            state: MessageDecodeState::WaitingForHeader,
-            message_number: 0,
-            payload_size: 0,
+            hello_allowed: true,
+            capabilities: Vec::new(),
+            closing: false,
+            removed: false,
+            read_paused: false,
+            login_pending: false,
             user_name: None,
+            authenticated: false,
+            global_operator: false,
+            rooms: HashSet::new(),
+            last_typing: None,
+            unread_messages: VecDeque::new(),
         }
     }
 
     /// Returns `true` if the connection should be removed and closed.
     pub fn read(&mut self) -> bool {
+        if self.login_pending {
+            self.read_paused = true;
+            return false;
+        }
+        self.read_paused = false;
+
         // We can (maybe) read from the connection.
         loop {
-            match self
-                .tcp_stream
-                .read(&mut self.in_buffer[self.in_buffer_pos..])
-            {
+            self.monitoring_stats.read_call();
+            match self.transport.read(self.decoder.unfilled()) {
                 Ok(0) => {
                     // Reading 0 bytes means the other side has closed the
                     // connection or is done writing, then so are we.
                     return true;
                 }
                 Ok(n) => {
-                    self.in_buffer_pos += n;
+                    self.decoder.filled(n);
                     self.monitoring_stats.bytes_read(n);
 
-                    if !self.decode() {
-                        println!("Invalid data, closing connection...",);
+                    if self.process_frames() {
                         return true;
                     }
+
+                    // backpressure, the client has to read the replies first
+                    if self.queue_above(2) || self.login_pending {
+                        self.read_paused = true;
+                        break;
+                    }
                 }
                 // Would block "errors" are the OS's way of saying that the
                 // connection is not actually ready to perform this I/O operation.
-                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                // The TLS handshake may still have to be answered.
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return self.send(),
                 Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
                 // Other errors we'll consider fatal.
                 Err(_) => return true,
             }
         }
 
-        return false;
+        false
     }
 
-    fn decode(&mut self) -> bool {
-        loop {
-            if !self.process_states() {
-                return false;
-            }
+    /// Processes the decoded frames and writes the replies together. Returns `true` if the
+    /// connection should be closed.
+    fn process_frames(&mut self) -> bool {
+        if let Err(err) = self.decode() {
+            println!("Invalid data ({}), closing connection...", err);
+            self.send_error(err.code(), err.to_string(), err.message_number());
+            self.send();
+            return true;
+        }
+
+        self.send() || self.should_remove()
+    }
 
-            if self.state != MessageDecodeState::PayloadSuccessfulRead {
+    fn decode(&mut self) -> Result<(), DecodeError> {
+        // the frames after a login with password wait for its result
+        while !self.login_pending {
+            let Some(frame) = self.decoder.decode()? else {
                 break;
-            }
+            };
+            self.monitoring_stats.message_received();
+            self.monitoring_stats
+                .compression(self.decoder.take_compression_stats());
+            frame.process(self);
+            self.hello_allowed = false;
         }
 
-        return true;
+        Ok(())
     }
 
-    fn process_states(&mut self) -> bool {
-        loop {
-            match self.state {
-                MessageDecodeState::WaitingForHeader
-                | MessageDecodeState::PayloadSuccessfulRead => {
-                    if !self.decode_header() {
-                        return false;
-                    }
-                }
-                MessageDecodeState::HeaderSuccessfulRead
-                | MessageDecodeState::WaitingForPayload => {
-                    if !self.decode_payload() {
-                        return false;
-                    }
-                }
-            }
+    /// Switches to the negotiated encoding for all frames after the current one in both
+    /// directions and enables the negotiated capabilities.
+    pub fn apply_welcome(&mut self, welcome: &WelcomeMessage) {
+        // already queued messages are still sent with the old encoding
+        while let Some(message) = self.pop_message() {
+            self.encode_or_report(&message);
+        }
 
-            if self.state == MessageDecodeState::WaitingForHeader
-                || self.state == MessageDecodeState::WaitingForPayload
-                || self.state == MessageDecodeState::PayloadSuccessfulRead
-            {
-                return true;
-            }
+        self.encoder.set_framing(welcome.framing);
+        self.encoder
+            .set_compression(welcome.compression, welcome.compression_threshold);
+        self.decoder.set_framing(welcome.framing);
+        self.decoder.set_codec(welcome.codec);
+        self.codec = welcome.codec;
+        self.capabilities = welcome.capabilities.clone();
+    }
+
+    /// Completes a login whose password was checked on the password thread, the frames received
+    /// in the meantime are processed now. Reading resumes with `resume_read`.
+    pub fn finish_login(&mut self, user_name: String, result: Result<Option<String>, LoginError>) {
+        self.login_pending = false;
+        complete_login(self, user_name, result);
+
+        if self.process_frames() {
+            self.mark_for_removal();
         }
     }
 
-    fn decode_header(&mut self) -> bool {
-        // check if the read data length is HEADER_SIZE or more
-        if self.in_buffer_pos < HEADER_SIZE {
-            self.state = MessageDecodeState::WaitingForHeader;
-            return true; // just wait for more data
+    /// Closes the connection after the current read or broadcast, already queued messages are
+    /// flushed first.
+    pub fn close(&mut self) {
+        self.closing = true;
+    }
+
+    /// Marks the connection for removal, the `ConnectionThread` removes it at the end of the
+    /// current read or broadcast.
+    pub fn mark_for_removal(&mut self) {
+        self.removed = true;
+    }
+
+    /// Logs and counts an error of this connection, the thread and other connections go on.
+    pub fn report_error(&self, err: ServerError) {
+        eprintln!("Connection error: {}", err);
+        self.monitoring_stats.error();
+    }
+
+    pub fn id(&self) -> ConnectionId {
+        self.id
+    }
+
+    pub fn peer_ip(&self) -> IpAddr {
+        self.address
+    }
+
+    /// Sets the user name, makes the connection reachable under it and announces the user,
+    /// returns `false` if the name is taken.
+    pub fn login(&mut self, user_name: String) -> bool {
+        let address = self.peer_ip();
+        if !self
+            .state
+            .user_directory
+            .insert(user_name.clone(), self.id, address)
+        {
+            return false;
         }
 
-        // load and check magic
-        for i in 0..8 {
-            if self.in_buffer[i] != MAGIC[i] {
-                return false; // invalid data read
-            }
+        if let Err(err) = self
+            .server_event_handler
+            .broadcast_user_joined(UserJoinedMessage {
+                user_name: user_name.clone(),
+            })
+        {
+            self.report_error(err);
         }
 
-        // load message number
-        match self.get_usize(8, 11) {
-            Some(message_number) => self.message_number = message_number,
-            None => return false,
+        self.user_name = Some(user_name);
+        true
+    }
+
+    /// Leaves all rooms of the user and announces that the user left.
+    pub fn logout(&mut self) {
+        for room in self.rooms.drain() {
+            // `rooms` mirrors the directory, the connection is a member
+            let _ = self.state.room_directory.leave(&room, self.id);
         }
 
-        // load and check payload size
-        match self.get_usize(11, 16) {
-            Some(payload_size) => self.payload_size = payload_size,
-            None => return false,
+        if let Some(user_name) = self.user_name.take() {
+            self.state.user_directory.remove(&user_name, self.id);
+
+            if let Err(err) = self
+                .server_event_handler
+                .broadcast_user_left(UserLeftMessage { user_name })
+            {
+                self.report_error(err);
+            }
         }
-        if self.payload_size <= 0 || self.payload_size > MAX_PAYLOAD {
-            return false;
+    }
+
+    /// Joins as `user_name`, the user logged in with this connection.
+    pub fn join_room(&mut self, room: String, user_name: &str) -> Result<(), RoomError> {
+        if self.rooms.len() >= self.config.max_rooms_per_user {
+            return Err(RoomError::TooManyRooms);
+        }
+
+        if self
+            .state
+            .moderation
+            .is_banned(Some(&room), Some(user_name), self.peer_ip())
+        {
+            return Err(RoomError::Banned);
         }
 
-        self.state = MessageDecodeState::HeaderSuccessfulRead;
-        return true;
+        let owner = self.authenticated.then_some(user_name);
+        self.state.room_directory.join(&room, owner, self.id)?;
+        self.rooms.insert(room);
+        Ok(())
     }
 
-    fn decode_payload(&mut self) -> bool {
-        // check if the read data length is HEADER_SIZE plus payload size or more
-        if self.in_buffer_pos < self.payload_size + HEADER_SIZE {
-            self.state = MessageDecodeState::WaitingForPayload;
-            return true; // just wait for more data
+    pub fn leave_room(&mut self, room: &str) -> Result<(), RoomError> {
+        if !self.rooms.remove(room) {
+            return Err(RoomError::NotMember);
         }
 
-        // parse json and check message number
-        match from_utf8(&self.in_buffer[HEADER_SIZE..self.payload_size + HEADER_SIZE]) {
-            Ok(utf8_payload) => match self.message_number {
-                0 => ProcessMessage!(PingMessage, utf8_payload, self),
-                1 => ProcessMessage!(LoginMessage, utf8_payload, self),
-                2 => ProcessMessage!(PublishGlobalChatMessage, utf8_payload, self),
-                3 => ProcessMessage!(GlobalChatMessage, utf8_payload, self),
-                4 => ProcessMessage!(PublishPrivateChatMessage, utf8_payload, self),
-                5 => ProcessMessage!(PrivateChatMessage, utf8_payload, self),
-                _ => return false,
-            },
-            Err(_) => return false,
+        self.state.room_directory.leave(room, self.id)
+    }
+
+    /// Notifies the user, a kick or ban removes the user from the room, or closes the
+    /// connection without room.
+    pub fn moderated(&mut self, notice: ModeratedMessage) {
+        let room = notice.room.clone();
+        let kicked = matches!(
+            notice.action,
+            ModerationAction::Kick | ModerationAction::Ban
+        );
+        self.send_frame(notice);
+
+        if kicked {
+            match room {
+                // the user may have left in the meantime
+                Some(room) => {
+                    let _ = self.leave_room(&room);
+                }
+                None => self.close(),
+            }
         }
+    }
 
-        // remove packet from buffer
-        let mut temp_buffer_pos: usize = 0;
-        for i in self.payload_size + HEADER_SIZE..self.in_buffer_pos {
-            self.in_buffer[temp_buffer_pos] = self.in_buffer[i];
-            temp_buffer_pos += 1;
+    /// Queues the private message, clients with `Capability::Typing` can send a read receipt
+    /// for it.
+    pub fn received_private_message(&mut self, message: PrivateChatMessage) {
+        if self.has_capability(Capability::Typing) {
+            if self.unread_messages.len() >= MAX_UNREAD_MESSAGES {
+                self.unread_messages.pop_front();
+            }
+            self.unread_messages
+                .push_back((message.message_id, message.from_user_name.clone()));
         }
 
-        self.state = MessageDecodeState::PayloadSuccessfulRead;
-        self.in_buffer_pos = temp_buffer_pos;
-        return true;
+        self.send_frame(message);
     }
 
-    fn get_usize(&self, buffer_start_pos: usize, buffer_end_pos: usize) -> Option<usize> {
-        let mut message_number_string = String::new();
-        for i in buffer_start_pos..buffer_end_pos {
-            message_number_string.push(self.in_buffer[i] as char);
-        }
+    /// Author of the private message, `None` if it was already read or never received.
+    pub fn read_private_message(&mut self, message_id: u64) -> Option<String> {
+        let index = self
+            .unread_messages
+            .iter()
+            .position(|(id, _)| *id == message_id)?;
+        self.unread_messages
+            .remove(index)
+            .map(|(_, from_user_name)| from_user_name)
+    }
 
-        match message_number_string.trim_end().parse::<usize>() {
-            Ok(read_usize) => Some(read_usize),
-            Err(_) => None,
+    /// Returns `true` if a typing indicator can be forwarded now, one per
+    /// `ServerConfig::typing_interval`.
+    pub fn typing_allowed(&mut self) -> bool {
+        let now = Instant::now();
+        if self
+            .last_typing
+            .is_some_and(|last_typing| now - last_typing < self.config.typing_interval)
+        {
+            return false;
         }
+
+        self.last_typing = Some(now);
+        true
+    }
+
+    pub fn has_capability(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn send_error(&mut self, code: ErrorCode, reason: String, in_reply_to: Option<u32>) {
+        self.send_frame(ErrorMessage {
+            code,
+            reason,
+            in_reply_to,
+        });
+    }
+
+    /// Encodes `message` with the codec of this connection and queues it.
+    pub fn send_frame<T: Into<Frame>>(&mut self, message: T) {
+        let message = Message::new(message, self.codec);
+        self.send_message(message);
     }
 
+    /// Queues `message`, it is written with the next `send`.
     pub fn send_message(&mut self, message: Message) {
+        // a disconnected slow consumer or a failed socket gets nothing more
+        if self.closing || self.removed {
+            return;
+        }
+
+        self.queued_bytes += message.payload.len();
         self.message_queue.push_back(message);
-        // return value of send is ignored!!!!
-        self.send();
+
+        if self.queue_above(1) {
+            self.evict();
+        }
+    }
+
+    pub fn has_queued_messages(&self) -> bool {
+        !self.message_queue.is_empty()
+    }
+
+    /// Returns `true` if the connection was closed or marked for removal.
+    pub fn should_remove(&self) -> bool {
+        self.closing || self.removed
+    }
+
+    /// Returns `true` if reading was paused and enough queued messages are written now.
+    pub fn resume_read(&self) -> bool {
+        self.read_paused && !self.login_pending && !self.queue_above(2)
+    }
+
+    /// Returns `true` if the queue exceeds `1 / divisor` of its limits.
+    fn queue_above(&self, divisor: usize) -> bool {
+        self.message_queue.len() > self.config.max_queued_messages / divisor
+            || self.queued_bytes > self.config.max_queued_bytes / divisor
+    }
+
+    fn pop_message(&mut self) -> Option<Message> {
+        let message = self.message_queue.pop_front()?;
+        self.queued_bytes -= message.payload.len();
+        Some(message)
+    }
+
+    /// Applies the `SlowConsumerPolicy` to the full queue.
+    fn evict(&mut self) {
+        match self.config.slow_consumer_policy {
+            SlowConsumerPolicy::DropOldest => {
+                while self.queue_above(1) {
+                    self.pop_message();
+                    self.monitoring_stats.evicted_messages(1);
+                }
+            }
+            SlowConsumerPolicy::DropNonCritical => {
+                let mut index = 0;
+                while self.queue_above(1) && index < self.message_queue.len() {
+                    if self.message_queue[index].critical {
+                        index += 1;
+                    } else if let Some(message) = self.message_queue.remove(index) {
+                        self.queued_bytes -= message.payload.len();
+                        self.monitoring_stats.evicted_messages(1);
+                    }
+                }
+
+                if self.queue_above(1) {
+                    self.disconnect_slow_consumer();
+                }
+            }
+            SlowConsumerPolicy::Disconnect => self.disconnect_slow_consumer(),
+        }
+    }
+
+    fn disconnect_slow_consumer(&mut self) {
+        println!("Slow consumer, closing connection...");
+        self.monitoring_stats
+            .evicted_messages(self.message_queue.len());
+        self.monitoring_stats.slow_consumer();
+
+        self.message_queue.clear();
+        self.queued_bytes = 0;
+        self.send_error(
+            ErrorCode::SlowConsumer,
+            "Too many queued messages".to_string(),
+            None,
+        );
+        self.close();
     }
 
     /// Returns `true` if the connection should be removed and closed.
     pub fn send(&mut self) -> bool {
         loop {
-            if self.out_buffer_size == 0 {
-                match self.message_queue.pop_front() {
-                    Some(message) => {
-                        if !self.encode(message.message, message.number) {
-                            eprintln!("Error while encode message!");
-                            return true;
-                        }
-                    }
-                    None => {
-                        if self.send_interest {
-                            // disable send interest
-                            self.send_interest = false;
-                            self.registry
-                                .reregister(&mut self.tcp_stream, self.token, Interest::READABLE)
-                                .expect("Error while regegister!");
-                        }
-
-                        return false;
+            if self.out_buffer.is_empty() {
+                self.fill_out_buffer();
+
+                if self.out_buffer.is_empty() {
+                    if self.transport.flush_tls().is_err() {
+                        self.mark_for_removal();
+                        return true;
                     }
+
+                    // encrypted data may still wait for the socket
+                    return self.set_send_interest(self.transport.wants_write());
                 }
             }
 
             let mut set_send_interest = false;
 
             // We can (maybe) write to the connection.
+            self.monitoring_stats.write_call();
             match self
-                .tcp_stream
-                .write(&self.out_buffer[self.out_buffer_pos..self.out_buffer_size])
+                .transport
+                .write(&self.out_buffer[self.out_buffer_pos..])
             {
                 Ok(n) => {
                     self.monitoring_stats.bytes_send(n);
 
-                    if n != self.out_buffer_size - self.out_buffer_pos {
-                        self.out_buffer_pos = n;
+                    if n != self.out_buffer.len() - self.out_buffer_pos {
+                        self.out_buffer_pos += n;
                         set_send_interest = true;
                     } else {
-                        self.monitoring_stats.messege_send();
+                        self.monitoring_stats.messege_send(self.out_buffer_messages);
 
-                        self.out_buffer_size = 0;
+                        self.out_buffer.clear();
                         self.out_buffer_pos = 0;
+                        self.out_buffer_messages = 0;
                         // we dont return because we can try to write more messages
                     }
                 }
@@ -307,71 +535,78 @@ impl Connection {
                 // Got interrupted (how rude!), we'll try again.
                 Err(ref err) if err.kind() == io::ErrorKind::Interrupted => return self.send(),
                 // Other errors we'll consider fatal.
-                Err(_) => return true,
+                Err(_) => {
+                    self.mark_for_removal();
+                    return true;
+                }
             }
 
-            // enable send interest if not already set
             if set_send_interest {
-                if !self.send_interest {
-                    // enable send interest
-                    self.send_interest = true;
-                    self.registry
-                        .reregister(
-                            &mut self.tcp_stream,
-                            self.token,
-                            Interest::READABLE.add(Interest::WRITABLE),
-                        )
-                        .expect("Error while regegister!");
-                }
-
-                return false;
+                return self.set_send_interest(true);
             }
         }
     }
 
-    pub fn encode(&mut self, message: String, message_number: u32) -> bool {
-        let message = message.into_bytes();
-
-        if message.len() > MAX_PAYLOAD {
+    /// Enables or disables the writable interest, returns `true` if the connection should be
+    /// removed and closed.
+    fn set_send_interest(&mut self, send_interest: bool) -> bool {
+        if self.send_interest == send_interest {
             return false;
         }
 
-        // write magic
-        for i in 0..8 {
-            self.out_buffer[i] = MAGIC[i];
+        self.send_interest = send_interest;
+        let interest = if send_interest {
+            Interest::READABLE.add(Interest::WRITABLE)
+        } else {
+            Interest::READABLE
+        };
+        let result = self
+            .registry
+            .reregister(self.transport.socket_mut(), self.id.token, interest)
+            .map_err(ServerError::Registry);
+
+        if let Err(err) = result {
+            self.report_error(err);
+            self.mark_for_removal();
+            return true;
         }
 
-        // write message number
-        let message_number = message_number.to_string().into_bytes();
-        for i in 0..3 {
-            if i < message_number.len() {
-                self.out_buffer[8 + i] = message_number[i];
-            } else {
-                self.out_buffer[8 + i] = b' ';
-            }
-        }
+        false
+    }
 
-        // write payload size
-        let payload_size = message.len().to_string().into_bytes();
-        for i in 0..5 {
-            if i < payload_size.len() {
-                self.out_buffer[11 + i] = payload_size[i];
-            } else {
-                self.out_buffer[11 + i] = b' ';
+    /// Encodes queued messages into the empty `out_buffer`, up to `MAX_COALESCED_SIZE` bytes.
+    fn fill_out_buffer(&mut self) {
+        while self.out_buffer.len() < MAX_COALESCED_SIZE {
+            match self.pop_message() {
+                Some(message) => self.encode_or_report(&message),
+                None => break,
             }
         }
+    }
 
-        // write payload
-        for i in 0..message.len() {
-            self.out_buffer[16 + i] = message[i];
+    /// Encodes `message`, a message that can not be sent is replaced by an `ErrorMessage`.
+    fn encode_or_report(&mut self, message: &Message) {
+        if let Err(err) = self.encode(message) {
+            eprintln!("Error while encode message: {}", err);
+            self.send_error(
+                ErrorCode::PayloadTooLarge,
+                format!("Message {} could not be sent: {}", message.number, err),
+                None,
+            );
         }
+    }
+
+    /// Appends `message` to `out_buffer`.
+    pub fn encode(&mut self, message: &Message) -> Result<(), EncodeError> {
+        self.encoder.encode(message, &mut self.out_buffer)?;
+        self.out_buffer_messages += 1;
+        self.monitoring_stats
+            .compression(self.encoder.take_compression_stats());
 
-        self.out_buffer_size = HEADER_SIZE + message.len();
-        self.out_buffer_pos = 0;
-        return true;
+        Ok(())
     }
 
-    pub fn tcp_stream(self) -> TcpStream {
-        self.tcp_stream
+    pub fn into_socket(self) -> TcpStream {
+        self.transport.into_socket()
     }
 }
@@ -1,13 +1,24 @@
 use crate::net::{
+    config::ServerConfig,
     connection::Connection,
-    event::EventHandler,
+    error::ServerError,
+    event::{EventHandler, PrivateChatResultEvent},
     monitoring::MonitoringStats,
-    msg::{message::Message, messages::PrivateChatMessage},
+    offline_queue::Deferral,
     server_stop::ServerThreadStop,
+    state::ServerState,
+    transport::new_transport,
+    user_directory::ConnectionId,
+};
+use mio::{net::TcpStream, Events, Interest, Poll, Registry, Token, Waker};
+use rust_chat_protocol::{
+    frame::Frame,
+    message::Message,
+    messages::{Capability, DeliveryStatus, PrivateChatResultMessage},
 };
-use mio::{net::TcpStream, Events, Interest, Poll, Token, Waker};
 use std::{
     collections::HashMap,
+    net::IpAddr,
     rc::Rc,
     sync::{
         mpsc::{channel, Sender},
@@ -24,30 +35,44 @@ pub struct ConnectionThread {
     server_thread_stop: ServerThreadStop,
     waker: Arc<Waker>,
     pub event_handler: EventHandler,
-    new_connection_sender: Sender<TcpStream>,
+    new_connection_sender: Sender<(TcpStream, IpAddr)>,
     connection_thread_handle: JoinHandle<()>,
 }
 
 impl ConnectionThread {
     pub fn new(
         connection_thread_name: String,
+        thread_index: usize,
         server_event_handler: EventHandler,
         monitoring_stats: Arc<MonitoringStats>,
-    ) -> Self {
+        config: Arc<ServerConfig>,
+        state: Arc<ServerState>,
+    ) -> Result<Self, ServerError> {
         // Create a poll instance.
-        let mut poll = Poll::new().expect("Error while creating poll!");
+        let mut poll = Poll::new().map_err(ServerError::Poll)?;
 
         // Create waker connection instance.
-        let waker = Arc::new(
-            Waker::new(poll.registry(), WAKER_TOKEN).expect("Error while creating waker!"),
-        );
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER_TOKEN).map_err(ServerError::Waker)?);
+
+        let registry = poll.registry().try_clone().map_err(ServerError::Registry)?;
 
         // create channels
-        let (new_connection_sender, new_connection_receiver) = channel::<TcpStream>();
+        let (new_connection_sender, new_connection_receiver) = channel::<(TcpStream, IpAddr)>();
 
         // create event handler
-        let (event_handler, global_chat_message_receiver, private_chat_message_event_receiver) =
-            EventHandler::new(Arc::clone(&waker));
+        let (
+            event_handler,
+            global_chat_message_receiver,
+            broadcast_event_receiver,
+            private_chat_message_event_receiver,
+            private_chat_result_event_receiver,
+            room_message_event_receiver,
+            user_message_event_receiver,
+            moderation_event_receiver,
+            login_event_receiver,
+            user_joined_receiver,
+            user_left_receiver,
+        ) = EventHandler::new(Arc::clone(&waker));
 
         let server_thread_stop = ServerThreadStop::new();
 
@@ -70,11 +95,7 @@ impl ConnectionThread {
 
                     let duration = Some(Duration::from_millis(500));
 
-                    let registry = Rc::new(
-                        poll.registry()
-                            .try_clone()
-                            .expect("Error while clone registry!"),
-                    );
+                    let registry = Rc::new(registry);
 
                     println!("[{}] Started.", connection_thread_name);
 
@@ -94,29 +115,50 @@ impl ConnectionThread {
                             match event.token() {
                                 WAKER_TOKEN => {
                                     // check for new connections
-                                    for mut new_connection in new_connection_receiver.try_iter() {
-                                        poll.registry()
+                                    for (mut new_connection, address) in
+                                        new_connection_receiver.try_iter()
+                                    {
+                                        let transport = poll
+                                            .registry()
                                             .register(
                                                 &mut new_connection,
                                                 next_token,
                                                 Interest::READABLE,
                                             )
-                                            .expect(
-                                                format!(
-                                                    "[{}] Error while registering new connection!",
-                                                    connection_thread_name
+                                            .map_err(ServerError::Registry)
+                                            .and_then(|_| {
+                                                new_transport(
+                                                    new_connection,
+                                                    state.tls_config.as_ref(),
                                                 )
-                                                .as_str(),
-                                            );
+                                            });
+                                        let transport = match transport {
+                                            Ok(transport) => transport,
+                                            Err(err) => {
+                                                eprintln!(
+                                                    "[{}] Error while adding new connection: {}",
+                                                    connection_thread_name, err
+                                                );
+                                                monitoring_stats.error();
+                                                monitoring_stats.lost_connection();
+                                                continue;
+                                            }
+                                        };
 
                                         connections.insert(
                                             next_token,
                                             Connection::new(
-                                                new_connection,
+                                                transport,
                                                 server_event_handler.clone(),
                                                 Arc::clone(&monitoring_stats),
+                                                Arc::clone(&config),
                                                 Rc::clone(&registry),
-                                                next_token,
+                                                ConnectionId {
+                                                    thread: thread_index,
+                                                    token: next_token,
+                                                },
+                                                address,
+                                                Arc::clone(&state),
                                             ),
                                         );
 
@@ -127,70 +169,266 @@ impl ConnectionThread {
                                     for global_chat_message in
                                         global_chat_message_receiver.try_iter()
                                     {
-                                        let message = Message::new(global_chat_message);
-                                        for connection in connections.iter_mut() {
-                                            connection.1.send_message(message.clone());
-                                        }
+                                        broadcast(&mut connections, global_chat_message, None);
+                                    }
+                                    for broadcast_event in broadcast_event_receiver.try_iter() {
+                                        broadcast(
+                                            &mut connections,
+                                            broadcast_event.frame,
+                                            broadcast_event.capability,
+                                        );
                                     }
 
-                                    // check for private messages
-                                    for private_chat_message_event in
+                                    // check for presence, only for clients asking for it
+                                    for user_joined_message in user_joined_receiver.try_iter() {
+                                        broadcast(
+                                            &mut connections,
+                                            user_joined_message,
+                                            Some(Capability::Presence),
+                                        );
+                                    }
+                                    for user_left_message in user_left_receiver.try_iter() {
+                                        broadcast(
+                                            &mut connections,
+                                            user_left_message,
+                                            Some(Capability::Presence),
+                                        );
+                                    }
+
+                                    // check for private messages, they are routed to the
+                                    // connection of the recipient
+                                    for mut private_chat_message_event in
                                         private_chat_message_event_receiver.try_iter()
                                     {
-                                        let message = Message::new(PrivateChatMessage {
-                                            from_user_name: private_chat_message_event
-                                                .from_user_name,
-                                            message: private_chat_message_event.message,
-                                        });
-                                        for connection in connections.iter_mut() {
-                                            match &connection.1.user_name {
-                                                Some(user_name) => {
-                                                    if user_name
-                                                        == &private_chat_message_event.to_user_name
+                                        let recipient = connections
+                                            .get_mut(&private_chat_message_event.recipient.token)
+                                            .filter(|connection| {
+                                                connection.user_name.as_ref()
+                                                    == Some(
+                                                        &private_chat_message_event.to_user_name,
+                                                    )
+                                            });
+
+                                        // the user may have logged out in the meantime, users
+                                        // with account get the message with their next login
+                                        let message_id =
+                                            private_chat_message_event.message.message_id;
+                                        let to_user_name = &private_chat_message_event.to_user_name;
+                                        let status = match recipient {
+                                            Some(connection) => {
+                                                connection.received_private_message(
+                                                    private_chat_message_event.message,
+                                                );
+                                                DeliveryStatus::Delivered
+                                            }
+                                            None if !state
+                                                .authenticator
+                                                .has_account(to_user_name) =>
+                                            {
+                                                DeliveryStatus::UserNotFound
+                                            }
+                                            None => match state.offline_queue.defer(
+                                                &state.user_directory,
+                                                to_user_name,
+                                                private_chat_message_event.message.clone(),
+                                            ) {
+                                                Deferral::Deferred => DeliveryStatus::Deferred,
+                                                Deferral::QueueFull => DeliveryStatus::QueueFull,
+                                                // logged in again, the thread of the new
+                                                // connection replies
+                                                Deferral::Online(recipient) => {
+                                                    private_chat_message_event.recipient =
+                                                        recipient;
+                                                    if let Err(err) = server_event_handler
+                                                        .private_chat_message_event(
+                                                            private_chat_message_event,
+                                                        )
                                                     {
-                                                        connection.1.send_message(message.clone());
+                                                        eprintln!(
+                                                            "[{}] Error while routing message: {}",
+                                                            connection_thread_name, err
+                                                        );
+                                                        monitoring_stats.error();
                                                     }
+                                                    continue;
                                                 }
-                                                None => {}
+                                            },
+                                        };
+                                        let message_id = matches!(
+                                            status,
+                                            DeliveryStatus::Delivered | DeliveryStatus::Deferred
+                                        )
+                                        .then_some(message_id);
+
+                                        let result = PrivateChatResultEvent {
+                                            sender: private_chat_message_event.sender,
+                                            result: PrivateChatResultMessage {
+                                                to_user_name: private_chat_message_event
+                                                    .to_user_name,
+                                                status,
+                                                message_id,
+                                            },
+                                        };
+                                        if let Err(err) =
+                                            server_event_handler.private_chat_result_event(result)
+                                        {
+                                            eprintln!(
+                                                "[{}] Error while sending result: {}",
+                                                connection_thread_name, err
+                                            );
+                                            monitoring_stats.error();
+                                        }
+                                    }
+
+                                    // check for results of send private messages
+                                    for private_chat_result_event in
+                                        private_chat_result_event_receiver.try_iter()
+                                    {
+                                        if let Some(connection) = connections
+                                            .get_mut(&private_chat_result_event.sender.token)
+                                        {
+                                            connection.send_frame(private_chat_result_event.result);
+                                        }
+                                    }
+
+                                    // check for room messages, only members of the room
+                                    // receive them
+                                    for room_message_event in room_message_event_receiver.try_iter()
+                                    {
+                                        let room = room_message_event.room;
+                                        let frame = room_message_event.frame;
+                                        let capability = room_message_event.capability;
+                                        // encode only once per codec
+                                        let mut messages = HashMap::new();
+                                        for member in room_message_event.members {
+                                            // the member may have left in the meantime
+                                            if let Some(connection) = connections
+                                                .get_mut(&member.token)
+                                                .filter(|connection| {
+                                                    connection.rooms.contains(&room)
+                                                        && capability.is_none_or(|capability| {
+                                                            connection.has_capability(capability)
+                                                        })
+                                                })
+                                            {
+                                                let codec = connection.codec();
+                                                let message =
+                                                    messages.entry(codec).or_insert_with(|| {
+                                                        Message::new(frame.clone(), codec)
+                                                    });
+                                                connection.send_message(message.clone());
                                             }
                                         }
                                     }
+
+                                    // check for frames for single users of this thread
+                                    for user_message_event in user_message_event_receiver.try_iter()
+                                    {
+                                        // the user may have logged out in the meantime
+                                        if let Some(connection) = connections
+                                            .get_mut(&user_message_event.recipient.token)
+                                            .filter(|connection| {
+                                                connection.user_name.as_ref()
+                                                    == Some(&user_message_event.user_name)
+                                                    && user_message_event.capability.is_none_or(
+                                                        |capability| {
+                                                            connection.has_capability(capability)
+                                                        },
+                                                    )
+                                            })
+                                        {
+                                            connection.send_frame(user_message_event.frame);
+                                        }
+                                    }
+
+                                    // check for moderation of users of this thread
+                                    for moderation_event in moderation_event_receiver.try_iter() {
+                                        // the user may have logged out in the meantime
+                                        if let Some(connection) = connections
+                                            .get_mut(&moderation_event.target.token)
+                                            .filter(|connection| {
+                                                connection.user_name.as_ref()
+                                                    == Some(&moderation_event.user_name)
+                                            })
+                                        {
+                                            connection.moderated(moderation_event.notice);
+                                        }
+                                    }
+
+                                    // check for checked passwords, the connection may be closed
+                                    // in the meantime
+                                    for login_event in login_event_receiver.try_iter() {
+                                        if let Some(connection) =
+                                            connections.get_mut(&login_event.connection.token)
+                                        {
+                                            connection.finish_login(
+                                                login_event.user_name,
+                                                login_event.result,
+                                            );
+                                        }
+                                    }
+
+                                    // write the queued messages with one write per connection,
+                                    // a failed write marks the connection for removal
+                                    let mut closed_connections = Vec::new();
+                                    for (token, connection) in connections.iter_mut() {
+                                        let mut remove_connection = false;
+                                        if connection.has_queued_messages() {
+                                            remove_connection = connection.send();
+                                        }
+
+                                        // like below, a paused connection gets no new readable
+                                        // event once its queue is written
+                                        if !remove_connection
+                                            && !connection.should_remove()
+                                            && connection.resume_read()
+                                        {
+                                            remove_connection = connection.read();
+                                        }
+
+                                        // dead sockets and slow consumers are removed now
+                                        if remove_connection || connection.should_remove() {
+                                            closed_connections.push(*token);
+                                        }
+                                    }
+
+                                    for token in closed_connections {
+                                        close_connection(
+                                            &mut connections,
+                                            token,
+                                            poll.registry(),
+                                            &monitoring_stats,
+                                            &connection_thread_name,
+                                        );
+                                    }
                                 }
                                 token => {
                                     // Maybe received an event for a TCP connection.
                                     let mut remove_connection = false;
 
-                                    match connections.get_mut(&token) {
-                                        Some(connection) => {
-                                            if event.is_writable() {
-                                                remove_connection = connection.send();
-                                            }
+                                    // Sporadic events happen, we can safely ignore them.
+                                    if let Some(connection) = connections.get_mut(&token) {
+                                        if event.is_writable() {
+                                            remove_connection = connection.send();
+                                        }
 
-                                            if !remove_connection {
-                                                if event.is_readable() {
-                                                    remove_connection = connection.read();
-                                                }
-                                            }
+                                        // reading continues after a pause if the queue is
+                                        // written, there will be no new readable event
+                                        if !remove_connection
+                                            && (event.is_readable() || connection.resume_read())
+                                        {
+                                            remove_connection = connection.read();
                                         }
-                                        // Sporadic events happen, we can safely ignore them.
-                                        None => {}
                                     }
 
                                     if remove_connection {
-                                        if let Some(connection) = connections.remove(&token) {
-                                            println!("Connection closed.");
-                                            monitoring_stats.lost_connection();
-
-                                            poll.registry()
-                                                .deregister(&mut connection.tcp_stream())
-                                                .expect(
-                                                    format!(
-                                                        "[{}] Error while deregister connection!",
-                                                        connection_thread_name
-                                                    )
-                                                    .as_str(),
-                                                );
-                                        }
+                                        close_connection(
+                                            &mut connections,
+                                            token,
+                                            poll.registry(),
+                                            &monitoring_stats,
+                                            &connection_thread_name,
+                                        );
                                     }
                                 }
                             }
@@ -198,24 +436,29 @@ impl ConnectionThread {
                     }
                 }
             })
-            .expect(format!("Error while creating: {}", connection_thread_name).as_str());
+            .map_err(|err| ServerError::SpawnThread(connection_thread_name.clone(), err))?;
 
-        Self {
+        Ok(Self {
             connection_thread_name,
             server_thread_stop,
             waker,
             event_handler,
             new_connection_sender,
             connection_thread_handle,
-        }
+        })
     }
 
-    pub fn add_connection(&self, connection: TcpStream) {
+    /// `address` is the address of the client returned by `accept`.
+    pub fn add_connection(
+        &self,
+        connection: TcpStream,
+        address: IpAddr,
+    ) -> Result<(), ServerError> {
         self.new_connection_sender
-            .send(connection)
-            .expect("Error while adding connection!");
+            .send((connection, address))
+            .map_err(|_| ServerError::ChannelClosed("add_connection"))?;
 
-        self.waker.wake().expect("Error while wake!")
+        self.waker.wake().map_err(ServerError::Waker)
     }
 
     pub fn get_server_thread_stop(&self) -> ServerThreadStop {
@@ -229,3 +472,48 @@ impl ConnectionThread {
         }
     }
 }
+
+/// Sends `message` to all connections, or only to those with `capability`.
+fn broadcast<T: Into<Frame>>(
+    connections: &mut HashMap<Token, Connection>,
+    message: T,
+    capability: Option<Capability>,
+) {
+    let frame = message.into();
+    // encode only once per codec
+    let mut messages = HashMap::new();
+    for connection in connections.values_mut() {
+        if capability.is_some_and(|capability| !connection.has_capability(capability)) {
+            continue;
+        }
+
+        let codec = connection.codec();
+        let message = messages
+            .entry(codec)
+            .or_insert_with(|| Message::new(frame.clone(), codec));
+        connection.send_message(message.clone());
+    }
+}
+
+fn close_connection(
+    connections: &mut HashMap<Token, Connection>,
+    token: Token,
+    registry: &Registry,
+    monitoring_stats: &MonitoringStats,
+    connection_thread_name: &str,
+) {
+    if let Some(mut connection) = connections.remove(&token) {
+        println!("Connection closed.");
+        connection.logout();
+        monitoring_stats.lost_connection();
+
+        // the socket is closed with the drop anyway
+        if let Err(err) = registry.deregister(&mut connection.into_socket()) {
+            eprintln!(
+                "[{}] Error while deregister connection: {}",
+                connection_thread_name, err
+            );
+            monitoring_stats.error();
+        }
+    }
+}
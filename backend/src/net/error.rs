@@ -0,0 +1,66 @@
+use std::{error::Error, fmt, io, net::SocketAddr};
+
+#[derive(Debug)]
+pub enum ServerError {
+    Bind(SocketAddr, io::Error),
+    Poll(io::Error),
+    Waker(io::Error),
+    /// Registering, reregistering or deregistering a socket failed.
+    Registry(io::Error),
+    SpawnThread(String, io::Error),
+    /// Reading or opening the accounts failed.
+    CredentialStore(io::Error),
+    /// Reading or opening the bans and mutes failed.
+    BanStore(io::Error),
+    /// Reading or opening the roles and topics of rooms failed.
+    RoomStore(io::Error),
+    /// Reading or opening the history failed.
+    HistoryStore(io::Error),
+    /// Reading or opening the messages for offline users failed.
+    OfflineStore(io::Error),
+    /// Loading the certificate or key failed.
+    Tls(String),
+    /// The receiving thread of an event or a new connection has stopped.
+    ChannelClosed(&'static str),
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServerError::Bind(address, err) => write!(f, "can not bind to {}: {}", address, err),
+            ServerError::Poll(err) => write!(f, "poll failed: {}", err),
+            ServerError::Waker(err) => write!(f, "waker failed: {}", err),
+            ServerError::Registry(err) => write!(f, "registry failed: {}", err),
+            ServerError::SpawnThread(name, err) => {
+                write!(f, "can not spawn thread {}: {}", name, err)
+            }
+            ServerError::CredentialStore(err) => write!(f, "can not read accounts: {}", err),
+            ServerError::BanStore(err) => write!(f, "can not read bans: {}", err),
+            ServerError::RoomStore(err) => write!(f, "can not read rooms: {}", err),
+            ServerError::HistoryStore(err) => write!(f, "can not open history: {}", err),
+            ServerError::OfflineStore(err) => {
+                write!(f, "can not read offline messages: {}", err)
+            }
+            ServerError::Tls(reason) => write!(f, "invalid tls config: {}", reason),
+            ServerError::ChannelClosed(channel) => write!(f, "channel {} is closed", channel),
+        }
+    }
+}
+
+impl Error for ServerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ServerError::Bind(_, err)
+            | ServerError::Poll(err)
+            | ServerError::Waker(err)
+            | ServerError::Registry(err)
+            | ServerError::SpawnThread(_, err)
+            | ServerError::CredentialStore(err)
+            | ServerError::BanStore(err)
+            | ServerError::RoomStore(err)
+            | ServerError::HistoryStore(err)
+            | ServerError::OfflineStore(err) => Some(err),
+            ServerError::Tls(_) | ServerError::ChannelClosed(_) => None,
+        }
+    }
+}
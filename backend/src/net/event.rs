@@ -1,5 +1,12 @@
-use crate::net::msg::messages::GlobalChatMessage;
+use crate::net::{error::ServerError, user_directory::ConnectionId};
 use mio::Waker;
+use rust_chat_protocol::{
+    frame::Frame,
+    messages::{
+        Capability, GlobalChatMessage, LoginError, ModeratedMessage, PrivateChatMessage,
+        PrivateChatResultMessage, UserJoinedMessage, UserLeftMessage,
+    },
+};
 use std::sync::{
     mpsc::{channel, Receiver, Sender},
     Arc,
@@ -23,13 +30,11 @@ macro_rules! EventHandler {
             }
 
             $(
-                pub fn $function_name(&self, $variable_name_sender: $type_name) {
+                pub fn $function_name(&self, $variable_name_sender: $type_name) -> Result<(), ServerError> {
                     self.$variable_name_sender
                         .send($variable_name_sender)
-                        .expect(format!("Error while sending event: {}", stringify!($function_name)).as_str());
-                    self.waker
-                        .wake()
-                        .expect(format!("Error while wake for event: {}", stringify!($function_name)).as_str());
+                        .map_err(|_| ServerError::ChannelClosed(stringify!($function_name)))?;
+                    self.waker.wake().map_err(ServerError::Waker)
                 }
             )+
         }
@@ -46,11 +51,67 @@ macro_rules! EventHandler {
     };
 }
 
+/// A frame for all users, e.g. a `MessageEditedMessage` of a global message. Routed to all
+/// threads like global chat messages.
+#[derive(Clone)]
+pub struct BroadcastEvent {
+    pub frame: Frame,
+    /// Only users with the capability receive `frame`.
+    pub capability: Option<Capability>,
+}
+
+/// Routed to the thread of `recipient`.
 #[derive(Clone)]
 pub struct PrivateChatMessageEvent {
-    pub from_user_name: String,
+    pub sender: ConnectionId,
+    pub recipient: ConnectionId,
     pub to_user_name: String,
-    pub message: String,
+    pub message: PrivateChatMessage,
+}
+
+/// Routed back to the thread of `sender`.
+#[derive(Clone)]
+pub struct PrivateChatResultEvent {
+    pub sender: ConnectionId,
+    pub result: PrivateChatResultMessage,
+}
+
+/// A frame for the members of `room`, e.g. a `RoomMessage`. Routed to the threads of
+/// `members`, each thread gets only the members it owns.
+#[derive(Clone)]
+pub struct RoomMessageEvent {
+    pub room: String,
+    pub members: Vec<ConnectionId>,
+    pub frame: Frame,
+    /// Only members with the capability receive `frame`.
+    pub capability: Option<Capability>,
+}
+
+/// A frame for one user, e.g. a `UserTypingMessage`. Routed to the thread of `recipient`.
+#[derive(Clone)]
+pub struct UserMessageEvent {
+    pub recipient: ConnectionId,
+    pub user_name: String,
+    pub frame: Frame,
+    /// Dropped if the user has not the capability.
+    pub capability: Option<Capability>,
+}
+
+/// Routed to the thread of `target`, which notifies the user and applies kicks.
+#[derive(Clone)]
+pub struct ModerationEvent {
+    pub target: ConnectionId,
+    pub user_name: String,
+    pub notice: ModeratedMessage,
+}
+
+/// Result of a login with password, checked on the password thread. Routed back to the thread
+/// of `connection`, which logs the user in.
+#[derive(Clone)]
+pub struct LoginEvent {
+    pub connection: ConnectionId,
+    pub user_name: String,
+    pub result: Result<Option<String>, LoginError>,
 }
 
 EventHandler!(
@@ -60,10 +121,58 @@ EventHandler!(
             global_chat_message_sender,
             global_chat_message_receiver,
         ),
+    BroadcastEvent:
+        (
+            broadcast_event,
+            broadcast_event_sender,
+            broadcast_event_receiver,
+        ),
     PrivateChatMessageEvent:
         (
             private_chat_message_event,
             private_chat_message_event_sender,
             private_chat_message_event_receiver,
+        ),
+    PrivateChatResultEvent:
+        (
+            private_chat_result_event,
+            private_chat_result_event_sender,
+            private_chat_result_event_receiver,
+        ),
+    RoomMessageEvent:
+        (
+            room_message_event,
+            room_message_event_sender,
+            room_message_event_receiver,
+        ),
+    UserMessageEvent:
+        (
+            user_message_event,
+            user_message_event_sender,
+            user_message_event_receiver,
+        ),
+    ModerationEvent:
+        (
+            moderation_event,
+            moderation_event_sender,
+            moderation_event_receiver,
+        ),
+    LoginEvent:
+        (
+            login_event,
+            login_event_sender,
+            login_event_receiver,
+        ),
+    UserJoinedMessage:
+        (
+            broadcast_user_joined,
+            user_joined_sender,
+            user_joined_receiver,
+        ),
+    UserLeftMessage:
+        (
+            broadcast_user_left,
+            user_left_sender,
+            user_left_receiver,
         )
 );
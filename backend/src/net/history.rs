@@ -0,0 +1,316 @@
+use crate::net::{config::ServerConfig, error::ServerError, record_file::RecordFile};
+use rust_chat_protocol::messages::{HistoryEntry, HistoryScope};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+/// Messages of one chat in the history.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Chat {
+    Global,
+    Room(String),
+    /// The user names sorted, both users have the same chat.
+    Private(String, String),
+}
+
+impl Chat {
+    pub fn private(user_name: &str, other_user_name: &str) -> Self {
+        if user_name <= other_user_name {
+            Chat::Private(user_name.to_string(), other_user_name.to_string())
+        } else {
+            Chat::Private(other_user_name.to_string(), user_name.to_string())
+        }
+    }
+
+    /// The chat of `scope` for `user_name`.
+    pub fn of(scope: HistoryScope, user_name: &str) -> Self {
+        match scope {
+            HistoryScope::Global => Chat::Global,
+            HistoryScope::Room(room) => Chat::Room(room),
+            HistoryScope::User(other_user_name) => Chat::private(user_name, &other_user_name),
+        }
+    }
+}
+
+/// One line of the history file, edits and deletes refer to a message of a line before.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Message {
+        chat: Chat,
+        #[serde(flatten)]
+        entry: HistoryEntry,
+    },
+    Edit {
+        edited_id: u64,
+        message: String,
+    },
+    Delete {
+        deleted_id: u64,
+    },
+}
+
+struct Store {
+    /// Entries sorted by id, the oldest first.
+    chats: HashMap<Chat, VecDeque<HistoryEntry>>,
+    /// Message id -> chat of the entry.
+    ids: HashMap<u64, Chat>,
+    /// Id and timestamp of the newest message, also if it was deleted.
+    last_stamp: (u64, u64),
+}
+
+impl Store {
+    fn apply(&mut self, record: Record, history_size: usize) {
+        match record {
+            Record::Message { chat, entry } => {
+                let entries = self.chats.entry(chat.clone()).or_default();
+                if entries.len() >= history_size {
+                    if let Some(oldest) = entries.pop_front() {
+                        self.ids.remove(&oldest.message_id);
+                    }
+                }
+                self.last_stamp = self.last_stamp.max((entry.message_id, entry.timestamp));
+                self.ids.insert(entry.message_id, chat);
+                entries.push_back(entry);
+            }
+            Record::Edit { edited_id, message } => {
+                if let Some(entry) = self.entry_mut(edited_id) {
+                    entry.message = message;
+                    entry.edited = true;
+                }
+            }
+            Record::Delete { deleted_id } => {
+                let Some(entries) = self
+                    .ids
+                    .remove(&deleted_id)
+                    .and_then(|chat| self.chats.get_mut(&chat))
+                else {
+                    return;
+                };
+                if let Ok(index) = position(entries, deleted_id) {
+                    entries.remove(index);
+                }
+            }
+        }
+    }
+
+    fn entry_mut(&mut self, message_id: u64) -> Option<&mut HistoryEntry> {
+        let entries = self.chats.get_mut(self.ids.get(&message_id)?)?;
+        let index = position(entries, message_id).ok()?;
+        entries.get_mut(index)
+    }
+}
+
+fn position(entries: &VecDeque<HistoryEntry>, message_id: u64) -> Result<usize, usize> {
+    entries.binary_search_by_key(&message_id, |entry| entry.message_id)
+}
+
+/// Chat messages of all chats, shared by all threads.
+///
+/// The records of `ServerConfig::history_path` are never compacted, the file keeps all
+/// messages. Only the newest `ServerConfig::history_size` messages of each chat are kept in
+/// memory and can be fetched, edited and deleted.
+pub struct History {
+    config: Arc<ServerConfig>,
+    store: Mutex<(Store, RecordFile)>,
+}
+
+impl History {
+    pub fn load(config: Arc<ServerConfig>) -> Result<Self, ServerError> {
+        let mut store = Store {
+            chats: HashMap::new(),
+            ids: HashMap::new(),
+            last_stamp: (0, 0),
+        };
+        let file = RecordFile::open("history", config.history_path.as_deref(), |record| {
+            store.apply(record, config.history_size)
+        })
+        .map_err(ServerError::HistoryStore)?;
+
+        Ok(Self {
+            config,
+            store: Mutex::new((store, file)),
+        })
+    }
+
+    /// Id and timestamp of the newest message, zero without messages. Ids of deleted messages
+    /// are not used again.
+    pub fn last_stamp(&self) -> (u64, u64) {
+        self.store.lock().unwrap().0.last_stamp
+    }
+
+    /// Stores the message, it needs a higher id than the messages of `chat` before.
+    pub fn append(&self, chat: Chat, entry: HistoryEntry) {
+        self.store(Record::Message { chat, entry });
+    }
+
+    /// Chat and author of the message, `None` if it is not in memory.
+    pub fn find(&self, message_id: u64) -> Option<(Chat, String)> {
+        let (store, _) = &*self.store.lock().unwrap();
+        let chat = store.ids.get(&message_id)?;
+        let entries = store.chats.get(chat)?;
+        let index = position(entries, message_id).ok()?;
+        Some((chat.clone(), entries[index].user_name.clone()))
+    }
+
+    pub fn edit(&self, message_id: u64, message: String) {
+        self.store(Record::Edit {
+            edited_id: message_id,
+            message,
+        });
+    }
+
+    pub fn delete(&self, message_id: u64) {
+        self.store(Record::Delete {
+            deleted_id: message_id,
+        });
+    }
+
+    fn store(&self, record: Record) {
+        let (store, file) = &mut *self.store.lock().unwrap();
+        file.append(&record);
+        store.apply(record, self.config.history_size);
+    }
+
+    /// The newest `limit` messages of `chat` with an id below `before_id`, the oldest first.
+    pub fn fetch(&self, chat: &Chat, before_id: Option<u64>, limit: usize) -> Vec<HistoryEntry> {
+        let (store, _) = &*self.store.lock().unwrap();
+        let Some(entries) = store.chats.get(chat) else {
+            return Vec::new();
+        };
+
+        let end = match before_id {
+            Some(before_id) => entries.partition_point(|entry| entry.message_id < before_id),
+            None => entries.len(),
+        };
+        entries
+            .range(end.saturating_sub(limit)..end)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::record_file::test_path;
+    use std::fs;
+
+    fn load(config: ServerConfig) -> History {
+        History::load(Arc::new(config)).unwrap()
+    }
+
+    fn entry(message_id: u64) -> HistoryEntry {
+        HistoryEntry {
+            message_id,
+            timestamp: message_id * 10,
+            user_name: "alice".to_string(),
+            message: format!("message {}", message_id),
+            edited: false,
+        }
+    }
+
+    fn ids(entries: &[HistoryEntry]) -> Vec<u64> {
+        entries.iter().map(|entry| entry.message_id).collect()
+    }
+
+    #[test]
+    fn pages_go_back_from_the_newest_message() {
+        let history = load(ServerConfig::default());
+        for message_id in 1..=5 {
+            history.append(Chat::Global, entry(message_id));
+        }
+        history.append(Chat::Room("rust".to_string()), entry(6));
+
+        assert_eq!(ids(&history.fetch(&Chat::Global, None, 2)), [4, 5]);
+        assert_eq!(ids(&history.fetch(&Chat::Global, Some(4), 2)), [2, 3]);
+        assert_eq!(ids(&history.fetch(&Chat::Global, Some(2), 2)), [1]);
+        assert!(history.fetch(&Chat::Global, Some(1), 2).is_empty());
+        assert_eq!(
+            ids(&history.fetch(&Chat::Room("rust".to_string()), None, 10)),
+            [6]
+        );
+        assert_eq!(history.last_stamp(), (6, 60));
+    }
+
+    #[test]
+    fn chats_keep_the_newest_messages() {
+        let history = load(ServerConfig {
+            history_size: 2,
+            ..ServerConfig::default()
+        });
+        for message_id in 1..=3 {
+            history.append(Chat::Global, entry(message_id));
+        }
+
+        assert_eq!(ids(&history.fetch(&Chat::Global, None, 10)), [2, 3]);
+    }
+
+    #[test]
+    fn private_chats_are_shared_by_both_users() {
+        let history = load(ServerConfig::default());
+        history.append(Chat::private("bob", "alice"), entry(1));
+
+        let scope = HistoryScope::User("bob".to_string());
+        assert_eq!(
+            ids(&history.fetch(&Chat::of(scope, "alice"), None, 10)),
+            [1]
+        );
+        let scope = HistoryScope::User("alice".to_string());
+        assert_eq!(ids(&history.fetch(&Chat::of(scope, "bob"), None, 10)), [1]);
+    }
+
+    #[test]
+    fn edits_and_deletes_change_the_entry() {
+        let history = load(ServerConfig::default());
+        history.append(Chat::Global, entry(1));
+        history.append(Chat::Room("rust".to_string()), entry(2));
+
+        assert_eq!(history.find(1), Some((Chat::Global, "alice".to_string())));
+        history.edit(1, "edited".to_string());
+        let entries = history.fetch(&Chat::Global, None, 10);
+        assert_eq!(entries[0].message, "edited");
+        assert!(entries[0].edited);
+
+        history.delete(2);
+        assert_eq!(history.find(2), None);
+        assert!(history
+            .fetch(&Chat::Room("rust".to_string()), None, 10)
+            .is_empty());
+        // the id of a deleted message is not used again
+        assert_eq!(history.last_stamp(), (2, 20));
+    }
+
+    #[test]
+    fn messages_are_saved() {
+        let path = test_path("history");
+        let config = || ServerConfig {
+            history_path: Some(path.clone()),
+            history_size: 2,
+            ..ServerConfig::default()
+        };
+        let history = load(config());
+        for message_id in 1..=3 {
+            history.append(Chat::Global, entry(message_id));
+        }
+        history.edit(2, "edited".to_string());
+        history.delete(3);
+        drop(history);
+
+        // the file keeps all messages, the loaded history only the newest
+        let history = load(config());
+        let entries = history.fetch(&Chat::Global, None, 10);
+        assert_eq!(ids(&entries), [2]);
+        assert_eq!(entries[0].message, "edited");
+        assert_eq!(history.last_stamp(), (3, 30));
+        drop(history);
+        let history = load(ServerConfig {
+            history_size: 10,
+            ..config()
+        });
+        assert_eq!(ids(&history.fetch(&Chat::Global, None, 10)), [1, 2]);
+        let _ = fs::remove_file(path);
+    }
+}
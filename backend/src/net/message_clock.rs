@@ -0,0 +1,110 @@
+use crate::net::history::Chat;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Locks the chats are spread over, chats with different locks are stamped at the same time.
+const SEQUENCES: usize = 64;
+
+/// Stamps accepted chat messages with an id and a timestamp, shared by all threads.
+///
+/// Ids increase by one per message and timestamps never decrease, even if the system clock goes
+/// back, so both order the messages of all threads the same way. Messages of one chat are
+/// stamped and sent in `stamp` one after another, they reach the event channels in the order of
+/// their ids and every chat is delivered in this order.
+pub struct MessageClock {
+    /// Id and timestamp of the last message, only locked to take the next one.
+    last: Mutex<(u64, u64)>,
+    /// A chat always uses the same lock, picked by its hash.
+    sequences: [Mutex<()>; SEQUENCES],
+}
+
+impl MessageClock {
+    /// Starts after the id and the timestamp of the last message.
+    pub fn new(last: (u64, u64)) -> Self {
+        Self {
+            last: Mutex::new(last),
+            sequences: std::array::from_fn(|_| Mutex::new(())),
+        }
+    }
+
+    /// Calls `send` with the id and the timestamp in milliseconds since the Unix epoch of a new
+    /// message, no other message of `chat` is stamped until `send` returns.
+    pub fn stamp<T>(&self, chat: &Chat, send: impl FnOnce(u64, u64) -> T) -> T {
+        let _sequence = self.sequences[sequence(chat)].lock().unwrap();
+        let (message_id, timestamp) = self.next();
+        send(message_id, timestamp)
+    }
+
+    fn next(&self) -> (u64, u64) {
+        let now = unix_millis();
+        let mut last = self.last.lock().unwrap();
+        *last = (last.0 + 1, last.1.max(now));
+        *last
+    }
+}
+
+fn sequence(chat: &Chat) -> usize {
+    let mut hasher = DefaultHasher::new();
+    chat.hash(&mut hasher);
+    hasher.finish() as usize % SEQUENCES
+}
+
+/// Milliseconds since the Unix epoch, the time of message timestamps.
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::mpsc::channel, thread};
+
+    #[test]
+    fn ids_increase_and_timestamps_never_decrease() {
+        // the last message is from the future, e.g. the clock went back since
+        let future = unix_millis() + 60_000;
+        let clock = MessageClock::new((41, future));
+
+        assert_eq!(
+            clock.stamp(&Chat::Global, |id, time| (id, time)),
+            (42, future)
+        );
+        assert_eq!(
+            clock.stamp(&Chat::Room("rust".to_string()), |id, time| (id, time)),
+            (43, future)
+        );
+    }
+
+    #[test]
+    fn chats_do_not_wait_for_each_other() {
+        let clock = MessageClock::new((0, 0));
+        let other = (0..)
+            .map(|i| Chat::Room(i.to_string()))
+            .find(|chat| sequence(chat) != sequence(&Chat::Global))
+            .unwrap();
+
+        let (stamped, stamped_receiver) = channel();
+        let (done, done_receiver) = channel::<()>();
+        thread::scope(|scope| {
+            // stamps a global message and waits inside `send`
+            let clock = &clock;
+            scope.spawn(move || {
+                clock.stamp(&Chat::Global, |id, _| {
+                    stamped.send(id).unwrap();
+                    done_receiver.recv().unwrap();
+                })
+            });
+
+            assert_eq!(stamped_receiver.recv().unwrap(), 1);
+            assert_eq!(clock.stamp(&other, |id, _| id), 2);
+            done.send(()).unwrap();
+        });
+    }
+}
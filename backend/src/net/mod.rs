@@ -1,8 +1,20 @@
+pub mod config;
+pub mod error;
 pub mod msg;
 pub mod server;
 
+mod auth;
 mod connection;
 mod connection_thread;
 mod event;
+mod history;
+mod message_clock;
+mod moderation;
 mod monitoring;
+mod offline_queue;
+mod record_file;
+mod room_directory;
 mod server_stop;
+mod state;
+mod transport;
+mod user_directory;
@@ -0,0 +1,240 @@
+use crate::net::{config::ServerConfig, error::ServerError, record_file::RecordFile};
+use rust_chat_protocol::messages::Role;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+/// Rank of a user in a room, global operators outrank all roles.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Rank {
+    Role(Role),
+    GlobalOperator,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Ban {
+    /// `None` for the whole server.
+    room: Option<String>,
+    user_name: String,
+    /// Address of the user at the time of the ban, unknown if the user was not logged in.
+    address: Option<IpAddr>,
+}
+
+impl Ban {
+    fn covers(&self, room: Option<&str>, user_name: Option<&str>, address: IpAddr) -> bool {
+        (self.room.is_none() || self.room.as_deref() == room)
+            && (user_name == Some(self.user_name.as_str()) || self.address == Some(address))
+    }
+}
+
+/// One line of the bans file, an unban or unmute removes the bans or mutes of a line before.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Ban(Ban),
+    Unban {
+        room: Option<String>,
+        unbanned: String,
+    },
+    Mute {
+        room: Option<String>,
+        muted: String,
+    },
+    Unmute {
+        room: Option<String>,
+        unmuted: String,
+    },
+}
+
+#[derive(Default)]
+struct Sanctions {
+    bans: Vec<Ban>,
+    /// Room and user name, without room the user is muted on the whole server.
+    mutes: HashSet<(Option<String>, String)>,
+}
+
+/// Bans and mutes, shared by all threads. Both are saved to `ServerConfig::bans_path`, its
+/// records are compacted with each start.
+pub struct Moderation {
+    config: Arc<ServerConfig>,
+    sanctions: Mutex<(Sanctions, RecordFile)>,
+}
+
+impl Moderation {
+    pub fn load(config: Arc<ServerConfig>) -> Result<Self, ServerError> {
+        let mut sanctions = Sanctions::default();
+        let mut file = RecordFile::open("bans", config.bans_path.as_deref(), |record| {
+            apply(&mut sanctions, record)
+        })
+        .map_err(ServerError::BanStore)?;
+        let bans = sanctions.bans.iter().cloned().map(Record::Ban);
+        let mutes = sanctions.mutes.iter().map(|(room, muted)| Record::Mute {
+            room: room.clone(),
+            muted: muted.clone(),
+        });
+        file.compact(bans.chain(mutes));
+
+        Ok(Self {
+            config,
+            sanctions: Mutex::new((sanctions, file)),
+        })
+    }
+
+    /// Returns `true` if the name is one of `ServerConfig::operators`.
+    pub fn is_operator(&self, user_name: &str) -> bool {
+        self.config
+            .operators
+            .iter()
+            .any(|operator| operator == user_name)
+    }
+
+    /// Returns `true` if the name or the address is banned from `room`, bans on the whole
+    /// server apply to all rooms. Without room only bans on the whole server are checked.
+    pub fn is_banned(&self, room: Option<&str>, user_name: Option<&str>, address: IpAddr) -> bool {
+        self.sanctions
+            .lock()
+            .unwrap()
+            .0
+            .bans
+            .iter()
+            .any(|ban| ban.covers(room, user_name, address))
+    }
+
+    pub fn ban(&self, room: Option<String>, user_name: String, address: Option<IpAddr>) {
+        self.store(Record::Ban(Ban {
+            room,
+            user_name,
+            address,
+        }));
+    }
+
+    /// Removes all bans of the user from `room`.
+    pub fn unban(&self, room: Option<&str>, user_name: &str) {
+        self.store(Record::Unban {
+            room: room.map(|room| room.to_string()),
+            unbanned: user_name.to_string(),
+        });
+    }
+
+    fn store(&self, record: Record) {
+        let (sanctions, file) = &mut *self.sanctions.lock().unwrap();
+        file.append(&record);
+        apply(sanctions, record);
+    }
+
+    /// Returns `true` if the user is muted in `room` or on the whole server.
+    pub fn is_muted(&self, room: Option<&str>, user_name: &str) -> bool {
+        let mutes = &self.sanctions.lock().unwrap().0.mutes;
+        mutes.contains(&(None, user_name.to_string()))
+            || room.is_some_and(|room| {
+                mutes.contains(&(Some(room.to_string()), user_name.to_string()))
+            })
+    }
+
+    pub fn mute(&self, room: Option<String>, user_name: String) {
+        self.store(Record::Mute {
+            room,
+            muted: user_name,
+        });
+    }
+
+    pub fn unmute(&self, room: Option<String>, user_name: String) {
+        self.store(Record::Unmute {
+            room,
+            unmuted: user_name,
+        });
+    }
+}
+
+fn apply(sanctions: &mut Sanctions, record: Record) {
+    match record {
+        Record::Ban(ban) => sanctions.bans.push(ban),
+        Record::Unban { room, unbanned } => sanctions
+            .bans
+            .retain(|ban| ban.room != room || ban.user_name != unbanned),
+        Record::Mute { room, muted } => {
+            sanctions.mutes.insert((room, muted));
+        }
+        Record::Unmute { room, unmuted } => {
+            sanctions.mutes.remove(&(room, unmuted));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::record_file::test_path;
+    use std::{fs, net::Ipv4Addr};
+
+    const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    const OTHER_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+    fn load(config: ServerConfig) -> Moderation {
+        Moderation::load(Arc::new(config)).unwrap()
+    }
+
+    #[test]
+    fn bans_cover_name_or_address() {
+        let moderation = load(ServerConfig::default());
+        moderation.ban(Some("rust".to_string()), "bob".to_string(), Some(ADDRESS));
+        moderation.ban(None, "eve".to_string(), None);
+
+        assert!(moderation.is_banned(Some("rust"), Some("bob"), OTHER_ADDRESS));
+        assert!(moderation.is_banned(Some("rust"), Some("bobby"), ADDRESS));
+        assert!(!moderation.is_banned(Some("go"), Some("bob"), ADDRESS));
+        assert!(!moderation.is_banned(None, Some("bob"), ADDRESS));
+        // bans on the whole server apply to all rooms
+        assert!(moderation.is_banned(Some("go"), Some("eve"), OTHER_ADDRESS));
+        assert!(moderation.is_banned(None, Some("eve"), OTHER_ADDRESS));
+
+        moderation.unban(Some("rust"), "bob");
+        assert!(!moderation.is_banned(Some("rust"), Some("bob"), ADDRESS));
+    }
+
+    #[test]
+    fn mutes_without_room_cover_all_chats() {
+        let moderation = load(ServerConfig::default());
+        moderation.mute(Some("rust".to_string()), "bob".to_string());
+        moderation.mute(None, "eve".to_string());
+
+        assert!(moderation.is_muted(Some("rust"), "bob"));
+        assert!(!moderation.is_muted(Some("go"), "bob"));
+        assert!(!moderation.is_muted(None, "bob"));
+        assert!(moderation.is_muted(Some("go"), "eve"));
+        assert!(moderation.is_muted(None, "eve"));
+
+        moderation.unmute(None, "eve".to_string());
+        assert!(!moderation.is_muted(Some("go"), "eve"));
+    }
+
+    #[test]
+    fn bans_and_mutes_are_saved() {
+        let path = test_path("bans");
+        let config = || ServerConfig {
+            bans_path: Some(path.clone()),
+            ..ServerConfig::default()
+        };
+        let moderation = load(config());
+        moderation.ban(Some("rust".to_string()), "bob".to_string(), Some(ADDRESS));
+        moderation.ban(None, "eve".to_string(), None);
+        moderation.unban(None, "eve");
+        moderation.mute(None, "carol".to_string());
+        moderation.mute(Some("rust".to_string()), "dave".to_string());
+        moderation.unmute(Some("rust".to_string()), "dave".to_string());
+        drop(moderation);
+
+        // loaded twice, the second time from the compacted file
+        for _ in 0..2 {
+            let moderation = load(config());
+            assert!(moderation.is_banned(Some("rust"), None, ADDRESS));
+            assert!(!moderation.is_banned(None, Some("eve"), OTHER_ADDRESS));
+            assert!(moderation.is_muted(None, "carol"));
+            assert!(!moderation.is_muted(Some("rust"), "dave"));
+        }
+        let _ = fs::remove_file(path);
+    }
+}
@@ -1,3 +1,4 @@
+use rust_chat_protocol::compression::CompressionStats;
 use std::{
     sync::{
         atomic::{AtomicUsize, Ordering},
@@ -31,6 +32,27 @@ pub struct MonitoringStats {
 
     messeges_send: AtomicUsize,
     total_messages_send: AtomicUsize,
+
+    read_calls: AtomicUsize,
+    total_read_calls: AtomicUsize,
+
+    write_calls: AtomicUsize,
+    total_write_calls: AtomicUsize,
+
+    evicted_messages: AtomicUsize,
+    total_evicted_messages: AtomicUsize,
+
+    slow_consumers: AtomicUsize,
+    total_slow_consumers: AtomicUsize,
+
+    errors: AtomicUsize,
+    total_errors: AtomicUsize,
+
+    bytes_uncompressed: AtomicUsize,
+    total_bytes_uncompressed: AtomicUsize,
+
+    bytes_compressed: AtomicUsize,
+    total_bytes_compressed: AtomicUsize,
 }
 
 impl Monitoring {
@@ -68,6 +90,27 @@ impl Monitoring {
             let mut messeges_send = 0;
             let mut total_messages_send = 0;
 
+            let mut read_calls = 0;
+            let mut total_read_calls = 0;
+
+            let mut write_calls = 0;
+            let mut total_write_calls = 0;
+
+            let mut evicted_messages = 0;
+            let mut total_evicted_messages = 0;
+
+            let mut slow_consumers = 0;
+            let mut total_slow_consumers = 0;
+
+            let mut errors = 0;
+            let mut total_errors = 0;
+
+            let mut bytes_uncompressed = 0;
+            let mut total_bytes_uncompressed = 0;
+
+            let mut bytes_compressed = 0;
+            let mut total_bytes_compressed = 0;
+
             for stats in &self.stats {
                 new_connections += stats.new_connections.load(Ordering::Relaxed);
                 total_new_connections += stats.total_new_connections.load(Ordering::Relaxed);
@@ -86,6 +129,27 @@ impl Monitoring {
 
                 messeges_send += stats.messeges_send.load(Ordering::Relaxed);
                 total_messages_send += stats.total_messages_send.load(Ordering::Relaxed);
+
+                read_calls += stats.read_calls.load(Ordering::Relaxed);
+                total_read_calls += stats.total_read_calls.load(Ordering::Relaxed);
+
+                write_calls += stats.write_calls.load(Ordering::Relaxed);
+                total_write_calls += stats.total_write_calls.load(Ordering::Relaxed);
+
+                evicted_messages += stats.evicted_messages.load(Ordering::Relaxed);
+                total_evicted_messages += stats.total_evicted_messages.load(Ordering::Relaxed);
+
+                slow_consumers += stats.slow_consumers.load(Ordering::Relaxed);
+                total_slow_consumers += stats.total_slow_consumers.load(Ordering::Relaxed);
+
+                errors += stats.errors.load(Ordering::Relaxed);
+                total_errors += stats.total_errors.load(Ordering::Relaxed);
+
+                bytes_uncompressed += stats.bytes_uncompressed.load(Ordering::Relaxed);
+                total_bytes_uncompressed += stats.total_bytes_uncompressed.load(Ordering::Relaxed);
+
+                bytes_compressed += stats.bytes_compressed.load(Ordering::Relaxed);
+                total_bytes_compressed += stats.total_bytes_compressed.load(Ordering::Relaxed);
             }
 
             // print stats
@@ -102,6 +166,13 @@ impl Monitoring {
                 Bytes send:            | {:w1$.3}  |  {:w1$.3}  |  {:w2$}  |  {:w2$} |                    \n\
                 Messages received:     | {:w1$.3}  |  {:w1$.3}  |  {:w2$}  |  {:w2$} |                    \n\
                 Messages send:         | {:w1$.3}  |  {:w1$.3}  |  {:w2$}  |  {:w2$} |                    \n\
+                Read calls:            | {:w1$.3}  |  {:w1$.3}  |  {:w2$}  |  {:w2$} |                    \n\
+                Write calls:           | {:w1$.3}  |  {:w1$.3}  |  {:w2$}  |  {:w2$} |                    \n\
+                Evicted messages:      | {:w1$.3}  |  {:w1$.3}  |  {:w2$}  |  {:w2$} |                    \n\
+                Slow consumers:        | {:w1$.3}  |  {:w1$.3}  |  {:w2$}  |  {:w2$} |                    \n\
+                Errors:                | {:w1$.3}  |  {:w1$.3}  |  {:w2$}  |  {:w2$} |                    \n\
+                Bytes uncompressed:    | {:w1$.3}  |  {:w1$.3}  |  {:w2$}  |  {:w2$} |                    \n\
+                Bytes compressed:      | {:w1$.3}  |  {:w1$.3}  |  {:w2$}  |  {:w2$} |                    \n\
                 ----------------------------------------------------------------------------------------- \n\
                 ",
                 elapsed_time,
@@ -130,6 +201,34 @@ impl Monitoring {
                 (total_messages_send as f32 / elapsed_start_time_sec),
                 messeges_send,
                 total_messages_send,
+                (read_calls as f32 / elapsed_time_sec),
+                (total_read_calls as f32 / elapsed_start_time_sec),
+                read_calls,
+                total_read_calls,
+                (write_calls as f32 / elapsed_time_sec),
+                (total_write_calls as f32 / elapsed_start_time_sec),
+                write_calls,
+                total_write_calls,
+                (evicted_messages as f32 / elapsed_time_sec),
+                (total_evicted_messages as f32 / elapsed_start_time_sec),
+                evicted_messages,
+                total_evicted_messages,
+                (slow_consumers as f32 / elapsed_time_sec),
+                (total_slow_consumers as f32 / elapsed_start_time_sec),
+                slow_consumers,
+                total_slow_consumers,
+                (errors as f32 / elapsed_time_sec),
+                (total_errors as f32 / elapsed_start_time_sec),
+                errors,
+                total_errors,
+                (bytes_uncompressed as f32 / elapsed_time_sec),
+                (total_bytes_uncompressed as f32 / elapsed_start_time_sec),
+                bytes_uncompressed,
+                total_bytes_uncompressed,
+                (bytes_compressed as f32 / elapsed_time_sec),
+                (total_bytes_compressed as f32 / elapsed_start_time_sec),
+                bytes_compressed,
+                total_bytes_compressed,
                 w1 = 12,
                 w2 = 8
             );
@@ -142,6 +241,13 @@ impl Monitoring {
                 stats.bytes_send.store(0, Ordering::SeqCst);
                 stats.messages_received.store(0, Ordering::SeqCst);
                 stats.messeges_send.store(0, Ordering::SeqCst);
+                stats.read_calls.store(0, Ordering::SeqCst);
+                stats.write_calls.store(0, Ordering::SeqCst);
+                stats.evicted_messages.store(0, Ordering::SeqCst);
+                stats.slow_consumers.store(0, Ordering::SeqCst);
+                stats.errors.store(0, Ordering::SeqCst);
+                stats.bytes_uncompressed.store(0, Ordering::SeqCst);
+                stats.bytes_compressed.store(0, Ordering::SeqCst);
             }
             self.last_time = Instant::now();
         }
@@ -151,7 +257,7 @@ impl Monitoring {
         let monitoring_stats = Arc::new(MonitoringStats::new());
         let monitoring_stats_return = Arc::clone(&monitoring_stats);
         self.stats.push(monitoring_stats);
-        return monitoring_stats_return;
+        monitoring_stats_return
     }
 }
 
@@ -175,6 +281,27 @@ impl MonitoringStats {
 
             messeges_send: AtomicUsize::new(0),
             total_messages_send: AtomicUsize::new(0),
+
+            read_calls: AtomicUsize::new(0),
+            total_read_calls: AtomicUsize::new(0),
+
+            write_calls: AtomicUsize::new(0),
+            total_write_calls: AtomicUsize::new(0),
+
+            evicted_messages: AtomicUsize::new(0),
+            total_evicted_messages: AtomicUsize::new(0),
+
+            slow_consumers: AtomicUsize::new(0),
+            total_slow_consumers: AtomicUsize::new(0),
+
+            errors: AtomicUsize::new(0),
+            total_errors: AtomicUsize::new(0),
+
+            bytes_uncompressed: AtomicUsize::new(0),
+            total_bytes_uncompressed: AtomicUsize::new(0),
+
+            bytes_compressed: AtomicUsize::new(0),
+            total_bytes_compressed: AtomicUsize::new(0),
         }
     }
 
@@ -203,8 +330,50 @@ impl MonitoringStats {
         self.total_messages_received.fetch_add(1, Ordering::SeqCst);
     }
 
-    pub fn messege_send(&self) {
-        self.messeges_send.fetch_add(1, Ordering::SeqCst);
-        self.total_messages_send.fetch_add(1, Ordering::SeqCst);
+    pub fn messege_send(&self, amount: usize) {
+        self.messeges_send.fetch_add(amount, Ordering::SeqCst);
+        self.total_messages_send.fetch_add(amount, Ordering::SeqCst);
+    }
+
+    pub fn evicted_messages(&self, amount: usize) {
+        self.evicted_messages.fetch_add(amount, Ordering::SeqCst);
+        self.total_evicted_messages
+            .fetch_add(amount, Ordering::SeqCst);
+    }
+
+    pub fn slow_consumer(&self) {
+        self.slow_consumers.fetch_add(1, Ordering::SeqCst);
+        self.total_slow_consumers.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::SeqCst);
+        self.total_errors.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn read_call(&self) {
+        self.read_calls.fetch_add(1, Ordering::SeqCst);
+        self.total_read_calls.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn write_call(&self) {
+        self.write_calls.fetch_add(1, Ordering::SeqCst);
+        self.total_write_calls.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Counts the payload sizes of compressed messages, send and received.
+    pub fn compression(&self, compression_stats: CompressionStats) {
+        if compression_stats.compressed == 0 {
+            return;
+        }
+
+        self.bytes_uncompressed
+            .fetch_add(compression_stats.uncompressed, Ordering::SeqCst);
+        self.total_bytes_uncompressed
+            .fetch_add(compression_stats.uncompressed, Ordering::SeqCst);
+        self.bytes_compressed
+            .fetch_add(compression_stats.compressed, Ordering::SeqCst);
+        self.total_bytes_compressed
+            .fetch_add(compression_stats.compressed, Ordering::SeqCst);
     }
 }
@@ -1,12 +1,84 @@
 use crate::net::connection::Connection;
-use crate::net::event::PrivateChatMessageEvent;
-use crate::net::msg::message::{Message, MessageTrait};
-use serde::{Deserialize, Serialize};
+use crate::net::error::ServerError;
+use crate::net::event::{
+    BroadcastEvent, LoginEvent, ModerationEvent, PrivateChatMessageEvent, RoomMessageEvent,
+    UserMessageEvent,
+};
+use crate::net::history::Chat;
+use crate::net::moderation::Rank;
+use crate::net::msg::message::MessageTrait;
+use crate::net::offline_queue::Deferral;
+use rust_chat_protocol::codec::Codec;
+use rust_chat_protocol::frame::Frame;
+use rust_chat_protocol::framing::Framing;
+use rust_chat_protocol::message::Message;
+use rust_chat_protocol::messages::{
+    is_valid_room_name, is_valid_user_name, Auth, Capability, Conversation, DeleteMessage,
+    DeliveryStatus, EditMessage, ErrorCode, ErrorMessage, FetchHistoryMessage, GlobalChatMessage,
+    HelloMessage, HistoryEntry, HistoryMessage, HistoryScope, JoinRoomMessage, LeaveRoomMessage,
+    ListRoomsMessage, ListUsersMessage, LoginError, LoginMessage, LoginResultMessage,
+    MessageDeletedMessage, MessageEditedMessage, MessageReadMessage, ModeratedMessage,
+    ModerationAction, ModerationError, ModerationMessage, ModerationResultMessage, PingMessage,
+    PrivateChatMessage, PrivateChatResultMessage, PublishGlobalChatMessage,
+    PublishPrivateChatMessage, PublishRoomMessage, ReadMessage, Role, RoomAction, RoomError,
+    RoomListMessage, RoomMessage, RoomResultMessage, RoomTopicMessage, SetTopicMessage,
+    TypingMessage, UserJoinedMessage, UserLeftMessage, UserListMessage, UserTypingMessage,
+    WelcomeMessage, MAX_TOPIC_LENGTH, MAX_USER_NAME_LENGTH, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use std::sync::Arc;
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct PingMessage {
-    pub nonce: u32,
-    pub reply: bool,
+/// Capabilities this server implements.
+const SERVER_CAPABILITIES: &[Capability] = &[
+    Capability::Compression,
+    Capability::Rooms,
+    Capability::Presence,
+    Capability::Typing,
+    Capability::History,
+    Capability::Edit,
+];
+
+impl MessageTrait for Frame {
+    fn process(self, connection: &mut Connection) {
+        match self {
+            Frame::Ping(message) => message.process(connection),
+            Frame::Login(message) => message.process(connection),
+            Frame::PublishGlobalChat(message) => message.process(connection),
+            Frame::GlobalChat(message) => message.process(connection),
+            Frame::PublishPrivateChat(message) => message.process(connection),
+            Frame::PrivateChat(message) => message.process(connection),
+            Frame::Error(message) => message.process(connection),
+            Frame::Hello(message) => message.process(connection),
+            Frame::Welcome(message) => message.process(connection),
+            Frame::PrivateChatResult(message) => message.process(connection),
+            Frame::LoginResult(message) => message.process(connection),
+            Frame::JoinRoom(message) => message.process(connection),
+            Frame::LeaveRoom(message) => message.process(connection),
+            Frame::RoomResult(message) => message.process(connection),
+            Frame::PublishRoom(message) => message.process(connection),
+            Frame::Room(message) => message.process(connection),
+            Frame::ListRooms(message) => message.process(connection),
+            Frame::RoomList(message) => message.process(connection),
+            Frame::Moderation(message) => message.process(connection),
+            Frame::ModerationResult(message) => message.process(connection),
+            Frame::Moderated(message) => message.process(connection),
+            Frame::SetTopic(message) => message.process(connection),
+            Frame::RoomTopic(message) => message.process(connection),
+            Frame::UserJoined(message) => message.process(connection),
+            Frame::UserLeft(message) => message.process(connection),
+            Frame::ListUsers(message) => message.process(connection),
+            Frame::UserList(message) => message.process(connection),
+            Frame::Typing(message) => message.process(connection),
+            Frame::UserTyping(message) => message.process(connection),
+            Frame::Read(message) => message.process(connection),
+            Frame::MessageRead(message) => message.process(connection),
+            Frame::FetchHistory(message) => message.process(connection),
+            Frame::History(message) => message.process(connection),
+            Frame::Edit(message) => message.process(connection),
+            Frame::Delete(message) => message.process(connection),
+            Frame::MessageEdited(message) => message.process(connection),
+            Frame::MessageDeleted(message) => message.process(connection),
+        }
+    }
 }
 
 impl MessageTrait for PingMessage {
@@ -16,108 +88,1195 @@ impl MessageTrait for PingMessage {
                 nonce: self.nonce,
                 reply: true,
             };
-            connection.send_message(Message::new(reply));
+            connection.send_frame(reply);
         }
     }
+}
 
-    fn number(&self) -> u32 {
-        0
+impl MessageTrait for LoginMessage {
+    fn process(self, connection: &mut Connection) {
+        let result = if connection.user_name.is_some() {
+            Err(LoginError::AlreadyLoggedIn)
+        } else if !is_valid_user_name(&self.user_name) {
+            Err(LoginError::InvalidName)
+        } else if connection
+            .config
+            .reserved_user_names
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(&self.user_name))
+        {
+            Err(LoginError::ReservedName)
+        } else if connection.state.moderation.is_banned(
+            None,
+            Some(&self.user_name),
+            connection.peer_ip(),
+        ) {
+            Err(LoginError::Banned)
+        } else if connection
+            .state
+            .user_directory
+            .get(&self.user_name)
+            .is_some()
+        {
+            // before an account is created for the name
+            Err(LoginError::NameTaken)
+        } else if let Some(Auth::Password(_)) = &self.auth {
+            // argon2 is slow, the event loop goes on while the password thread checks it
+            connection.login_pending = true;
+            let state = Arc::clone(&connection.state);
+            let server_event_handler = connection.server_event_handler.clone();
+            let connection_id = connection.id();
+            let address = connection.peer_ip();
+            connection
+                .state
+                .authenticator
+                .check_password_later(move || {
+                    let result =
+                        state
+                            .authenticator
+                            .authenticate(&self.user_name, self.auth, address);
+                    let login_event = LoginEvent {
+                        connection: connection_id,
+                        user_name: self.user_name,
+                        result,
+                    };
+                    if let Err(err) = server_event_handler.login_event(login_event) {
+                        eprintln!("Error while sending login result: {}", err);
+                    }
+                });
+            return;
+        } else {
+            connection.state.authenticator.authenticate(
+                &self.user_name,
+                self.auth,
+                connection.peer_ip(),
+            )
+        };
+
+        complete_login(connection, self.user_name, result);
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct LoginMessage {
-    pub user_name: String,
-}
+/// Logs the user in if authenticated, replies with the `LoginResultMessage` and sends the
+/// history replay and the queued private messages.
+pub fn complete_login(
+    connection: &mut Connection,
+    user_name: String,
+    result: Result<Option<String>, LoginError>,
+) {
+    let result = result.and_then(|session_token| {
+        if connection.login(user_name.clone()) {
+            Ok(session_token)
+        } else {
+            Err(LoginError::NameTaken)
+        }
+    });
 
-impl MessageTrait for LoginMessage {
-    fn process(self, connection: &mut Connection) {
-        connection.user_name = Some(self.user_name);
+    let (error, session_token) = match result {
+        Ok(session_token) => {
+            connection.authenticated = session_token.is_some();
+            connection.global_operator =
+                connection.authenticated && connection.state.moderation.is_operator(&user_name);
+            (None, session_token)
+        }
+        Err(error) => (Some(error), None),
+    };
+    let logged_in = error.is_none();
+    connection.send_frame(LoginResultMessage {
+        user_name: user_name.clone(),
+        error,
+        session_token,
+    });
+
+    // what was said before the login
+    if logged_in && connection.has_capability(Capability::History) {
+        let messages =
+            connection
+                .state
+                .history
+                .fetch(&Chat::Global, None, connection.config.history_replay);
+        if !messages.is_empty() {
+            send_history(connection, HistoryScope::Global, messages);
+        }
     }
 
-    fn number(&self) -> u32 {
-        1
+    // what was sent to the user while offline
+    if logged_in {
+        for message in connection.state.offline_queue.take(&user_name) {
+            connection.received_private_message(message);
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct PublishGlobalChatMessage {
-    pub message: String,
+impl MessageTrait for LoginResultMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for UserJoinedMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for UserLeftMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for ListUsersMessage {
+    fn process(self, connection: &mut Connection) {
+        let user_names = connection.state.user_directory.list();
+        connection.send_frame(UserListMessage { user_names });
+    }
+}
+
+impl MessageTrait for UserListMessage {
+    fn process(self, _connection: &mut Connection) {}
 }
 
 impl MessageTrait for PublishGlobalChatMessage {
     fn process(self, connection: &mut Connection) {
         match &connection.user_name {
+            Some(user_name) if connection.state.moderation.is_muted(None, user_name) => connection
+                .send_error(
+                    ErrorCode::Muted,
+                    "You are muted".to_string(),
+                    Some(Frame::from(self).number()),
+                ),
+            Some(_) if !fits_message_size(connection, &Chat::Global, &self.message) => connection
+                .send_error(
+                    ErrorCode::PayloadTooLarge,
+                    "The message is too large".to_string(),
+                    Some(Frame::from(self).number()),
+                ),
             Some(user_name) => {
-                let reply = GlobalChatMessage {
-                    user_name: user_name.clone(),
-                    message: self.message,
-                };
-                connection
-                    .server_event_handler
-                    .broadcast_global_chat_message(reply);
+                let result =
+                    connection
+                        .state
+                        .message_clock
+                        .stamp(&Chat::Global, |message_id, timestamp| {
+                            connection.state.history.append(
+                                Chat::Global,
+                                HistoryEntry {
+                                    message_id,
+                                    timestamp,
+                                    user_name: user_name.clone(),
+                                    message: self.message.clone(),
+                                    edited: false,
+                                },
+                            );
+                            let reply = GlobalChatMessage {
+                                user_name: user_name.clone(),
+                                message: self.message,
+                                message_id,
+                                timestamp,
+                            };
+                            connection
+                                .server_event_handler
+                                .broadcast_global_chat_message(reply)
+                        });
+                if let Err(err) = result {
+                    connection.report_error(err);
+                }
             }
-            None => {}
+            None => connection.send_error(
+                ErrorCode::NotLoggedIn,
+                "Login before sending global chat messages".to_string(),
+                Some(Frame::from(self).number()),
+            ),
         }
     }
+}
+
+impl MessageTrait for GlobalChatMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for PublishPrivateChatMessage {
+    fn process(self, connection: &mut Connection) {
+        let user_name = match connection.user_name.clone() {
+            Some(user_name) => user_name,
+            None => {
+                connection.send_error(
+                    ErrorCode::NotLoggedIn,
+                    "Login before sending private chat messages".to_string(),
+                    Some(Frame::from(self).number()),
+                );
+                return;
+            }
+        };
+
+        if connection.state.moderation.is_muted(None, &user_name) {
+            connection.send_error(
+                ErrorCode::Muted,
+                "You are muted".to_string(),
+                Some(Frame::from(self).number()),
+            );
+            return;
+        }
+
+        // users with account get the message with their next login
+        let state = Arc::clone(&connection.state);
+        let recipient = state.user_directory.get(&self.to_user_name);
+        if recipient.is_none() && !state.authenticator.has_account(&self.to_user_name) {
+            connection.send_frame(PrivateChatResultMessage {
+                to_user_name: self.to_user_name,
+                status: DeliveryStatus::UserNotFound,
+                message_id: None,
+            });
+            return;
+        }
+
+        let chat = Chat::private(&user_name, &self.to_user_name);
+        if !fits_message_size(connection, &chat, &self.message) {
+            connection.send_error(
+                ErrorCode::PayloadTooLarge,
+                "The message is too large".to_string(),
+                Some(Frame::from(self).number()),
+            );
+            return;
+        }
+
+        let result = state.message_clock.stamp(&chat, |message_id, timestamp| {
+            let message = PrivateChatMessage {
+                from_user_name: user_name.clone(),
+                message: self.message,
+                message_id,
+                timestamp,
+            };
+            let deferral = match recipient {
+                Some(recipient) => Deferral::Online(recipient),
+                None => state.offline_queue.defer(
+                    &state.user_directory,
+                    &self.to_user_name,
+                    message.clone(),
+                ),
+            };
+
+            if !matches!(deferral, Deferral::QueueFull) {
+                state.history.append(
+                    chat.clone(),
+                    HistoryEntry {
+                        message_id,
+                        timestamp,
+                        user_name: user_name.clone(),
+                        message: message.message.clone(),
+                        edited: false,
+                    },
+                );
+            }
 
-    fn number(&self) -> u32 {
-        2
+            match deferral {
+                // the thread of the recipient replies with the result
+                Deferral::Online(recipient) => connection
+                    .server_event_handler
+                    .private_chat_message_event(PrivateChatMessageEvent {
+                        sender: connection.id(),
+                        recipient,
+                        to_user_name: self.to_user_name.clone(),
+                        message,
+                    })
+                    .map(|_| None),
+                Deferral::Deferred => Ok(Some((DeliveryStatus::Deferred, Some(message_id)))),
+                Deferral::QueueFull => Ok(Some((DeliveryStatus::QueueFull, None))),
+            }
+        });
+
+        match result {
+            Ok(Some((status, message_id))) => connection.send_frame(PrivateChatResultMessage {
+                to_user_name: self.to_user_name,
+                status,
+                message_id,
+            }),
+            Ok(None) => {}
+            Err(err) => connection.report_error(err),
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct GlobalChatMessage {
-    pub user_name: String,
-    pub message: String,
+impl MessageTrait for PrivateChatMessage {
+    fn process(self, _connection: &mut Connection) {}
 }
 
-impl MessageTrait for GlobalChatMessage {
+impl MessageTrait for PrivateChatResultMessage {
     fn process(self, _connection: &mut Connection) {}
+}
 
-    fn number(&self) -> u32 {
-        3
+impl MessageTrait for FetchHistoryMessage {
+    fn process(self, connection: &mut Connection) {
+        let user_name = match connection.user_name.clone() {
+            Some(user_name) => user_name,
+            None => {
+                connection.send_error(
+                    ErrorCode::NotLoggedIn,
+                    "Login before fetching the history".to_string(),
+                    Some(Frame::from(self).number()),
+                );
+                return;
+            }
+        };
+
+        if let HistoryScope::Room(room) = &self.scope {
+            if !connection.rooms.contains(room) {
+                connection.send_error(
+                    ErrorCode::NotInRoom,
+                    format!("Join room {} before fetching its history", room),
+                    Some(Frame::from(self).number()),
+                );
+                return;
+            }
+        }
+
+        let limit = self.limit.min(connection.config.max_history_fetch);
+        let messages = connection.state.history.fetch(
+            &Chat::of(self.scope.clone(), &user_name),
+            self.before_id,
+            limit,
+        );
+        send_history(connection, self.scope, messages);
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct PublishPrivateChatMessage {
-    pub to_user_name: String,
-    pub message: String,
+impl MessageTrait for HistoryMessage {
+    fn process(self, _connection: &mut Connection) {}
 }
 
-impl MessageTrait for PublishPrivateChatMessage {
+impl MessageTrait for EditMessage {
     fn process(self, connection: &mut Connection) {
-        match &connection.user_name {
-            Some(user_name) => {
-                let reply = PrivateChatMessageEvent {
-                    from_user_name: user_name.clone(),
-                    to_user_name: self.to_user_name,
-                    message: self.message,
-                };
+        let user_name = match connection.user_name.clone() {
+            Some(user_name) => user_name,
+            None => {
+                connection.send_error(
+                    ErrorCode::NotLoggedIn,
+                    "Login before editing messages".to_string(),
+                    Some(Frame::from(self).number()),
+                );
+                return;
+            }
+        };
+
+        let (chat, author) = match changeable_chat(connection, &user_name, self.message_id) {
+            Ok(changeable) => changeable,
+            Err((code, reason)) => {
+                connection.send_error(code, reason, Some(Frame::from(self).number()));
+                return;
+            }
+        };
+
+        // muted users can not change what they said
+        let moderation = &connection.state.moderation;
+        let muted = match &chat {
+            Chat::Global | Chat::Private(..) => moderation.is_muted(None, &user_name),
+            Chat::Room(room) => moderation.is_muted(Some(room), &user_name),
+        };
+        if muted {
+            connection.send_error(
+                ErrorCode::Muted,
+                "You are muted".to_string(),
+                Some(Frame::from(self).number()),
+            );
+            return;
+        }
+
+        if !fits_message_size(connection, &chat, &self.message) {
+            connection.send_error(
+                ErrorCode::PayloadTooLarge,
+                "The message is too large".to_string(),
+                Some(Frame::from(self).number()),
+            );
+            return;
+        }
+
+        connection
+            .state
+            .history
+            .edit(self.message_id, self.message.clone());
+        connection
+            .state
+            .offline_queue
+            .edit(self.message_id, &author, &self.message);
+
+        let notice = MessageEditedMessage {
+            message_id: self.message_id,
+            message: self.message,
+            user_name,
+        };
+        if let Err(err) = send_to_chat(connection, chat, Frame::from(notice)) {
+            connection.report_error(err);
+        }
+    }
+}
+
+impl MessageTrait for DeleteMessage {
+    fn process(self, connection: &mut Connection) {
+        let user_name = match connection.user_name.clone() {
+            Some(user_name) => user_name,
+            None => {
+                connection.send_error(
+                    ErrorCode::NotLoggedIn,
+                    "Login before deleting messages".to_string(),
+                    Some(Frame::from(self).number()),
+                );
+                return;
+            }
+        };
+
+        let (chat, author) = match changeable_chat(connection, &user_name, self.message_id) {
+            Ok(changeable) => changeable,
+            Err((code, reason)) => {
+                connection.send_error(code, reason, Some(Frame::from(self).number()));
+                return;
+            }
+        };
+
+        connection.state.history.delete(self.message_id);
+        connection
+            .state
+            .offline_queue
+            .delete(self.message_id, &author);
+
+        let notice = MessageDeletedMessage {
+            message_id: self.message_id,
+            user_name,
+        };
+        if let Err(err) = send_to_chat(connection, chat, Frame::from(notice)) {
+            connection.report_error(err);
+        }
+    }
+}
+
+impl MessageTrait for MessageEditedMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for MessageDeletedMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for TypingMessage {
+    fn process(self, connection: &mut Connection) {
+        let user_name = match connection.user_name.clone() {
+            Some(user_name) => user_name,
+            None => {
+                connection.send_error(
+                    ErrorCode::NotLoggedIn,
+                    "Login before sending typing indicators".to_string(),
+                    Some(Frame::from(self).number()),
+                );
+                return;
+            }
+        };
+
+        // indicators are only a hint, too many are dropped without error
+        if !connection.typing_allowed() {
+            return;
+        }
+
+        let result = match &self.to {
+            Conversation::Room(room) => {
+                if !connection.rooms.contains(room) {
+                    connection.send_error(
+                        ErrorCode::NotInRoom,
+                        format!("Join room {} before typing in it", room),
+                        Some(Frame::from(self).number()),
+                    );
+                    return;
+                }
+
+                // not back to the typing user
+                let id = connection.id();
+                let mut members = connection.state.room_directory.members(room);
+                members.retain(|member| *member != id);
+
                 connection
                     .server_event_handler
-                    .private_chat_message_event(reply);
+                    .room_message_event(RoomMessageEvent {
+                        room: room.clone(),
+                        members,
+                        frame: Frame::from(UserTypingMessage {
+                            user_name,
+                            room: Some(room.clone()),
+                        }),
+                        capability: Some(Capability::Typing),
+                    })
+            }
+            Conversation::User(to_user_name) => {
+                match connection.state.user_directory.get(to_user_name) {
+                    Some(recipient) => {
+                        connection
+                            .server_event_handler
+                            .user_message_event(UserMessageEvent {
+                                recipient,
+                                user_name: to_user_name.clone(),
+                                frame: Frame::from(UserTypingMessage {
+                                    user_name,
+                                    room: None,
+                                }),
+                                capability: Some(Capability::Typing),
+                            })
+                    }
+                    None => {
+                        connection.send_error(
+                            ErrorCode::UnknownUser,
+                            format!("User {} is not logged in", to_user_name),
+                            Some(Frame::from(self).number()),
+                        );
+                        return;
+                    }
+                }
             }
-            None => {}
+        };
+
+        if let Err(err) = result {
+            connection.report_error(err);
         }
     }
+}
+
+impl MessageTrait for UserTypingMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for ReadMessage {
+    fn process(self, connection: &mut Connection) {
+        let user_name = match connection.user_name.clone() {
+            Some(user_name) => user_name,
+            None => {
+                connection.send_error(
+                    ErrorCode::NotLoggedIn,
+                    "Login before sending read receipts".to_string(),
+                    Some(Frame::from(self).number()),
+                );
+                return;
+            }
+        };
 
-    fn number(&self) -> u32 {
-        4
+        // receipts are best effort, unknown ids are ignored
+        let Some(from_user_name) = connection.read_private_message(self.message_id) else {
+            return;
+        };
+
+        // the author may have logged out in the meantime
+        if let Some(author) = connection.state.user_directory.get(&from_user_name) {
+            let event = UserMessageEvent {
+                recipient: author,
+                user_name: from_user_name,
+                frame: Frame::from(MessageReadMessage {
+                    message_id: self.message_id,
+                    user_name,
+                }),
+                capability: Some(Capability::Typing),
+            };
+            if let Err(err) = connection.server_event_handler.user_message_event(event) {
+                connection.report_error(err);
+            }
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct PrivateChatMessage {
-    pub from_user_name: String,
-    pub message: String,
+impl MessageTrait for MessageReadMessage {
+    fn process(self, _connection: &mut Connection) {}
 }
 
-impl MessageTrait for PrivateChatMessage {
+impl MessageTrait for JoinRoomMessage {
+    fn process(self, connection: &mut Connection) {
+        let user_name = match connection.user_name.clone() {
+            Some(user_name) => user_name,
+            None => {
+                connection.send_error(
+                    ErrorCode::NotLoggedIn,
+                    "Login before joining rooms".to_string(),
+                    Some(Frame::from(self).number()),
+                );
+                return;
+            }
+        };
+
+        let result = if is_valid_room_name(&self.room) {
+            connection.join_room(self.room.clone(), &user_name)
+        } else {
+            Err(RoomError::InvalidName)
+        };
+
+        let joined = result.is_ok();
+        connection.send_frame(RoomResultMessage {
+            room: self.room.clone(),
+            action: RoomAction::Join,
+            error: result.err(),
+        });
+
+        if joined {
+            let topic = connection.state.room_directory.topic(&self.room);
+            if !topic.is_empty() {
+                connection.send_frame(RoomTopicMessage {
+                    room: self.room,
+                    topic,
+                });
+            }
+        }
+    }
+}
+
+impl MessageTrait for LeaveRoomMessage {
+    fn process(self, connection: &mut Connection) {
+        if connection.user_name.is_none() {
+            connection.send_error(
+                ErrorCode::NotLoggedIn,
+                "Login before leaving rooms".to_string(),
+                Some(Frame::from(self).number()),
+            );
+            return;
+        }
+
+        let result = connection.leave_room(&self.room);
+        connection.send_frame(RoomResultMessage {
+            room: self.room,
+            action: RoomAction::Leave,
+            error: result.err(),
+        });
+    }
+}
+
+impl MessageTrait for RoomResultMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for PublishRoomMessage {
+    fn process(self, connection: &mut Connection) {
+        let user_name = match connection.user_name.clone() {
+            Some(user_name) => user_name,
+            None => {
+                connection.send_error(
+                    ErrorCode::NotLoggedIn,
+                    "Login before sending room messages".to_string(),
+                    Some(Frame::from(self).number()),
+                );
+                return;
+            }
+        };
+
+        if !connection.rooms.contains(&self.room) {
+            connection.send_error(
+                ErrorCode::NotInRoom,
+                format!("Join room {} before sending messages to it", self.room),
+                Some(Frame::from(self).number()),
+            );
+            return;
+        }
+
+        if connection
+            .state
+            .moderation
+            .is_muted(Some(&self.room), &user_name)
+        {
+            connection.send_error(
+                ErrorCode::Muted,
+                format!("You are muted in room {}", self.room),
+                Some(Frame::from(self).number()),
+            );
+            return;
+        }
+
+        if !fits_message_size(connection, &Chat::Room(self.room.clone()), &self.message) {
+            connection.send_error(
+                ErrorCode::PayloadTooLarge,
+                "The message is too large".to_string(),
+                Some(Frame::from(self).number()),
+            );
+            return;
+        }
+
+        let members = connection.state.room_directory.members(&self.room);
+        let result = connection.state.message_clock.stamp(
+            &Chat::Room(self.room.clone()),
+            |message_id, timestamp| {
+                connection.state.history.append(
+                    Chat::Room(self.room.clone()),
+                    HistoryEntry {
+                        message_id,
+                        timestamp,
+                        user_name: user_name.clone(),
+                        message: self.message.clone(),
+                        edited: false,
+                    },
+                );
+                let event = RoomMessageEvent {
+                    room: self.room.clone(),
+                    members,
+                    frame: Frame::from(RoomMessage {
+                        room: self.room,
+                        user_name,
+                        message: self.message,
+                        message_id,
+                        timestamp,
+                    }),
+                    capability: None,
+                };
+                connection.server_event_handler.room_message_event(event)
+            },
+        );
+        if let Err(err) = result {
+            connection.report_error(err);
+        }
+    }
+}
+
+impl MessageTrait for RoomMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for ListRoomsMessage {
+    fn process(self, connection: &mut Connection) {
+        let rooms = connection.state.room_directory.list();
+        connection.send_frame(RoomListMessage { rooms });
+    }
+}
+
+impl MessageTrait for RoomListMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for ModerationMessage {
+    fn process(self, connection: &mut Connection) {
+        let user_name = match connection.user_name.clone() {
+            Some(user_name) => user_name,
+            None => {
+                connection.send_error(
+                    ErrorCode::NotLoggedIn,
+                    "Login before moderating".to_string(),
+                    Some(Frame::from(self).number()),
+                );
+                return;
+            }
+        };
+
+        let result = moderate(connection, &user_name, &self);
+        connection.send_frame(ModerationResultMessage {
+            room: self.room,
+            user_name: self.user_name,
+            action: self.action,
+            error: result.err(),
+        });
+    }
+}
+
+/// Rank of the user of `connection` in `room`, a room role counts only with room and for
+/// authenticated users.
+fn rank(connection: &Connection, room: Option<&str>, user_name: &str) -> Rank {
+    if connection.global_operator {
+        return Rank::GlobalOperator;
+    }
+
+    match room {
+        Some(room) if connection.authenticated => {
+            Rank::Role(connection.state.room_directory.role(room, user_name))
+        }
+        _ => Rank::Role(Role::Member),
+    }
+}
+
+/// Sends the newest of the `messages` that fit into `max_message_size`, the client fetches the
+/// older ones with `before_id`.
+fn send_history(connection: &mut Connection, scope: HistoryScope, messages: Vec<HistoryEntry>) {
+    let max_message_size = connection.config.max_message_size;
+    let codec = connection.codec();
+    let encode = |start: usize| {
+        let history_message = HistoryMessage {
+            scope: scope.clone(),
+            messages: messages[start..].to_vec(),
+        };
+        Message::new(history_message, codec)
+    };
+
+    let mut message = encode(0);
+    if message.payload.len() > max_message_size && messages.len() > 1 {
+        // the first entry the page can start with, at least the newest one is sent
+        let (mut low, mut high) = (1, messages.len() - 1);
+        while low < high {
+            let middle = (low + high) / 2;
+            if encode(middle).payload.len() > max_message_size {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        message = encode(low);
+    }
+    connection.send_message(message);
+}
+
+/// Returns `true` if `text` fits into `max_message_size` in every frame that carries it to the
+/// users of `chat`, with each codec the server offers.
+fn fits_message_size(connection: &Connection, chat: &Chat, text: &str) -> bool {
+    // json escapes a byte to at most 6 bytes, short texts fit without encoding
+    let max_message_size = connection.config.max_message_size;
+    if text.len() * 6 + 1024 <= max_message_size {
+        return true;
+    }
+
+    // the longest names, ids and timestamps the frames can have
+    let user_name = char::MAX.to_string().repeat(MAX_USER_NAME_LENGTH);
+    let message = text.to_string();
+    let (message_id, timestamp) = (u64::MAX, u64::MAX);
+    let frame = match chat {
+        Chat::Global => Frame::from(GlobalChatMessage {
+            user_name: user_name.clone(),
+            message: message.clone(),
+            message_id,
+            timestamp,
+        }),
+        Chat::Room(room) => Frame::from(RoomMessage {
+            room: room.clone(),
+            user_name: user_name.clone(),
+            message: message.clone(),
+            message_id,
+            timestamp,
+        }),
+        Chat::Private(..) => Frame::from(PrivateChatMessage {
+            from_user_name: user_name.clone(),
+            message: message.clone(),
+            message_id,
+            timestamp,
+        }),
+    };
+
+    // replayed after the next login or fetched as one history entry
+    let scope = match chat {
+        Chat::Global => HistoryScope::Global,
+        Chat::Room(room) => HistoryScope::Room(room.clone()),
+        Chat::Private(..) => HistoryScope::User(user_name.clone()),
+    };
+    let history = Frame::from(HistoryMessage {
+        scope,
+        messages: vec![HistoryEntry {
+            message_id,
+            timestamp,
+            user_name: user_name.clone(),
+            message: message.clone(),
+            edited: true,
+        }],
+    });
+
+    // an edit may replace the message with a text of the same size
+    let edited = Frame::from(MessageEditedMessage {
+        message_id,
+        message,
+        user_name,
+    });
+
+    encodes_within_limit(connection, &frame)
+        && encodes_within_limit(connection, &history)
+        && encodes_within_limit(connection, &edited)
+}
+
+/// Returns `true` if `frame` encodes within `max_message_size` with each codec the server offers.
+fn encodes_within_limit(connection: &Connection, frame: &Frame) -> bool {
+    connection.config.codecs.iter().all(|&codec| {
+        frame
+            .encode(codec)
+            .is_ok_and(|message| message.payload.len() <= connection.config.max_message_size)
+    })
+}
+
+/// Chat and author of the message if the user may edit or delete it. Authors change their own
+/// messages, operators of the room and global operators also the public messages of others.
+fn changeable_chat(
+    connection: &Connection,
+    user_name: &str,
+    message_id: u64,
+) -> Result<(Chat, String), (ErrorCode, String)> {
+    let Some((chat, author)) = connection.state.history.find(message_id) else {
+        return Err((
+            ErrorCode::UnknownMessageId,
+            format!("Message {} not found", message_id),
+        ));
+    };
+    if author == user_name {
+        return Ok((chat, author));
+    }
+
+    let permitted = match &chat {
+        Chat::Global => rank(connection, None, user_name) >= Rank::GlobalOperator,
+        Chat::Room(room) => rank(connection, Some(room), user_name) >= Rank::Role(Role::Operator),
+        // private chats are not moderated
+        Chat::Private(..) => false,
+    };
+    if permitted {
+        Ok((chat, author))
+    } else {
+        Err((
+            ErrorCode::NotPermitted,
+            "Only the author or a moderator can change the message".to_string(),
+        ))
+    }
+}
+
+/// Sends `frame` to the users with `Capability::Edit` who received the messages of `chat`: all
+/// users, the members of the room or both users of the private chat.
+fn send_to_chat(connection: &Connection, chat: Chat, frame: Frame) -> Result<(), ServerError> {
+    let capability = Some(Capability::Edit);
+    match chat {
+        Chat::Global => connection
+            .server_event_handler
+            .broadcast_event(BroadcastEvent { frame, capability }),
+        Chat::Room(room) => {
+            let members = connection.state.room_directory.members(&room);
+            connection
+                .server_event_handler
+                .room_message_event(RoomMessageEvent {
+                    room,
+                    members,
+                    frame,
+                    capability,
+                })
+        }
+        Chat::Private(user_name, other_user_name) => {
+            for user_name in [user_name, other_user_name] {
+                // the other user may be offline
+                if let Some(recipient) = connection.state.user_directory.get(&user_name) {
+                    connection
+                        .server_event_handler
+                        .user_message_event(UserMessageEvent {
+                            recipient,
+                            user_name,
+                            frame: frame.clone(),
+                            capability,
+                        })?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Checks the ranks and applies `message`, the thread of the moderated user notifies it.
+fn moderate(
+    connection: &Connection,
+    by_user_name: &str,
+    message: &ModerationMessage,
+) -> Result<(), ModerationError> {
+    let state = &connection.state;
+    let room = message.room.as_deref();
+
+    if room.is_some_and(|room| !state.room_directory.exists(room)) {
+        return Err(ModerationError::RoomNotFound);
+    }
+
+    // operators of a room moderate members, the owner gives roles, global operators
+    // everything else
+    let required_rank = match (room, message.action) {
+        (None, _) => Rank::GlobalOperator,
+        (Some(_), ModerationAction::SetRole(Role::Owner)) => {
+            return Err(ModerationError::NotPermitted)
+        }
+        (Some(_), ModerationAction::SetRole(_)) => Rank::Role(Role::Owner),
+        (Some(_), _) => Rank::Role(Role::Operator),
+    };
+    // configured operators are protected even if not logged in
+    let target_rank = if state.moderation.is_operator(&message.user_name) {
+        Rank::GlobalOperator
+    } else {
+        match room {
+            Some(room) => Rank::Role(state.room_directory.role(room, &message.user_name)),
+            None => Rank::Role(Role::Member),
+        }
+    };
+    let own_rank = rank(connection, room, by_user_name);
+    if own_rank < required_rank || own_rank <= target_rank {
+        return Err(ModerationError::NotPermitted);
+    }
+
+    let target = state.user_directory.get(&message.user_name);
+    let in_room = |target| room.is_none_or(|room| state.room_directory.is_member(room, target));
+
+    match message.action {
+        ModerationAction::Kick => match target {
+            Some(target) if in_room(target) => {}
+            Some(_) => return Err(ModerationError::NotMember),
+            None => return Err(ModerationError::UserNotFound),
+        },
+        ModerationAction::Ban => state.moderation.ban(
+            message.room.clone(),
+            message.user_name.clone(),
+            state.user_directory.address(&message.user_name),
+        ),
+        ModerationAction::Unban => state.moderation.unban(room, &message.user_name),
+        ModerationAction::Mute => state
+            .moderation
+            .mute(message.room.clone(), message.user_name.clone()),
+        ModerationAction::Unmute => state
+            .moderation
+            .unmute(message.room.clone(), message.user_name.clone()),
+        ModerationAction::SetRole(role) => {
+            if let Some(room) = room {
+                state
+                    .room_directory
+                    .set_role(room, &message.user_name, role);
+            }
+        }
+    }
+
+    if let Some(target) = target {
+        let event = ModerationEvent {
+            target,
+            user_name: message.user_name.clone(),
+            notice: ModeratedMessage {
+                room: message.room.clone(),
+                action: message.action,
+                by_user_name: by_user_name.to_string(),
+            },
+        };
+        if let Err(err) = connection.server_event_handler.moderation_event(event) {
+            connection.report_error(err);
+        }
+    }
+
+    Ok(())
+}
+
+impl MessageTrait for ModerationResultMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for ModeratedMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for SetTopicMessage {
+    fn process(self, connection: &mut Connection) {
+        let user_name = match connection.user_name.clone() {
+            Some(user_name) => user_name,
+            None => {
+                connection.send_error(
+                    ErrorCode::NotLoggedIn,
+                    "Login before setting topics".to_string(),
+                    Some(Frame::from(self).number()),
+                );
+                return;
+            }
+        };
+
+        if rank(connection, Some(&self.room), &user_name) < Rank::Role(Role::Operator) {
+            connection.send_error(
+                ErrorCode::NotPermitted,
+                format!("Only operators can set the topic of room {}", self.room),
+                Some(Frame::from(self).number()),
+            );
+            return;
+        }
+
+        if self.topic.chars().count() > MAX_TOPIC_LENGTH {
+            connection.send_error(
+                ErrorCode::InvalidPayload,
+                format!("Topics have at most {} characters", MAX_TOPIC_LENGTH),
+                Some(Frame::from(self).number()),
+            );
+            return;
+        }
+
+        let state = &connection.state;
+        if !state
+            .room_directory
+            .set_topic(&self.room, self.topic.clone())
+        {
+            connection.send_error(
+                ErrorCode::NotInRoom,
+                format!("Room {} does not exist", self.room),
+                Some(Frame::from(self).number()),
+            );
+            return;
+        }
+
+        let event = RoomMessageEvent {
+            room: self.room.clone(),
+            members: state.room_directory.members(&self.room),
+            frame: Frame::from(RoomTopicMessage {
+                room: self.room,
+                topic: self.topic,
+            }),
+            capability: None,
+        };
+        if let Err(err) = connection.server_event_handler.room_message_event(event) {
+            connection.report_error(err);
+        }
+    }
+}
+
+impl MessageTrait for RoomTopicMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for ErrorMessage {
     fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for HelloMessage {
+    fn process(self, connection: &mut Connection) {
+        if !connection.hello_allowed {
+            connection.send_error(
+                ErrorCode::InvalidHandshake,
+                "Hello is only allowed as first message".to_string(),
+                Some(Frame::from(self).number()),
+            );
+            return;
+        }
+
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&self.version) {
+            connection.send_error(
+                ErrorCode::IncompatibleVersion,
+                format!(
+                    "Protocol version {} is not supported, supported versions are {} to {}",
+                    self.version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+                ),
+                Some(Frame::from(self).number()),
+            );
+            connection.close();
+            return;
+        }
 
-    fn number(&self) -> u32 {
-        5
+        println!(
+            "Client {} connected with protocol version {}.",
+            self.client_name, self.version
+        );
+
+        // the server supports all framings, so the client preference wins
+        let framing = self.framings.first().copied().unwrap_or(Framing::Ascii);
+        // first codec of the client the server config allows
+        let codec = self
+            .codecs
+            .into_iter()
+            .find(|codec| connection.config.codecs.contains(codec))
+            .unwrap_or(Codec::Json);
+
+        let mut capabilities: Vec<Capability> = self
+            .capabilities
+            .into_iter()
+            .filter(|capability| SERVER_CAPABILITIES.contains(capability))
+            .collect();
+
+        // compression needs the flags of the binary framing
+        let compression = match framing {
+            Framing::Binary if capabilities.contains(&Capability::Compression) => self
+                .compressions
+                .into_iter()
+                .find(|compression| connection.config.compressions.contains(compression)),
+            _ => None,
+        };
+        if compression.is_none() {
+            capabilities.retain(|capability| *capability != Capability::Compression);
+        }
+
+        let welcome_message = WelcomeMessage {
+            version: PROTOCOL_VERSION,
+            server_name: concat!("rust_chat ", env!("CARGO_PKG_VERSION")).to_string(),
+            capabilities,
+            framing,
+            codec,
+            compression,
+            compression_threshold: connection.config.compression_threshold,
+        };
+        connection.send_frame(welcome_message.clone());
+        connection.apply_welcome(&welcome_message);
     }
 }
+
+impl MessageTrait for WelcomeMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
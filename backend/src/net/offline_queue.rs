@@ -0,0 +1,344 @@
+use crate::net::{
+    config::ServerConfig,
+    error::ServerError,
+    message_clock::unix_millis,
+    record_file::RecordFile,
+    user_directory::{ConnectionId, UserDirectory},
+};
+use rust_chat_protocol::messages::PrivateChatMessage;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+/// One line of the offline messages file, changes refer to a message of a line before.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Message {
+        to_user_name: String,
+        #[serde(flatten)]
+        message: PrivateChatMessage,
+    },
+    Edit {
+        edited_id: u64,
+        from_user_name: String,
+        message: String,
+    },
+    Delete {
+        deleted_id: u64,
+        from_user_name: String,
+    },
+    /// All queued messages of the user were delivered.
+    Delivered { delivered_to: String },
+}
+
+type Queues = HashMap<String, VecDeque<PrivateChatMessage>>;
+
+/// Result of `OfflineQueue::defer`.
+pub enum Deferral {
+    Deferred,
+    /// The user logged in in the meantime, the message was not queued.
+    Online(ConnectionId),
+    /// The user has `ServerConfig::max_offline_messages` queued messages.
+    QueueFull,
+}
+
+/// Private messages for users that are not logged in, shared by all threads. They are delivered
+/// with the next login of the user.
+///
+/// The records of `ServerConfig::offline_messages_path` are compacted with each start, messages
+/// older than `ServerConfig::offline_message_lifetime` are dropped.
+pub struct OfflineQueue {
+    config: Arc<ServerConfig>,
+    /// Recipient -> messages, the oldest first.
+    queues: Mutex<(Queues, RecordFile)>,
+    /// Id and timestamp of the newest message of the file, also if it expired.
+    last_stamp: (u64, u64),
+}
+
+impl OfflineQueue {
+    pub fn load(config: Arc<ServerConfig>) -> Result<Self, ServerError> {
+        let mut queues = Queues::new();
+        let mut last_stamp = (0, 0);
+        let mut file = RecordFile::open(
+            "offline messages",
+            config.offline_messages_path.as_deref(),
+            |record| {
+                if let Record::Message { message, .. } = &record {
+                    last_stamp = last_stamp.max((message.message_id, message.timestamp));
+                }
+                apply(&mut queues, record);
+            },
+        )
+        .map_err(ServerError::OfflineStore)?;
+
+        expire(&mut queues, &config);
+        file.compact(queues.iter().flat_map(|(to_user_name, queue)| {
+            queue.iter().map(|message| Record::Message {
+                to_user_name: to_user_name.clone(),
+                message: message.clone(),
+            })
+        }));
+
+        Ok(Self {
+            config,
+            queues: Mutex::new((queues, file)),
+            last_stamp,
+        })
+    }
+
+    /// Id and timestamp of the newest loaded message, zero without messages.
+    pub fn last_stamp(&self) -> (u64, u64) {
+        self.last_stamp
+    }
+
+    /// Queues the message if the user is still not logged in. Checked under the lock of the
+    /// queues, a login can not miss the message, see `take`.
+    pub fn defer(
+        &self,
+        user_directory: &UserDirectory,
+        to_user_name: &str,
+        message: PrivateChatMessage,
+    ) -> Deferral {
+        let (queues, file) = &mut *self.queues.lock().unwrap();
+        if let Some(recipient) = user_directory.get(to_user_name) {
+            return Deferral::Online(recipient);
+        }
+
+        expire(queues, &self.config);
+        let queued = queues.get(to_user_name).map_or(0, VecDeque::len);
+        if queued >= self.config.max_offline_messages {
+            return Deferral::QueueFull;
+        }
+
+        store(
+            queues,
+            file,
+            Record::Message {
+                to_user_name: to_user_name.to_string(),
+                message,
+            },
+        );
+        Deferral::Deferred
+    }
+
+    /// Removes the queued messages of the user, the oldest first. The user has to be in the
+    /// `UserDirectory` already.
+    pub fn take(&self, user_name: &str) -> Vec<PrivateChatMessage> {
+        let (queues, file) = &mut *self.queues.lock().unwrap();
+        expire(queues, &self.config);
+
+        match queues.remove(user_name) {
+            Some(queue) => {
+                file.append(&Record::Delivered {
+                    delivered_to: user_name.to_string(),
+                });
+                queue.into()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Replaces the text of the message of `from_user_name` if it is still queued.
+    pub fn edit(&self, message_id: u64, from_user_name: &str, message: &str) {
+        let (queues, file) = &mut *self.queues.lock().unwrap();
+        if is_queued(queues, message_id, from_user_name) {
+            let record = Record::Edit {
+                edited_id: message_id,
+                from_user_name: from_user_name.to_string(),
+                message: message.to_string(),
+            };
+            store(queues, file, record);
+        }
+    }
+
+    /// Removes the message of `from_user_name` if it is still queued.
+    pub fn delete(&self, message_id: u64, from_user_name: &str) {
+        let (queues, file) = &mut *self.queues.lock().unwrap();
+        if is_queued(queues, message_id, from_user_name) {
+            let record = Record::Delete {
+                deleted_id: message_id,
+                from_user_name: from_user_name.to_string(),
+            };
+            store(queues, file, record);
+        }
+    }
+}
+
+fn store(queues: &mut Queues, file: &mut RecordFile, record: Record) {
+    file.append(&record);
+    apply(queues, record);
+}
+
+fn apply(queues: &mut Queues, record: Record) {
+    match record {
+        Record::Message {
+            to_user_name,
+            message,
+        } => queues.entry(to_user_name).or_default().push_back(message),
+        Record::Edit {
+            edited_id,
+            from_user_name,
+            message,
+        } => {
+            let queued = queues
+                .values_mut()
+                .flatten()
+                .find(|queued| is_message(queued, edited_id, &from_user_name));
+            if let Some(queued) = queued {
+                queued.message = message;
+            }
+        }
+        Record::Delete {
+            deleted_id,
+            from_user_name,
+        } => {
+            for queue in queues.values_mut() {
+                queue.retain(|queued| !is_message(queued, deleted_id, &from_user_name));
+            }
+            queues.retain(|_, queue| !queue.is_empty());
+        }
+        Record::Delivered { delivered_to } => {
+            queues.remove(&delivered_to);
+        }
+    }
+}
+
+fn expire(queues: &mut Queues, config: &ServerConfig) {
+    let now = unix_millis();
+    let lifetime = config.offline_message_lifetime.as_millis() as u64;
+    for queue in queues.values_mut() {
+        queue.retain(|message| now.saturating_sub(message.timestamp) < lifetime);
+    }
+    queues.retain(|_, queue| !queue.is_empty());
+}
+
+fn is_queued(queues: &Queues, message_id: u64, from_user_name: &str) -> bool {
+    queues
+        .values()
+        .flatten()
+        .any(|queued| is_message(queued, message_id, from_user_name))
+}
+
+/// Ids are only unique together with the author, the ids of a lost history are used again.
+fn is_message(queued: &PrivateChatMessage, message_id: u64, from_user_name: &str) -> bool {
+    queued.message_id == message_id && queued.from_user_name == from_user_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::record_file::test_path;
+    use mio::Token;
+    use std::{
+        fs,
+        net::{IpAddr, Ipv4Addr},
+        time::Duration,
+    };
+
+    fn load(config: ServerConfig) -> OfflineQueue {
+        OfflineQueue::load(Arc::new(config)).unwrap()
+    }
+
+    fn message(from_user_name: &str, message_id: u64, timestamp: u64) -> PrivateChatMessage {
+        PrivateChatMessage {
+            from_user_name: from_user_name.to_string(),
+            message: format!("message {}", message_id),
+            message_id,
+            timestamp,
+        }
+    }
+
+    fn texts(messages: &[PrivateChatMessage]) -> Vec<&str> {
+        messages
+            .iter()
+            .map(|message| message.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn messages_wait_for_offline_users() {
+        let queue = load(ServerConfig {
+            max_offline_messages: 2,
+            ..ServerConfig::default()
+        });
+        let users = UserDirectory::new();
+        let now = unix_millis();
+        for message_id in 1..=2 {
+            let deferral = queue.defer(&users, "bob", message("alice", message_id, now));
+            assert!(matches!(deferral, Deferral::Deferred));
+        }
+        let deferral = queue.defer(&users, "bob", message("alice", 3, now));
+        assert!(matches!(deferral, Deferral::QueueFull));
+
+        let bob = ConnectionId {
+            thread: 0,
+            token: Token(1),
+        };
+        users.insert("bob".to_string(), bob, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let deferral = queue.defer(&users, "bob", message("alice", 4, now));
+        assert!(matches!(deferral, Deferral::Online(id) if id == bob));
+
+        assert_eq!(texts(&queue.take("bob")), ["message 1", "message 2"]);
+        assert!(queue.take("bob").is_empty());
+    }
+
+    #[test]
+    fn edits_and_deletes_need_the_author() {
+        let queue = load(ServerConfig::default());
+        let users = UserDirectory::new();
+        let now = unix_millis();
+        queue.defer(&users, "bob", message("alice", 1, now));
+        queue.defer(&users, "bob", message("carol", 1, now));
+        queue.defer(&users, "bob", message("alice", 2, now));
+
+        queue.edit(1, "carol", "edited");
+        queue.delete(2, "carol");
+        queue.delete(2, "alice");
+        assert_eq!(texts(&queue.take("bob")), ["message 1", "edited"]);
+    }
+
+    #[test]
+    fn old_messages_expire() {
+        let queue = load(ServerConfig {
+            offline_message_lifetime: Duration::from_secs(60),
+            ..ServerConfig::default()
+        });
+        let users = UserDirectory::new();
+        let now = unix_millis();
+        queue.defer(&users, "bob", message("alice", 1, now - 61_000));
+        queue.defer(&users, "bob", message("alice", 2, now));
+        assert_eq!(texts(&queue.take("bob")), ["message 2"]);
+    }
+
+    #[test]
+    fn messages_are_saved() {
+        let path = test_path("offline_messages");
+        let config = || ServerConfig {
+            offline_messages_path: Some(path.clone()),
+            ..ServerConfig::default()
+        };
+        let queue = load(config());
+        let users = UserDirectory::new();
+        let now = unix_millis();
+        queue.defer(&users, "bob", message("alice", 1, now));
+        queue.defer(&users, "bob", message("alice", 2, now));
+        queue.defer(&users, "carol", message("alice", 3, now));
+        queue.defer(&users, "dave", message("alice", 4, now + 1));
+        queue.edit(1, "alice", "edited");
+        queue.delete(2, "alice");
+        queue.take("carol");
+        drop(queue);
+
+        // loaded twice, the second time from the compacted file
+        for _ in 0..2 {
+            let queue = load(config());
+            assert_eq!(queue.last_stamp(), (4, now + 1));
+            assert!(queue.take("carol").is_empty());
+        }
+        assert_eq!(texts(&load(config()).take("bob")), ["edited"]);
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,109 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Append only file of JSON records, one per line, for the persistent stores of the server.
+///
+/// Changes are appended as records and applied again on the next load, a missing file has no
+/// records. Write errors are only logged, the change stays in memory.
+pub struct RecordFile {
+    /// Name of the store in log messages.
+    name: &'static str,
+    path: Option<PathBuf>,
+    /// `None` keeps the records in memory only.
+    file: Option<File>,
+}
+
+impl RecordFile {
+    /// Passes the records of `path` to `apply` and opens the file for appending. An unreadable
+    /// line, e.g. cut off by a crash, loses only its record.
+    pub fn open<R: DeserializeOwned>(
+        name: &'static str,
+        path: Option<&Path>,
+        mut apply: impl FnMut(R),
+    ) -> io::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self {
+                name,
+                path: None,
+                file: None,
+            });
+        };
+
+        match File::open(path) {
+            Ok(file) => {
+                // lines are read as bytes, invalid UTF-8 is such an unreadable line
+                for (number, line) in BufReader::new(file).split(b'\n').enumerate() {
+                    match serde_json::from_slice(&line?) {
+                        Ok(record) => apply(record),
+                        Err(err) => {
+                            eprintln!("Skipping line {} of the {}: {}", number + 1, name, err)
+                        }
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            name,
+            path: Some(path.to_path_buf()),
+            file: Some(file),
+        })
+    }
+
+    /// Appends `record` with one write, a crash cuts off at most the last line.
+    pub fn append<R: Serialize>(&mut self, record: &R) {
+        if let Some(file) = &mut self.file {
+            let result = serde_json::to_string(record)
+                .map_err(io::Error::from)
+                .and_then(|line| file.write_all(format!("{}\n", line).as_bytes()));
+            if let Err(err) = result {
+                eprintln!("Error while saving {}: {}", self.name, err);
+            }
+        }
+    }
+
+    /// Replaces the file with `records`, the state after the applied changes.
+    pub fn compact<R: Serialize>(&mut self, records: impl IntoIterator<Item = R>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        // a crash while writing keeps the old file
+        let compacted_path = path.with_extension("compacted");
+        let result = File::create(&compacted_path)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                for record in records {
+                    serde_json::to_writer(&mut writer, &record)?;
+                    writeln!(writer)?;
+                }
+                writer.flush()
+            })
+            .and_then(|_| fs::rename(&compacted_path, path))
+            .and_then(|_| OpenOptions::new().append(true).open(path));
+
+        match result {
+            Ok(file) => self.file = Some(file),
+            Err(err) => eprintln!("Error while compacting {}: {}", self.name, err),
+        }
+    }
+}
+
+/// A path in the temp directory, unique per test and process, without file.
+#[cfg(test)]
+pub fn test_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "rust_chat_unit_{}_{}.jsonl",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_file(&path);
+    path
+}
@@ -0,0 +1,318 @@
+use crate::net::{
+    config::ServerConfig, error::ServerError, record_file::RecordFile, user_directory::ConnectionId,
+};
+use rust_chat_protocol::messages::{Role, RoomError, RoomInfo};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+#[derive(Default)]
+struct Room {
+    members: HashSet<ConnectionId>,
+    /// User name -> role, users without entry are members. Roles stay if a user leaves.
+    roles: HashMap<String, Role>,
+    topic: String,
+}
+
+/// One line of the rooms file, the last role or topic of a room counts.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Role {
+        room: String,
+        user_name: String,
+        role: Role,
+    },
+    Topic {
+        room: String,
+        topic: String,
+    },
+}
+
+/// Maps the names of rooms to the connections of their members, shared by all threads.
+///
+/// Rooms are created with the first join and removed with the last leave, unless a user has a
+/// role in it. Such a room keeps roles and topic while it is empty, so the next user joining it
+/// does not become its owner. Roles and topics are saved to `ServerConfig::rooms_path`, its
+/// records are compacted with each start.
+pub struct RoomDirectory {
+    rooms: RwLock<(HashMap<String, Room>, RecordFile)>,
+}
+
+impl RoomDirectory {
+    pub fn load(config: &ServerConfig) -> Result<Self, ServerError> {
+        let mut rooms = HashMap::new();
+        let mut file = RecordFile::open("rooms", config.rooms_path.as_deref(), |record| {
+            apply(&mut rooms, record)
+        })
+        .map_err(ServerError::RoomStore)?;
+
+        // rooms without roles end with their last member, the server restarted without any
+        rooms.retain(|_, room: &mut Room| !room.roles.is_empty());
+        file.compact(rooms.iter().flat_map(|(name, room)| {
+            let roles = room.roles.iter().map(|(user_name, &role)| Record::Role {
+                room: name.clone(),
+                user_name: user_name.clone(),
+                role,
+            });
+            let topic = (!room.topic.is_empty()).then(|| Record::Topic {
+                room: name.clone(),
+                topic: room.topic.clone(),
+            });
+            roles.chain(topic)
+        }));
+
+        Ok(Self {
+            rooms: RwLock::new((rooms, file)),
+        })
+    }
+
+    /// The `owner` creating the room gets `Role::Owner`, rooms created by guests have none.
+    pub fn join(
+        &self,
+        room: &str,
+        owner: Option<&str>,
+        connection_id: ConnectionId,
+    ) -> Result<(), RoomError> {
+        let (rooms, file) = &mut *self.rooms.write().unwrap();
+        if !rooms.contains_key(room) {
+            if let Some(owner) = owner {
+                let record = Record::Role {
+                    room: room.to_string(),
+                    user_name: owner.to_string(),
+                    role: Role::Owner,
+                };
+                file.append(&record);
+                apply(rooms, record);
+            }
+        }
+
+        let room = rooms.entry(room.to_string()).or_default();
+        if room.members.insert(connection_id) {
+            Ok(())
+        } else {
+            Err(RoomError::AlreadyMember)
+        }
+    }
+
+    pub fn leave(&self, room: &str, connection_id: ConnectionId) -> Result<(), RoomError> {
+        let rooms = &mut self.rooms.write().unwrap().0;
+        let entry = rooms.get_mut(room).ok_or(RoomError::NotMember)?;
+        if !entry.members.remove(&connection_id) {
+            return Err(RoomError::NotMember);
+        }
+
+        if entry.members.is_empty() && entry.roles.is_empty() {
+            rooms.remove(room);
+        }
+        Ok(())
+    }
+
+    pub fn exists(&self, room: &str) -> bool {
+        self.rooms.read().unwrap().0.contains_key(room)
+    }
+
+    pub fn is_member(&self, room: &str, connection_id: ConnectionId) -> bool {
+        self.rooms
+            .read()
+            .unwrap()
+            .0
+            .get(room)
+            .is_some_and(|room| room.members.contains(&connection_id))
+    }
+
+    /// Connections of all members of `room`, empty if the room does not exist.
+    pub fn members(&self, room: &str) -> Vec<ConnectionId> {
+        self.rooms
+            .read()
+            .unwrap()
+            .0
+            .get(room)
+            .map(|room| room.members.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// `Role::Member` if the user has no role or the room does not exist.
+    pub fn role(&self, room: &str, user_name: &str) -> Role {
+        self.rooms
+            .read()
+            .unwrap()
+            .0
+            .get(room)
+            .and_then(|room| room.roles.get(user_name).copied())
+            .unwrap_or(Role::Member)
+    }
+
+    /// Returns `false` if the room does not exist.
+    pub fn set_role(&self, room: &str, user_name: &str, role: Role) -> bool {
+        self.store(Record::Role {
+            room: room.to_string(),
+            user_name: user_name.to_string(),
+            role,
+        })
+    }
+
+    /// Empty if the room has no topic or does not exist.
+    pub fn topic(&self, room: &str) -> String {
+        self.rooms
+            .read()
+            .unwrap()
+            .0
+            .get(room)
+            .map(|room| room.topic.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns `false` if the room does not exist.
+    pub fn set_topic(&self, room: &str, topic: String) -> bool {
+        self.store(Record::Topic {
+            room: room.to_string(),
+            topic,
+        })
+    }
+
+    /// Returns `false` if the room of `record` does not exist.
+    fn store(&self, record: Record) -> bool {
+        let (rooms, file) = &mut *self.rooms.write().unwrap();
+        let room = match &record {
+            Record::Role { room, .. } | Record::Topic { room, .. } => room.clone(),
+        };
+        if !rooms.contains_key(&room) {
+            return false;
+        }
+
+        file.append(&record);
+        apply(rooms, record);
+        if rooms[&room].members.is_empty() && rooms[&room].roles.is_empty() {
+            rooms.remove(&room);
+        }
+        true
+    }
+
+    /// All rooms sorted by name.
+    pub fn list(&self) -> Vec<RoomInfo> {
+        let mut rooms: Vec<RoomInfo> = self
+            .rooms
+            .read()
+            .unwrap()
+            .0
+            .iter()
+            .map(|(name, room)| RoomInfo {
+                name: name.clone(),
+                members: room.members.len(),
+            })
+            .collect();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        rooms
+    }
+}
+
+fn apply(rooms: &mut HashMap<String, Room>, record: Record) {
+    match record {
+        Record::Role {
+            room,
+            user_name,
+            role: Role::Member,
+        } => {
+            if let Some(room) = rooms.get_mut(&room) {
+                room.roles.remove(&user_name);
+            }
+        }
+        Record::Role {
+            room,
+            user_name,
+            role,
+        } => {
+            rooms.entry(room).or_default().roles.insert(user_name, role);
+        }
+        Record::Topic { room, topic } => rooms.entry(room).or_default().topic = topic,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::record_file::test_path;
+    use mio::Token;
+    use std::fs;
+
+    fn connection(token: usize) -> ConnectionId {
+        ConnectionId {
+            thread: 0,
+            token: Token(token),
+        }
+    }
+
+    #[test]
+    fn owner_keeps_empty_room() {
+        let rooms = RoomDirectory::load(&ServerConfig::default()).unwrap();
+        rooms.join("rust", Some("alice"), connection(1)).unwrap();
+        rooms.leave("rust", connection(1)).unwrap();
+
+        // the next user joining is no owner
+        assert!(rooms.exists("rust"));
+        rooms.join("rust", Some("bob"), connection(2)).unwrap();
+        assert_eq!(rooms.role("rust", "bob"), Role::Member);
+        assert_eq!(rooms.role("rust", "alice"), Role::Owner);
+    }
+
+    #[test]
+    fn room_without_roles_ends_with_last_member() {
+        let rooms = RoomDirectory::load(&ServerConfig::default()).unwrap();
+        rooms.join("guests", None, connection(1)).unwrap();
+        rooms.join("guests", None, connection(2)).unwrap();
+        assert_eq!(
+            rooms.join("guests", None, connection(2)),
+            Err(RoomError::AlreadyMember)
+        );
+
+        rooms.leave("guests", connection(1)).unwrap();
+        assert!(rooms.exists("guests"));
+        rooms.leave("guests", connection(2)).unwrap();
+        assert!(!rooms.exists("guests"));
+        assert!(!rooms.set_topic("guests", "topic".to_string()));
+        assert_eq!(
+            rooms.leave("guests", connection(2)),
+            Err(RoomError::NotMember)
+        );
+    }
+
+    #[test]
+    fn last_role_removed_from_empty_room() {
+        let rooms = RoomDirectory::load(&ServerConfig::default()).unwrap();
+        rooms.join("rust", Some("alice"), connection(1)).unwrap();
+        rooms.leave("rust", connection(1)).unwrap();
+
+        assert!(rooms.set_role("rust", "alice", Role::Member));
+        assert!(!rooms.exists("rust"));
+    }
+
+    #[test]
+    fn roles_and_topics_are_saved() {
+        let config = ServerConfig {
+            rooms_path: Some(test_path("rooms")),
+            ..ServerConfig::default()
+        };
+        let rooms = RoomDirectory::load(&config).unwrap();
+        rooms.join("rust", Some("alice"), connection(1)).unwrap();
+        rooms.join("rust", None, connection(2)).unwrap();
+        assert!(rooms.set_role("rust", "bob", Role::Operator));
+        assert!(rooms.set_topic("rust", "crabs".to_string()));
+        rooms.join("guests", None, connection(1)).unwrap();
+        assert!(rooms.set_topic("guests", "anyone".to_string()));
+        drop(rooms);
+
+        // loaded twice, the second time from the compacted file
+        for _ in 0..2 {
+            let rooms = RoomDirectory::load(&config).unwrap();
+            assert_eq!(rooms.role("rust", "alice"), Role::Owner);
+            assert_eq!(rooms.role("rust", "bob"), Role::Operator);
+            assert_eq!(rooms.topic("rust"), "crabs");
+            assert!(rooms.members("rust").is_empty());
+            assert!(!rooms.exists("guests"));
+        }
+        let _ = fs::remove_file(config.rooms_path.unwrap());
+    }
+}
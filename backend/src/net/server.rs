@@ -1,13 +1,24 @@
 use crate::net::{
+    auth::Authenticator,
+    config::ServerConfig,
     connection_thread::ConnectionThread,
-    event::EventHandler,
+    error::ServerError,
+    event::{EventHandler, RoomMessageEvent},
+    history::History,
+    message_clock::MessageClock,
+    moderation::Moderation,
     monitoring::Monitoring,
+    offline_queue::OfflineQueue,
+    room_directory::RoomDirectory,
     server_stop::{ServerStop, ServerThreadStop},
+    state::ServerState,
+    transport::load_tls_config,
+    user_directory::UserDirectory,
 };
 use mio::{net::TcpListener, Events, Interest, Poll, Token, Waker};
 use std::{
+    collections::HashMap,
     io,
-    net::SocketAddr,
     sync::{Arc, Mutex},
     thread::{self, JoinHandle},
     time::Duration,
@@ -24,31 +35,60 @@ pub struct Server {
 }
 
 impl Server {
-    pub fn new(connection_thread_amount: usize, address: SocketAddr) -> Self {
+    /// Binds the server socket and starts the main and the connection threads.
+    pub fn bind(config: ServerConfig) -> Result<Self, ServerError> {
+        let config = Arc::new(config);
+        let connection_thread_amount = config.connection_thread_amount;
+
         // Setup the TCP server socket.
-        let mut server_socket =
-            TcpListener::bind(address).expect("Error while creating server socket!");
+        let mut server_socket = TcpListener::bind(config.address)
+            .map_err(|err| ServerError::Bind(config.address, err))?;
 
         // Create a poll instance.
-        let mut poll = Poll::new().expect("Error while creating poll!");
+        let mut poll = Poll::new().map_err(ServerError::Poll)?;
 
         // Register the server with poll we can receive events for it.
         poll.registry()
             .register(&mut server_socket, SERVER_SOCKET_TOKEN, Interest::READABLE)
-            .expect("Error while registering server socket!");
+            .map_err(ServerError::Registry)?;
 
         // Create storage for events.
         let mut events = Events::with_capacity(64);
 
         // Create waker broadcast instance.
         let waker = Arc::new(
-            Waker::new(poll.registry(), WAKER_TOKEN_BROADCAST)
-                .expect("Error while creating waker!"),
+            Waker::new(poll.registry(), WAKER_TOKEN_BROADCAST).map_err(ServerError::Waker)?,
         );
 
         // create event handler
-        let (event_handler, global_chat_message_receiver, private_chat_message_event_receiver) =
-            EventHandler::new(waker);
+        let (
+            event_handler,
+            global_chat_message_receiver,
+            broadcast_event_receiver,
+            private_chat_message_event_receiver,
+            private_chat_result_event_receiver,
+            room_message_event_receiver,
+            user_message_event_receiver,
+            moderation_event_receiver,
+            login_event_receiver,
+            user_joined_receiver,
+            user_left_receiver,
+        ) = EventHandler::new(waker);
+
+        // ids go on after the stored messages, queued ones may not be in the history
+        let history = History::load(Arc::clone(&config))?;
+        let offline_queue = OfflineQueue::load(Arc::clone(&config))?;
+        let message_clock = MessageClock::new(history.last_stamp().max(offline_queue.last_stamp()));
+        let state = Arc::new(ServerState {
+            user_directory: UserDirectory::new(),
+            room_directory: RoomDirectory::load(&config)?,
+            authenticator: Authenticator::load(Arc::clone(&config))?,
+            moderation: Moderation::load(Arc::clone(&config))?,
+            history,
+            message_clock,
+            offline_queue,
+            tls_config: config.tls.as_ref().map(load_tls_config).transpose()?,
+        });
 
         let duration = Some(Duration::from_millis(500));
 
@@ -66,18 +106,31 @@ impl Server {
         let monitoring_stats = monitoring.get_new_stats();
 
         // create connection threads
-        let connection_threads = Arc::new(Mutex::new(Vec::new()));
-        let mut connection_threads_guard = connection_threads.lock().unwrap();
+        let mut started_connection_threads = Vec::new();
         for i in 0..connection_thread_amount {
-            let connection_thread = ConnectionThread::new(
+            match ConnectionThread::new(
                 format!("Thread-{}", i),
+                i,
                 event_handler.clone(),
                 monitoring.get_new_stats(),
-            );
-            server_thread_stops.push(connection_thread.get_server_thread_stop());
-            connection_threads_guard.push(connection_thread);
+                Arc::clone(&config),
+                Arc::clone(&state),
+            ) {
+                Ok(connection_thread) => {
+                    server_thread_stops.push(connection_thread.get_server_thread_stop());
+                    started_connection_threads.push(connection_thread);
+                }
+                Err(err) => {
+                    // stop the already started threads
+                    ServerStop::new(server_thread_stops).stop();
+                    for connection_thread in started_connection_threads {
+                        connection_thread.join();
+                    }
+                    return Err(err);
+                }
+            }
         }
-        drop(connection_threads_guard);
+        let connection_threads = Arc::new(Mutex::new(started_connection_threads));
 
         let server_socket_thread_handle = thread::Builder::new()
             .name(MAIN_THREAD_NAME.to_string())
@@ -115,19 +168,33 @@ impl Server {
                                             // more.
                                             break;
                                         }
+                                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                                            continue;
+                                        }
                                         Err(e) => {
-                                            // If it was any other kind of error, something went
-                                            // wrong and we terminate with an error.
+                                            // Any other error (e.g. too many open files) only
+                                            // affects this connection, we try again with the
+                                            // next event.
                                             eprintln!(
-                                                "[{}] Unexpected error: {}",
+                                                "[{}] Error while accepting connection: {}",
                                                 MAIN_THREAD_NAME, e
                                             );
-                                            return;
+                                            monitoring_stats.error();
+                                            break;
                                         }
                                     };
 
                                     monitoring_stats.new_connection();
 
+                                    if state.moderation.is_banned(None, None, address.ip()) {
+                                        println!(
+                                            "[{}] Rejected banned address: {}",
+                                            MAIN_THREAD_NAME, address
+                                        );
+                                        monitoring_stats.lost_connection();
+                                        continue;
+                                    }
+
                                     println!(
                                         "[{}] Accepted connection from: {} and moved to Thread: {}",
                                         MAIN_THREAD_NAME, address, next_thread
@@ -135,11 +202,19 @@ impl Server {
 
                                     let connection_threads = connection_threads.lock().unwrap();
 
-                                    match connection_threads.get(next_thread) {
-                                        Some(connection_thread) => {
-                                            connection_thread.add_connection(connection);
+                                    if let Some(connection_thread) =
+                                        connection_threads.get(next_thread)
+                                    {
+                                        if let Err(err) = connection_thread
+                                            .add_connection(connection, address.ip())
+                                        {
+                                            eprintln!(
+                                                "[{}] Error while adding connection: {}",
+                                                MAIN_THREAD_NAME, err
+                                            );
+                                            monitoring_stats.error();
+                                            monitoring_stats.lost_connection();
                                         }
-                                        None => (),
                                     }
 
                                     next_thread += 1;
@@ -160,25 +235,198 @@ impl Server {
                                     {
                                         for connection_thread in connection_threads_guard.iter_mut()
                                         {
-                                            connection_thread
+                                            if let Err(err) = connection_thread
                                                 .event_handler
                                                 .broadcast_global_chat_message(
                                                     global_chat_message.clone(),
+                                                )
+                                            {
+                                                eprintln!(
+                                                    "[{}] Error while broadcasting: {}",
+                                                    MAIN_THREAD_NAME, err
+                                                );
+                                                monitoring_stats.error();
+                                            }
+                                        }
+                                    }
+
+                                    for broadcast_event in broadcast_event_receiver.try_iter() {
+                                        for connection_thread in connection_threads_guard.iter() {
+                                            if let Err(err) = connection_thread
+                                                .event_handler
+                                                .broadcast_event(broadcast_event.clone())
+                                            {
+                                                eprintln!(
+                                                    "[{}] Error while broadcasting: {}",
+                                                    MAIN_THREAD_NAME, err
+                                                );
+                                                monitoring_stats.error();
+                                            }
+                                        }
+                                    }
+
+                                    // presence goes to all threads like global chat messages
+                                    for user_joined_message in user_joined_receiver.try_iter() {
+                                        for connection_thread in connection_threads_guard.iter() {
+                                            if let Err(err) = connection_thread
+                                                .event_handler
+                                                .broadcast_user_joined(user_joined_message.clone())
+                                            {
+                                                eprintln!(
+                                                    "[{}] Error while broadcasting: {}",
+                                                    MAIN_THREAD_NAME, err
+                                                );
+                                                monitoring_stats.error();
+                                            }
+                                        }
+                                    }
+
+                                    for user_left_message in user_left_receiver.try_iter() {
+                                        for connection_thread in connection_threads_guard.iter() {
+                                            if let Err(err) = connection_thread
+                                                .event_handler
+                                                .broadcast_user_left(user_left_message.clone())
+                                            {
+                                                eprintln!(
+                                                    "[{}] Error while broadcasting: {}",
+                                                    MAIN_THREAD_NAME, err
                                                 );
+                                                monitoring_stats.error();
+                                            }
                                         }
                                     }
 
-                                    // check for private messages
+                                    // route private messages to the thread of the recipient
                                     for private_chat_message_event in
                                         private_chat_message_event_receiver.try_iter()
                                     {
-                                        for connection_thread in connection_threads_guard.iter_mut()
+                                        if let Some(connection_thread) = connection_threads_guard
+                                            .get(private_chat_message_event.recipient.thread)
                                         {
-                                            connection_thread
+                                            if let Err(err) = connection_thread
                                                 .event_handler
                                                 .private_chat_message_event(
-                                                    private_chat_message_event.clone(),
+                                                    private_chat_message_event,
+                                                )
+                                            {
+                                                eprintln!(
+                                                    "[{}] Error while routing: {}",
+                                                    MAIN_THREAD_NAME, err
                                                 );
+                                                monitoring_stats.error();
+                                            }
+                                        }
+                                    }
+
+                                    // and the results back to the thread of the sender
+                                    for private_chat_result_event in
+                                        private_chat_result_event_receiver.try_iter()
+                                    {
+                                        if let Some(connection_thread) = connection_threads_guard
+                                            .get(private_chat_result_event.sender.thread)
+                                        {
+                                            if let Err(err) = connection_thread
+                                                .event_handler
+                                                .private_chat_result_event(
+                                                    private_chat_result_event,
+                                                )
+                                            {
+                                                eprintln!(
+                                                    "[{}] Error while routing: {}",
+                                                    MAIN_THREAD_NAME, err
+                                                );
+                                                monitoring_stats.error();
+                                            }
+                                        }
+                                    }
+
+                                    // room messages only to the threads of the members
+                                    for room_message_event in room_message_event_receiver.try_iter()
+                                    {
+                                        let mut members_by_thread: HashMap<usize, Vec<_>> =
+                                            HashMap::new();
+                                        for member in room_message_event.members {
+                                            members_by_thread
+                                                .entry(member.thread)
+                                                .or_default()
+                                                .push(member);
+                                        }
+
+                                        for (thread, members) in members_by_thread {
+                                            if let Some(connection_thread) =
+                                                connection_threads_guard.get(thread)
+                                            {
+                                                if let Err(err) = connection_thread
+                                                    .event_handler
+                                                    .room_message_event(RoomMessageEvent {
+                                                        room: room_message_event.room.clone(),
+                                                        members,
+                                                        frame: room_message_event.frame.clone(),
+                                                        capability: room_message_event.capability,
+                                                    })
+                                                {
+                                                    eprintln!(
+                                                        "[{}] Error while routing: {}",
+                                                        MAIN_THREAD_NAME, err
+                                                    );
+                                                    monitoring_stats.error();
+                                                }
+                                            }
+                                        }
+                                    }
+
+                                    // frames for one user to the thread of the user
+                                    for user_message_event in user_message_event_receiver.try_iter()
+                                    {
+                                        if let Some(connection_thread) = connection_threads_guard
+                                            .get(user_message_event.recipient.thread)
+                                        {
+                                            if let Err(err) = connection_thread
+                                                .event_handler
+                                                .user_message_event(user_message_event)
+                                            {
+                                                eprintln!(
+                                                    "[{}] Error while routing: {}",
+                                                    MAIN_THREAD_NAME, err
+                                                );
+                                                monitoring_stats.error();
+                                            }
+                                        }
+                                    }
+
+                                    // moderation to the thread of the moderated user
+                                    for moderation_event in moderation_event_receiver.try_iter() {
+                                        if let Some(connection_thread) = connection_threads_guard
+                                            .get(moderation_event.target.thread)
+                                        {
+                                            if let Err(err) = connection_thread
+                                                .event_handler
+                                                .moderation_event(moderation_event)
+                                            {
+                                                eprintln!(
+                                                    "[{}] Error while routing: {}",
+                                                    MAIN_THREAD_NAME, err
+                                                );
+                                                monitoring_stats.error();
+                                            }
+                                        }
+                                    }
+
+                                    // checked passwords to the thread of the connection
+                                    for login_event in login_event_receiver.try_iter() {
+                                        if let Some(connection_thread) = connection_threads_guard
+                                            .get(login_event.connection.thread)
+                                        {
+                                            if let Err(err) = connection_thread
+                                                .event_handler
+                                                .login_event(login_event)
+                                            {
+                                                eprintln!(
+                                                    "[{}] Error while routing: {}",
+                                                    MAIN_THREAD_NAME, err
+                                                );
+                                                monitoring_stats.error();
+                                            }
                                         }
                                     }
 
@@ -197,13 +445,25 @@ impl Server {
                     }
                 }
             })
-            .expect(format!("Error while creating: {}", MAIN_THREAD_NAME).as_str());
+            .map_err(|err| ServerError::SpawnThread(MAIN_THREAD_NAME.to_string(), err));
+
+        let server_stop = ServerStop::new(server_thread_stops);
+        let server_socket_thread_handle = match server_socket_thread_handle {
+            Ok(server_socket_thread_handle) => server_socket_thread_handle,
+            Err(err) => {
+                server_stop.stop();
+                for connection_thread in connection_threads.lock().unwrap().drain(0..) {
+                    connection_thread.join();
+                }
+                return Err(err);
+            }
+        };
 
-        Self {
+        Ok(Self {
             server_socket_thread_handle,
-            server_stop: ServerStop::new(server_thread_stops),
+            server_stop,
             connection_threads,
-        }
+        })
     }
 
     pub fn get_server_stop(&self) -> ServerStop {
@@ -45,7 +45,7 @@ impl ServerThreadStop {
     }
 
     pub fn should_stop(&self) -> bool {
-        return self.should_stop.load(Ordering::SeqCst);
+        self.should_stop.load(Ordering::SeqCst)
     }
 }
 
@@ -0,0 +1,18 @@
+use crate::net::{
+    auth::Authenticator, history::History, message_clock::MessageClock, moderation::Moderation,
+    offline_queue::OfflineQueue, room_directory::RoomDirectory, user_directory::UserDirectory,
+};
+use std::sync::Arc;
+
+/// State shared by all connection threads.
+pub struct ServerState {
+    pub user_directory: UserDirectory,
+    pub room_directory: RoomDirectory,
+    pub authenticator: Authenticator,
+    pub moderation: Moderation,
+    pub history: History,
+    pub message_clock: MessageClock,
+    pub offline_queue: OfflineQueue,
+    /// Set if clients connect with TLS.
+    pub tls_config: Option<Arc<rustls::ServerConfig>>,
+}
@@ -0,0 +1,40 @@
+use crate::net::{config::TlsConfig, error::ServerError};
+use mio::net::TcpStream;
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig, ServerConnection,
+};
+use std::sync::Arc;
+
+/// Socket of a connection, optionally encrypted with TLS.
+pub type Transport = rust_chat_protocol::transport::Transport<TcpStream>;
+
+/// Loads the certificate chain and key of `tls_config`.
+pub fn load_tls_config(tls_config: &TlsConfig) -> Result<Arc<ServerConfig>, ServerError> {
+    let certs = CertificateDer::pem_file_iter(&tls_config.cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| ServerError::Tls(format!("{}: {}", tls_config.cert_path.display(), err)))?;
+    let key = PrivateKeyDer::from_pem_file(&tls_config.key_path)
+        .map_err(|err| ServerError::Tls(format!("{}: {}", tls_config.key_path.display(), err)))?;
+
+    ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map(Arc::new)
+        .map_err(|err| ServerError::Tls(err.to_string()))
+}
+
+/// Starts the TLS handshake if `tls_config` is set.
+pub fn new_transport(
+    socket: TcpStream,
+    tls_config: Option<&Arc<ServerConfig>>,
+) -> Result<Transport, ServerError> {
+    match tls_config {
+        Some(tls_config) => {
+            let tls = ServerConnection::new(Arc::clone(tls_config))
+                .map_err(|err| ServerError::Tls(err.to_string()))?;
+            Ok(Transport::tls(socket, tls))
+        }
+        None => Ok(Transport::Plain(socket)),
+    }
+}
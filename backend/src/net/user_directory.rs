@@ -0,0 +1,80 @@
+use mio::Token;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::IpAddr,
+    sync::RwLock,
+};
+
+/// Identifies a connection across all connection threads.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ConnectionId {
+    /// Index of the `ConnectionThread` owning the connection.
+    pub thread: usize,
+    pub token: Token,
+}
+
+struct User {
+    connection_id: ConnectionId,
+    address: IpAddr,
+}
+
+/// Maps the names of logged in users to their connections, shared by all threads.
+pub struct UserDirectory {
+    users: RwLock<HashMap<String, User>>,
+}
+
+impl UserDirectory {
+    pub fn new() -> Self {
+        Self {
+            users: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns `false` if the name is taken by another connection.
+    pub fn insert(&self, user_name: String, connection_id: ConnectionId, address: IpAddr) -> bool {
+        match self.users.write().unwrap().entry(user_name) {
+            Entry::Occupied(entry) => entry.get().connection_id == connection_id,
+            Entry::Vacant(entry) => {
+                entry.insert(User {
+                    connection_id,
+                    address,
+                });
+                true
+            }
+        }
+    }
+
+    /// Removes the user only if the name still belongs to `connection_id`.
+    pub fn remove(&self, user_name: &str, connection_id: ConnectionId) {
+        let mut users = self.users.write().unwrap();
+        if users
+            .get(user_name)
+            .is_some_and(|user| user.connection_id == connection_id)
+        {
+            users.remove(user_name);
+        }
+    }
+
+    pub fn get(&self, user_name: &str) -> Option<ConnectionId> {
+        self.users
+            .read()
+            .unwrap()
+            .get(user_name)
+            .map(|user| user.connection_id)
+    }
+
+    /// All logged in users sorted by name.
+    pub fn list(&self) -> Vec<String> {
+        let mut user_names: Vec<String> = self.users.read().unwrap().keys().cloned().collect();
+        user_names.sort();
+        user_names
+    }
+
+    pub fn address(&self, user_name: &str) -> Option<IpAddr> {
+        self.users
+            .read()
+            .unwrap()
+            .get(user_name)
+            .map(|user| user.address)
+    }
+}
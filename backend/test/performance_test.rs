@@ -1,7 +1,21 @@
-use rust_chat::net::server::Server;
+use rust_chat::net::{
+    config::{ServerConfig, TlsConfig},
+    server::Server,
+};
+use rust_chat_protocol::{
+    codec::Codec,
+    compression::{Compression, DEFAULT_COMPRESSION_THRESHOLD},
+    frame::Frame,
+    framing::{Decoder, Encoder, Framing, Header, MAX_PACKET_SIZE},
+    message::Message,
+    messages::{Capability, HelloMessage, PingMessage, WelcomeMessage, PROTOCOL_VERSION},
+};
+use rustls::{pki_types::CertificateDer, ClientConfig, ClientConnection, RootCertStore};
 use std::{
-    io::{ErrorKind, Read, Write},
+    env, fs,
+    io::{self, ErrorKind, Read, Write},
     net::TcpStream,
+    sync::{Arc, OnceLock},
     thread,
     thread::JoinHandle,
     time::{Duration, Instant},
@@ -10,91 +24,303 @@ use std::{
 // 4 is an optimal test value for a 16 thread cpu
 const THREADS_AMOUNT: usize = 4;
 const BUFFER_SIZE: usize = 100;
-const BUFFER_HEAD: &[u8; 11] = b"RustChat0  ";
 
-macro_rules! PerformanceTest {
-    ($function:ident) => {
-        println!("-------------------------------------------------------------------");
-        println!("\x1b[1;32mRunning test: {}\x1b[0m", stringify!($function));
+/// Trusts the self-signed certificate of `create_certificate`.
+static TLS_CLIENT_CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+
+/// Framing, codec and compression a test connection negotiates with the server, and whether the
+/// connection is encrypted.
+#[derive(Clone, Copy, Debug)]
+struct Encoding {
+    framing: Framing,
+    codec: Codec,
+    compression: Option<Compression>,
+    tls: bool,
+}
 
-        let address = "127.0.0.1:4444"
-            .parse()
-            .expect("Error while parsing address!");
+/// The handshake always uses the ascii framing and json.
+const HANDSHAKE_ENCODING: Encoding = Encoding {
+    framing: Framing::Ascii,
+    codec: Codec::Json,
+    compression: None,
+    tls: false,
+};
 
-        let server = Server::new(THREADS_AMOUNT, address);
-        let server_stop = server.get_server_stop();
+/// Connection to the test server, optionally encrypted with TLS.
+enum TestStream {
+    Plain(TcpStream),
+    Tls(Box<rustls::StreamOwned<ClientConnection, TcpStream>>),
+}
 
-        let start_time = Instant::now();
-        $function();
-        let elapsed = start_time.elapsed();
+impl Read for TestStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            TestStream::Plain(stream) => stream.read(buf),
+            TestStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
 
-        server_stop.stop();
-        server.join();
+impl Write for TestStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            TestStream::Plain(stream) => stream.write(buf),
+            TestStream::Tls(stream) => stream.write(buf),
+        }
+    }
 
-        println!(
-            "\x1b[1;32mTest: {} finished in {:.3?}\x1b[0m",
-            stringify!($function),
-            elapsed
-        );
-        println!("-------------------------------------------------------------------");
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            TestStream::Plain(stream) => stream.flush(),
+            TestStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+macro_rules! PerformanceTest {
+    ($function:ident, $encoding:expr) => {
+        if selected(stringify!($function)) {
+            println!("-------------------------------------------------------------------");
+            println!(
+                "\x1b[1;32mRunning test: {} ({:?})\x1b[0m",
+                stringify!($function),
+                $encoding
+            );
+
+            let address = "127.0.0.1:4444"
+                .parse()
+                .expect("Error while parsing address!");
+
+            let tls = $encoding.tls.then(|| TlsConfig {
+                cert_path: env::temp_dir().join("rust_chat_test_cert.pem"),
+                key_path: env::temp_dir().join("rust_chat_test_key.pem"),
+            });
+
+            let server = Server::bind(ServerConfig {
+                address,
+                connection_thread_amount: THREADS_AMOUNT,
+                tls,
+                ..ServerConfig::default()
+            })
+            .unwrap_or_else(|err| panic!("Error while starting server: {}", err));
+            let server_stop = server.get_server_stop();
+
+            let start_time = Instant::now();
+            $function($encoding);
+            let elapsed = start_time.elapsed();
+
+            server_stop.stop();
+            server.join();
+
+            println!(
+                "\x1b[1;32mTest: {} ({:?}) finished in {:.3?}\x1b[0m",
+                stringify!($function),
+                $encoding,
+                elapsed
+            );
+            println!("-------------------------------------------------------------------");
+        }
     };
 }
 
 fn main() {
-    PerformanceTest!(test_server_performace_single);
-    PerformanceTest!(test_server_performace_single_batch);
-    PerformanceTest!(test_server_performace_multi);
-    PerformanceTest!(test_server_performace_multi_batch);
-    PerformanceTest!(test_server_performace_massive);
-    PerformanceTest!(test_server_performace_massive_batch);
+    create_certificate();
+
+    let encodings = [
+        (Framing::Ascii, Codec::Json, None, false),
+        (Framing::Binary, Codec::Json, None, false),
+        (Framing::Binary, Codec::MessagePack, None, false),
+        (Framing::Binary, Codec::Cbor, None, false),
+        (
+            Framing::Binary,
+            Codec::MessagePack,
+            Some(Compression::Zstd),
+            false,
+        ),
+        (
+            Framing::Binary,
+            Codec::Json,
+            Some(Compression::Deflate),
+            false,
+        ),
+        (Framing::Binary, Codec::MessagePack, None, true),
+    ];
+
+    for (framing, codec, compression, tls) in encodings {
+        let encoding = Encoding {
+            framing,
+            codec,
+            compression,
+            tls,
+        };
+
+        PerformanceTest!(test_server_performace_single, encoding);
+        PerformanceTest!(test_server_performace_single_batch, encoding);
+        PerformanceTest!(test_decoder_single_batch, encoding);
+        PerformanceTest!(test_server_performace_multi, encoding);
+        PerformanceTest!(test_server_performace_multi_batch, encoding);
+        PerformanceTest!(test_server_performace_massive, encoding);
+        PerformanceTest!(test_server_performace_massive_batch, encoding);
+    }
 }
 
-fn create_stream() -> TcpStream {
-    let stream = TcpStream::connect("127.0.0.1:4444").expect("Error while connecting to server!");
-    stream
+/// Writes a self-signed certificate for localhost to the temp directory, the server loads it
+/// for the TLS encodings.
+fn create_certificate() {
+    let certified_key = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+        .expect("Error while generating certificate!");
+    fs::write(
+        env::temp_dir().join("rust_chat_test_cert.pem"),
+        certified_key.cert.pem(),
+    )
+    .expect("Error while writing certificate!");
+    fs::write(
+        env::temp_dir().join("rust_chat_test_key.pem"),
+        certified_key.key_pair.serialize_pem(),
+    )
+    .expect("Error while writing key!");
+
+    let mut root_store = RootCertStore::empty();
+    root_store
+        .add(CertificateDer::from(certified_key.cert.der().to_vec()))
+        .expect("Error while adding certificate!");
+    let client_config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("Error while creating tls config!")
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+    TLS_CLIENT_CONFIG
+        .set(Arc::new(client_config))
+        .expect("Certificate already created!");
+}
+
+/// Runs only the tests containing the first argument, if given.
+fn selected(test_name: &str) -> bool {
+    std::env::args()
+        .nth(1)
+        .is_none_or(|filter| test_name.contains(&filter))
+}
+
+fn create_stream(encoding: Encoding) -> TestStream {
+    let tcp_stream =
+        TcpStream::connect("127.0.0.1:4444").expect("Error while connecting to server!");
+    tcp_stream
         .set_read_timeout(Some(Duration::from_secs(5)))
         .expect("Error while set read timeout!");
 
-    return stream;
-}
+    let mut stream = if encoding.tls {
+        let client_config = TLS_CLIENT_CONFIG.get().expect("Certificate not created!");
+        let tls = ClientConnection::new(
+            Arc::clone(client_config),
+            "localhost"
+                .try_into()
+                .expect("Error while parsing server name!"),
+        )
+        .expect("Error while creating tls connection!");
+        TestStream::Tls(Box::new(rustls::StreamOwned::new(tls, tcp_stream)))
+    } else {
+        TestStream::Plain(tcp_stream)
+    };
 
-fn init_buffer() -> [u8; BUFFER_SIZE] {
-    let mut buffer = [0; BUFFER_SIZE];
+    // ascii framing and json without compression are the defaults, they do not need a handshake
+    if encoding.framing != HANDSHAKE_ENCODING.framing
+        || encoding.codec != HANDSHAKE_ENCODING.codec
+        || encoding.compression.is_some()
+    {
+        let welcome_message = hello(&mut stream, encoding, vec![Capability::Compression]);
+        assert_eq!(encoding.framing, welcome_message.framing);
+        assert_eq!(encoding.codec, welcome_message.codec);
+        assert_eq!(encoding.compression, welcome_message.compression);
+        assert_eq!(
+            DEFAULT_COMPRESSION_THRESHOLD,
+            welcome_message.compression_threshold
+        );
+    }
+
+    stream
+}
 
-    for i in 0..11 {
-        buffer[i] = BUFFER_HEAD[i];
+/// Negotiates `encoding` and `capabilities` with the server.
+fn hello(
+    stream: &mut TestStream,
+    encoding: Encoding,
+    capabilities: Vec<Capability>,
+) -> WelcomeMessage {
+    let hello_message = HelloMessage {
+        version: PROTOCOL_VERSION,
+        client_name: "performance_test".to_string(),
+        capabilities,
+        framings: vec![encoding.framing],
+        codecs: vec![encoding.codec],
+        compressions: encoding.compression.into_iter().collect(),
+    };
+    write_message(
+        stream,
+        HANDSHAKE_ENCODING,
+        Message::new(hello_message, HANDSHAKE_ENCODING.codec),
+    );
+
+    match read_frame(stream, &mut Decoder::default()) {
+        Frame::Welcome(welcome_message) => welcome_message,
+        _ => panic!("Expected welcome message!"),
     }
+}
+
+fn encode(buffer: &mut [u8; BUFFER_SIZE], encoding: Encoding, nonce: u32, reply: bool) -> usize {
+    let message = Message::new(PingMessage { nonce, reply }, encoding.codec);
 
-    return buffer;
+    let mut encoder = Encoder::default();
+    encoder.set_framing(encoding.framing);
+
+    let mut out_buffer = Vec::new();
+    encoder
+        .encode(&message, &mut out_buffer)
+        .expect("Error while encode message!");
+    buffer[..out_buffer.len()].copy_from_slice(&out_buffer);
+
+    out_buffer.len()
 }
 
-fn encode(buffer: &mut [u8; BUFFER_SIZE], nonce: u32, reply: bool) -> usize {
-    let payload = format!("{{\"nonce\":{},\"reply\":{}}}", nonce, reply).into_bytes();
+fn write_message(stream: &mut TestStream, encoding: Encoding, message: Message) {
+    // no limit, the server has to reject too large messages
+    let mut encoder = Encoder::new(usize::MAX);
+    encoder.set_framing(encoding.framing);
+    encoder.set_compression(encoding.compression, DEFAULT_COMPRESSION_THRESHOLD);
+
+    let mut out_buffer = Vec::new();
+    encoder
+        .encode(&message, &mut out_buffer)
+        .expect("Error while encode message!");
 
-    let payload_size = payload.len().to_string().into_bytes();
-    for i in 0..5 {
-        if i < payload_size.len() {
-            buffer[11 + i] = payload_size[i];
-        } else {
-            buffer[11 + i] = b' ';
+    stream
+        .write_all(&out_buffer)
+        .expect("Error while write message!");
+}
+
+fn read_frame(stream: &mut TestStream, decoder: &mut Decoder) -> Frame {
+    loop {
+        if let Some(frame) = decoder.decode().expect("Error while decode frame!") {
+            return frame;
         }
-    }
 
-    for i in 0..payload.len() {
-        buffer[16 + i] = payload[i];
+        match stream.read(decoder.unfilled()) {
+            Ok(0) => panic!("Connection closed!"),
+            Ok(n) => decoder.filled(n),
+            Err(err) => panic!("{}", err),
+        }
     }
-
-    return 16 + payload.len();
 }
 
 fn write(
-    stream: &mut TcpStream,
+    stream: &mut TestStream,
     buffer: &mut [u8; BUFFER_SIZE],
+    encoding: Encoding,
     mut nonce: u32,
     messages_amount: u32,
 ) {
     for _ in 0..messages_amount {
-        let out_buffer_size = encode(buffer, nonce, false);
+        let out_buffer_size = encode(buffer, encoding, nonce, false);
         let mut out_buffer_pos = 0;
 
         loop {
@@ -114,15 +340,16 @@ fn write(
 }
 
 fn read(
-    stream: &mut TcpStream,
+    stream: &mut TestStream,
     buffer: &mut [u8; BUFFER_SIZE],
     read_buffer: &mut [u8; BUFFER_SIZE],
+    encoding: Encoding,
     mut nonce: u32,
     messages_amount: u32,
 ) {
     let mut total_recv = 0;
     for _ in 0..messages_amount {
-        let in_buffer_size = encode(buffer, nonce, true);
+        let in_buffer_size = encode(buffer, encoding, nonce, true);
         let mut in_buffer_pos = 0;
 
         loop {
@@ -150,50 +377,176 @@ fn read(
     assert_eq!(messages_amount, total_recv);
 }
 
-fn test_server_performace_single() {
-    let mut stream = create_stream();
+fn test_server_performace_single(encoding: Encoding) {
+    let mut stream = create_stream(encoding);
 
-    let buffer: &mut [u8; BUFFER_SIZE] = &mut init_buffer();
-    let read_buffer: &mut [u8; BUFFER_SIZE] = &mut init_buffer();
-    let mut nonce: u32 = 0;
+    let buffer: &mut [u8; BUFFER_SIZE] = &mut [0; BUFFER_SIZE];
+    let read_buffer: &mut [u8; BUFFER_SIZE] = &mut [0; BUFFER_SIZE];
+    for nonce in 0..100000 {
+        write(&mut stream, buffer, encoding, nonce, 1);
 
-    for _ in 0..100000 {
-        write(&mut stream, buffer, nonce, 1);
+        read(&mut stream, buffer, read_buffer, encoding, nonce, 1);
+    }
+}
 
-        read(&mut stream, buffer, read_buffer, nonce, 1);
+fn test_server_performace_single_batch(encoding: Encoding) {
+    let mut stream = create_stream(encoding);
 
-        nonce += 1;
+    let buffer: &mut [u8; BUFFER_SIZE] = &mut [0; BUFFER_SIZE];
+    let read_buffer: &mut [u8; BUFFER_SIZE] = &mut [0; BUFFER_SIZE];
+    let nonce: u32 = 0;
+
+    write(&mut stream, buffer, encoding, nonce, 100000);
+
+    read(&mut stream, buffer, read_buffer, encoding, nonce, 100000);
+}
+
+/// The frames of `test_server_performace_single_batch` without network, only the decoders are
+/// timed. Compares the `Decoder` with the decoding before it, which moved the remaining bytes
+/// to the front of a one packet buffer after every frame. The reads are the socket reads the
+/// server needs for the batch, each one fills the free part of the input buffer.
+fn test_decoder_single_batch(encoding: Encoding) {
+    let buffer: &mut [u8; BUFFER_SIZE] = &mut [0; BUFFER_SIZE];
+    let mut in_bytes = Vec::new();
+    for nonce in 0..100000 {
+        let out_buffer_size = encode(buffer, encoding, nonce, false);
+        in_bytes.extend_from_slice(&buffer[..out_buffer_size]);
     }
+
+    let start_time = Instant::now();
+    let mut in_place_reads = 0;
+    for _ in 0..10 {
+        let (frames, reads) = decode_in_place(&in_bytes, encoding);
+        assert_eq!(100000, frames);
+        in_place_reads = reads;
+    }
+    let in_place = start_time.elapsed();
+
+    let start_time = Instant::now();
+    let mut compacting_reads = 0;
+    for _ in 0..10 {
+        let (frames, reads) = decode_compacting(&in_bytes, encoding);
+        assert_eq!(100000, frames);
+        compacting_reads = reads;
+    }
+    let compacting = start_time.elapsed();
+
+    println!(
+        "Decoded 10 x 100000 frames in place in {:.3?} with {} reads per batch",
+        in_place, in_place_reads
+    );
+    println!(
+        "Decoded 10 x 100000 frames compacting per frame in {:.3?} with {} reads per batch",
+        compacting, compacting_reads
+    );
 }
 
-fn test_server_performace_single_batch() {
-    let mut stream = create_stream();
+/// Returns the number of frames decoded by `Decoder` and the number of reads.
+fn decode_in_place(in_bytes: &[u8], encoding: Encoding) -> (usize, usize) {
+    let mut decoder = Decoder::default();
+    decoder.set_framing(encoding.framing);
+    decoder.set_codec(encoding.codec);
+
+    let mut in_bytes_pos = 0;
+    let (mut frames, mut reads) = (0, 0);
+    while in_bytes_pos < in_bytes.len() {
+        reads += 1;
+        let unfilled = decoder.unfilled();
+        let n = unfilled.len().min(in_bytes.len() - in_bytes_pos);
+        unfilled[..n].copy_from_slice(&in_bytes[in_bytes_pos..in_bytes_pos + n]);
+        decoder.filled(n);
+        in_bytes_pos += n;
+
+        while decoder
+            .decode()
+            .expect("Error while decode frame!")
+            .is_some()
+        {
+            frames += 1;
+        }
+    }
 
-    let buffer: &mut [u8; BUFFER_SIZE] = &mut init_buffer();
-    let read_buffer: &mut [u8; BUFFER_SIZE] = &mut init_buffer();
-    let nonce: u32 = 0;
+    (frames, reads)
+}
 
-    write(&mut stream, buffer, nonce, 100000);
+/// Returns the number of frames and reads, decoded like before `Decoder` decoded in place: reads fill a
+/// buffer of one packet and each frame is removed by moving the remaining bytes to the front.
+/// Only unfragmented frames without compression, like the ones of the batch.
+fn decode_compacting(in_bytes: &[u8], encoding: Encoding) -> (usize, usize) {
+    let header_size = encoding.framing.header_size();
+    let mut in_buffer = Box::new([0; MAX_PACKET_SIZE]);
+    let mut in_buffer_pos = 0;
+
+    let mut in_bytes_pos = 0;
+    let (mut frames, mut reads) = (0, 0);
+    while in_bytes_pos < in_bytes.len() {
+        reads += 1;
+        let n = (MAX_PACKET_SIZE - in_buffer_pos).min(in_bytes.len() - in_bytes_pos);
+        in_buffer[in_buffer_pos..in_buffer_pos + n]
+            .copy_from_slice(&in_bytes[in_bytes_pos..in_bytes_pos + n]);
+        in_buffer_pos += n;
+        in_bytes_pos += n;
+
+        while in_buffer_pos >= header_size {
+            let header = match encoding.framing {
+                Framing::Ascii => Header {
+                    number: parse_ascii(&in_buffer[8..11]) as u32,
+                    payload_size: parse_ascii(&in_buffer[11..16]),
+                    flags: 0,
+                },
+                Framing::Binary => Header {
+                    number: u16::from_le_bytes([in_buffer[3], in_buffer[4]]) as u32,
+                    payload_size: u32::from_le_bytes([
+                        in_buffer[5],
+                        in_buffer[6],
+                        in_buffer[7],
+                        in_buffer[8],
+                    ]) as usize,
+                    flags: in_buffer[9],
+                },
+            };
+            let packet_size = header_size + header.payload_size;
+            if in_buffer_pos < packet_size {
+                break;
+            }
 
-    read(&mut stream, buffer, read_buffer, nonce, 100000);
+            Frame::decode(
+                &header,
+                &in_buffer[header_size..packet_size],
+                encoding.codec,
+            )
+            .expect("Error while decode frame!");
+            frames += 1;
+
+            // remove packet from buffer
+            in_buffer.copy_within(packet_size..in_buffer_pos, 0);
+            in_buffer_pos -= packet_size;
+        }
+    }
+
+    (frames, reads)
+}
+
+fn parse_ascii(field: &[u8]) -> usize {
+    std::str::from_utf8(field)
+        .expect("Error while parse header!")
+        .trim()
+        .parse()
+        .expect("Error while parse header!")
 }
 
-fn test_server_performace_multi() {
+fn test_server_performace_multi(encoding: Encoding) {
     let mut handels: Vec<JoinHandle<()>> = Vec::new();
     for _ in 0..THREADS_AMOUNT {
-        let handle = thread::spawn(|| {
-            let mut stream = create_stream();
+        let handle = thread::spawn(move || {
+            let mut stream = create_stream(encoding);
 
-            let buffer: &mut [u8; BUFFER_SIZE] = &mut init_buffer();
-            let read_buffer: &mut [u8; BUFFER_SIZE] = &mut init_buffer();
-            let mut nonce: u32 = 0;
+            let buffer: &mut [u8; BUFFER_SIZE] = &mut [0; BUFFER_SIZE];
+            let read_buffer: &mut [u8; BUFFER_SIZE] = &mut [0; BUFFER_SIZE];
+            for nonce in 0..100000 {
+                write(&mut stream, buffer, encoding, nonce, 1);
 
-            for _ in 0..100000 {
-                write(&mut stream, buffer, nonce, 1);
-
-                read(&mut stream, buffer, read_buffer, nonce, 1);
-
-                nonce += 1;
+                read(&mut stream, buffer, read_buffer, encoding, nonce, 1);
             }
         });
         handels.push(handle);
@@ -204,20 +557,20 @@ fn test_server_performace_multi() {
     }
 }
 
-fn test_server_performace_multi_batch() {
+fn test_server_performace_multi_batch(encoding: Encoding) {
     let mut handels: Vec<JoinHandle<()>> = Vec::new();
 
     for _ in 0..THREADS_AMOUNT {
-        let handle = thread::spawn(|| {
-            let mut stream = create_stream();
+        let handle = thread::spawn(move || {
+            let mut stream = create_stream(encoding);
 
-            let buffer: &mut [u8; BUFFER_SIZE] = &mut init_buffer();
-            let read_buffer: &mut [u8; BUFFER_SIZE] = &mut init_buffer();
+            let buffer: &mut [u8; BUFFER_SIZE] = &mut [0; BUFFER_SIZE];
+            let read_buffer: &mut [u8; BUFFER_SIZE] = &mut [0; BUFFER_SIZE];
             let nonce: u32 = 0;
 
-            write(&mut stream, buffer, nonce, 100000);
+            write(&mut stream, buffer, encoding, nonce, 100000);
 
-            read(&mut stream, buffer, read_buffer, nonce, 100000);
+            read(&mut stream, buffer, read_buffer, encoding, nonce, 100000);
         });
         handels.push(handle);
     }
@@ -227,22 +580,18 @@ fn test_server_performace_multi_batch() {
     }
 }
 
-fn test_server_performace_massive() {
+fn test_server_performace_massive(encoding: Encoding) {
     let mut handels: Vec<JoinHandle<()>> = Vec::new();
     for _ in 0..THREADS_AMOUNT * 3 {
-        let handle = thread::spawn(|| {
-            let mut stream = create_stream();
-
-            let buffer: &mut [u8; BUFFER_SIZE] = &mut init_buffer();
-            let read_buffer: &mut [u8; BUFFER_SIZE] = &mut init_buffer();
-            let mut nonce: u32 = 0;
-
-            for _ in 0..100000 {
-                write(&mut stream, buffer, nonce, 1);
+        let handle = thread::spawn(move || {
+            let mut stream = create_stream(encoding);
 
-                read(&mut stream, buffer, read_buffer, nonce, 1);
+            let buffer: &mut [u8; BUFFER_SIZE] = &mut [0; BUFFER_SIZE];
+            let read_buffer: &mut [u8; BUFFER_SIZE] = &mut [0; BUFFER_SIZE];
+            for nonce in 0..100000 {
+                write(&mut stream, buffer, encoding, nonce, 1);
 
-                nonce += 1;
+                read(&mut stream, buffer, read_buffer, encoding, nonce, 1);
             }
         });
         handels.push(handle);
@@ -253,20 +602,20 @@ fn test_server_performace_massive() {
     }
 }
 
-fn test_server_performace_massive_batch() {
+fn test_server_performace_massive_batch(encoding: Encoding) {
     let mut handels: Vec<JoinHandle<()>> = Vec::new();
 
     for _ in 0..THREADS_AMOUNT * 3 {
-        let handle = thread::spawn(|| {
-            let mut stream = create_stream();
+        let handle = thread::spawn(move || {
+            let mut stream = create_stream(encoding);
 
-            let buffer: &mut [u8; BUFFER_SIZE] = &mut init_buffer();
-            let read_buffer: &mut [u8; BUFFER_SIZE] = &mut init_buffer();
+            let buffer: &mut [u8; BUFFER_SIZE] = &mut [0; BUFFER_SIZE];
+            let read_buffer: &mut [u8; BUFFER_SIZE] = &mut [0; BUFFER_SIZE];
             let nonce: u32 = 0;
 
-            write(&mut stream, buffer, nonce, 100000);
+            write(&mut stream, buffer, encoding, nonce, 100000);
 
-            read(&mut stream, buffer, read_buffer, nonce, 100000);
+            read(&mut stream, buffer, read_buffer, encoding, nonce, 100000);
         });
         handels.push(handle);
     }
@@ -0,0 +1,475 @@
+use rust_chat::net::{
+    config::{ServerConfig, TlsConfig},
+    server::Server,
+};
+use rust_chat_protocol::{
+    codec::Codec,
+    compression::{Compression, DEFAULT_COMPRESSION_THRESHOLD},
+    frame::Frame,
+    framing::{Decoder, Encoder, Framing},
+    message::Message,
+    messages::{
+        Auth, Capability, ErrorCode, FetchHistoryMessage, HelloMessage, HistoryEntry, HistoryScope,
+        ListRoomsMessage, LoginError, LoginMessage, LoginResultMessage, ModerationAction,
+        ModerationError, ModerationMessage, PrivateChatResultMessage, PublishPrivateChatMessage,
+        RoomError, RoomInfo, WelcomeMessage, PROTOCOL_VERSION,
+    },
+};
+use rustls::{pki_types::CertificateDer, ClientConfig, ClientConnection, RootCertStore};
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    process,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+/// Framing, codec and compression a test connection negotiates with the server, and whether the
+/// connection is encrypted.
+#[derive(Clone, Copy, Debug)]
+pub struct Encoding {
+    pub framing: Framing,
+    pub codec: Codec,
+    pub compression: Option<Compression>,
+    pub tls: bool,
+}
+
+/// The handshake always uses the ascii framing and json.
+pub const HANDSHAKE_ENCODING: Encoding = Encoding {
+    framing: Framing::Ascii,
+    codec: Codec::Json,
+    compression: None,
+    tls: false,
+};
+
+/// Each framing, codec and compression once, and TLS.
+pub const ENCODINGS: [Encoding; 7] = [
+    HANDSHAKE_ENCODING,
+    Encoding {
+        framing: Framing::Binary,
+        codec: Codec::Json,
+        compression: None,
+        tls: false,
+    },
+    Encoding {
+        framing: Framing::Binary,
+        codec: Codec::MessagePack,
+        compression: None,
+        tls: false,
+    },
+    Encoding {
+        framing: Framing::Binary,
+        codec: Codec::Cbor,
+        compression: None,
+        tls: false,
+    },
+    Encoding {
+        framing: Framing::Binary,
+        codec: Codec::MessagePack,
+        compression: Some(Compression::Zstd),
+        tls: false,
+    },
+    Encoding {
+        framing: Framing::Binary,
+        codec: Codec::Json,
+        compression: Some(Compression::Deflate),
+        tls: false,
+    },
+    Encoding {
+        framing: Framing::Binary,
+        codec: Codec::MessagePack,
+        compression: None,
+        tls: true,
+    },
+];
+
+/// Self-signed certificate for localhost, written to the temp directory once per process.
+struct Certificate {
+    client_config: Arc<ClientConfig>,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+}
+
+static CERTIFICATE: OnceLock<Certificate> = OnceLock::new();
+
+fn certificate() -> &'static Certificate {
+    CERTIFICATE.get_or_init(|| {
+        let certified_key = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+            .expect("Error while generating certificate!");
+        let cert_path = temp_path("cert.pem");
+        let key_path = temp_path("key.pem");
+        fs::write(&cert_path, certified_key.cert.pem()).expect("Error while writing certificate!");
+        fs::write(&key_path, certified_key.key_pair.serialize_pem())
+            .expect("Error while writing key!");
+
+        let mut root_store = RootCertStore::empty();
+        root_store
+            .add(CertificateDer::from(certified_key.cert.der().to_vec()))
+            .expect("Error while adding certificate!");
+        let client_config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .expect("Error while creating tls config!")
+                .with_root_certificates(root_store)
+                .with_no_client_auth();
+
+        Certificate {
+            client_config: Arc::new(client_config),
+            cert_path,
+            key_path,
+        }
+    })
+}
+
+/// A path in the temp directory, unique per name and process, without file.
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("rust_chat_test_{}_{}", process::id(), name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// Server on a free port of localhost, it is stopped when dropped.
+pub struct TestServer {
+    server: Option<Server>,
+    address: SocketAddr,
+}
+
+impl TestServer {
+    /// Starts the server with `config`, with TLS if `encoding` needs it.
+    pub fn start(encoding: Encoding, config: ServerConfig) -> Self {
+        // the port is free again when the listener is dropped
+        let address = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("Error while looking for a free port!");
+
+        let tls = encoding.tls.then(|| TlsConfig {
+            cert_path: certificate().cert_path.clone(),
+            key_path: certificate().key_path.clone(),
+        });
+
+        let server = Server::bind(ServerConfig {
+            address,
+            tls,
+            ..config
+        })
+        .unwrap_or_else(|err| panic!("Error while starting server: {}", err));
+
+        Self {
+            server: Some(server),
+            address,
+        }
+    }
+
+    /// Connects to the server and negotiates `encoding`.
+    pub fn connect(&self, encoding: Encoding) -> TestStream {
+        let tcp_stream =
+            TcpStream::connect(self.address).expect("Error while connecting to server!");
+        tcp_stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .expect("Error while set read timeout!");
+
+        let mut stream = if encoding.tls {
+            let tls = ClientConnection::new(
+                Arc::clone(&certificate().client_config),
+                "localhost"
+                    .try_into()
+                    .expect("Error while parsing server name!"),
+            )
+            .expect("Error while creating tls connection!");
+            TestStream::Tls(Box::new(rustls::StreamOwned::new(tls, tcp_stream)))
+        } else {
+            TestStream::Plain(tcp_stream)
+        };
+
+        // ascii framing and json without compression are the defaults, they do not need a
+        // handshake
+        if encoding.framing != HANDSHAKE_ENCODING.framing
+            || encoding.codec != HANDSHAKE_ENCODING.codec
+            || encoding.compression.is_some()
+        {
+            let welcome_message = hello(&mut stream, encoding, vec![Capability::Compression]);
+            assert_eq!(encoding.framing, welcome_message.framing);
+            assert_eq!(encoding.codec, welcome_message.codec);
+            assert_eq!(encoding.compression, welcome_message.compression);
+            assert_eq!(
+                DEFAULT_COMPRESSION_THRESHOLD,
+                welcome_message.compression_threshold
+            );
+        }
+
+        stream
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.get_server_stop().stop();
+            server.join();
+        }
+    }
+}
+
+/// Connection to the test server, optionally encrypted with TLS.
+pub enum TestStream {
+    Plain(TcpStream),
+    Tls(Box<rustls::StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Read for TestStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            TestStream::Plain(stream) => stream.read(buf),
+            TestStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for TestStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            TestStream::Plain(stream) => stream.write(buf),
+            TestStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            TestStream::Plain(stream) => stream.flush(),
+            TestStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Negotiates `encoding` and `capabilities` with the server.
+pub fn hello(
+    stream: &mut TestStream,
+    encoding: Encoding,
+    capabilities: Vec<Capability>,
+) -> WelcomeMessage {
+    let hello_message = HelloMessage {
+        version: PROTOCOL_VERSION,
+        client_name: "server_test".to_string(),
+        capabilities,
+        framings: vec![encoding.framing],
+        codecs: vec![encoding.codec],
+        compressions: encoding.compression.into_iter().collect(),
+    };
+    write_message(
+        stream,
+        HANDSHAKE_ENCODING,
+        Message::new(hello_message, HANDSHAKE_ENCODING.codec),
+    );
+
+    match read_frame(stream, &mut Decoder::default()) {
+        Frame::Welcome(welcome_message) => welcome_message,
+        _ => panic!("Expected welcome message!"),
+    }
+}
+
+pub fn write_message(stream: &mut TestStream, encoding: Encoding, message: Message) {
+    // no limit, the server has to reject too large messages
+    let mut encoder = Encoder::new(usize::MAX);
+    encoder.set_framing(encoding.framing);
+    encoder.set_compression(encoding.compression, DEFAULT_COMPRESSION_THRESHOLD);
+
+    let mut out_buffer = Vec::new();
+    encoder
+        .encode(&message, &mut out_buffer)
+        .expect("Error while encode message!");
+
+    stream
+        .write_all(&out_buffer)
+        .expect("Error while write message!");
+}
+
+pub fn read_frame(stream: &mut TestStream, decoder: &mut Decoder) -> Frame {
+    loop {
+        if let Some(frame) = decoder.decode().expect("Error while decode frame!") {
+            return frame;
+        }
+
+        match stream.read(decoder.unfilled()) {
+            Ok(0) => panic!("Connection closed!"),
+            Ok(n) => decoder.filled(n),
+            Err(err) => panic!("{}", err),
+        }
+    }
+}
+
+pub fn read_error_code(stream: &mut TestStream, decoder: &mut Decoder) -> ErrorCode {
+    match read_frame(stream, decoder) {
+        Frame::Error(error_message) => error_message.code,
+        _ => panic!("Expected error message!"),
+    }
+}
+
+/// Returns the error of the `LoginResultMessage` of a guest login.
+pub fn login(
+    stream: &mut TestStream,
+    decoder: &mut Decoder,
+    encoding: Encoding,
+    user_name: &str,
+) -> Option<LoginError> {
+    login_with_auth(stream, decoder, encoding, user_name, None).error
+}
+
+pub fn login_with_auth(
+    stream: &mut TestStream,
+    decoder: &mut Decoder,
+    encoding: Encoding,
+    user_name: &str,
+    auth: Option<Auth>,
+) -> LoginResultMessage {
+    let login_message = LoginMessage {
+        user_name: user_name.to_string(),
+        auth,
+    };
+    write_message(
+        stream,
+        encoding,
+        Message::new(login_message, encoding.codec),
+    );
+
+    match read_frame(stream, decoder) {
+        Frame::LoginResult(login_result_message) => {
+            assert_eq!(user_name, login_result_message.user_name);
+            login_result_message
+        }
+        _ => panic!("Expected login result message!"),
+    }
+}
+
+/// Returns the error of the `RoomResultMessage`.
+pub fn room_action<T: Into<Frame>>(
+    stream: &mut TestStream,
+    decoder: &mut Decoder,
+    encoding: Encoding,
+    message: T,
+) -> Option<RoomError> {
+    write_message(stream, encoding, Message::new(message, encoding.codec));
+
+    match read_frame(stream, decoder) {
+        Frame::RoomResult(room_result_message) => room_result_message.error,
+        _ => panic!("Expected room result message!"),
+    }
+}
+
+pub fn list_rooms(
+    stream: &mut TestStream,
+    decoder: &mut Decoder,
+    encoding: Encoding,
+) -> Vec<RoomInfo> {
+    write_message(
+        stream,
+        encoding,
+        Message::new(ListRoomsMessage {}, encoding.codec),
+    );
+
+    match read_frame(stream, decoder) {
+        Frame::RoomList(room_list_message) => room_list_message.rooms,
+        _ => panic!("Expected room list message!"),
+    }
+}
+
+/// Returns the room and the text of the next `RoomMessage`.
+pub fn read_room_message(stream: &mut TestStream, decoder: &mut Decoder) -> (String, String) {
+    match read_frame(stream, decoder) {
+        Frame::Room(room_message) => (room_message.room, room_message.message),
+        _ => panic!("Expected room message!"),
+    }
+}
+
+/// Returns the error of the `ModerationResultMessage`.
+pub fn moderate(
+    stream: &mut TestStream,
+    decoder: &mut Decoder,
+    encoding: Encoding,
+    room: Option<&str>,
+    user_name: &str,
+    action: ModerationAction,
+) -> Option<ModerationError> {
+    let moderation_message = ModerationMessage {
+        room: room.map(|room| room.to_string()),
+        user_name: user_name.to_string(),
+        action,
+    };
+    write_message(
+        stream,
+        encoding,
+        Message::new(moderation_message, encoding.codec),
+    );
+
+    match read_frame(stream, decoder) {
+        Frame::ModerationResult(moderation_result_message) => moderation_result_message.error,
+        _ => panic!("Expected moderation result message!"),
+    }
+}
+
+pub fn fetch_history(
+    stream: &mut TestStream,
+    decoder: &mut Decoder,
+    encoding: Encoding,
+    scope: HistoryScope,
+    before_id: Option<u64>,
+) -> Vec<HistoryEntry> {
+    let fetch_history_message = FetchHistoryMessage {
+        scope: scope.clone(),
+        before_id,
+        limit: 100,
+    };
+    write_message(
+        stream,
+        encoding,
+        Message::new(fetch_history_message, encoding.codec),
+    );
+
+    read_history(stream, decoder, scope)
+}
+
+pub fn read_history(
+    stream: &mut TestStream,
+    decoder: &mut Decoder,
+    scope: HistoryScope,
+) -> Vec<HistoryEntry> {
+    match read_frame(stream, decoder) {
+        Frame::History(history_message) => {
+            assert_eq!(scope, history_message.scope);
+            history_message.messages
+        }
+        _ => panic!("Expected history message!"),
+    }
+}
+
+/// Texts of the messages.
+pub fn texts(entries: &[HistoryEntry]) -> Vec<&str> {
+    entries.iter().map(|entry| entry.message.as_str()).collect()
+}
+
+pub fn send_private_message(
+    stream: &mut TestStream,
+    decoder: &mut Decoder,
+    encoding: Encoding,
+    to_user_name: &str,
+    message: &str,
+) -> PrivateChatResultMessage {
+    let publish_private_chat_message = PublishPrivateChatMessage {
+        to_user_name: to_user_name.to_string(),
+        message: message.to_string(),
+    };
+    write_message(
+        stream,
+        encoding,
+        Message::new(publish_private_chat_message, encoding.codec),
+    );
+
+    match read_frame(stream, decoder) {
+        Frame::PrivateChatResult(private_chat_result_message) => {
+            assert_eq!(to_user_name, private_chat_result_message.to_user_name);
+            private_chat_result_message
+        }
+        _ => panic!("Expected private chat result message!"),
+    }
+}
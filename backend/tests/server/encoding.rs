@@ -0,0 +1,171 @@
+use crate::common::{login, read_frame, write_message, TestServer, ENCODINGS, HANDSHAKE_ENCODING};
+use rust_chat::net::config::ServerConfig;
+use rust_chat_protocol::{
+    codec::Codec,
+    frame::Frame,
+    framing::{Decoder, Framing, DEFAULT_MAX_MESSAGE_SIZE},
+    message::Message,
+    messages::{ErrorCode, HelloMessage, PingMessage, PublishGlobalChatMessage, PROTOCOL_VERSION},
+};
+use std::io::Read;
+
+#[test]
+fn ping() {
+    for encoding in ENCODINGS {
+        let server = TestServer::start(encoding, ServerConfig::default());
+        let mut stream = server.connect(encoding);
+        let mut decoder = Decoder::default();
+        decoder.set_framing(encoding.framing);
+        decoder.set_codec(encoding.codec);
+
+        // the reply has the nonce of the ping
+        let ping_message = PingMessage {
+            nonce: 7,
+            reply: false,
+        };
+        write_message(
+            &mut stream,
+            encoding,
+            Message::new(ping_message, encoding.codec),
+        );
+        match read_frame(&mut stream, &mut decoder) {
+            Frame::Ping(ping_message) => {
+                assert_eq!(7, ping_message.nonce);
+                assert!(ping_message.reply);
+            }
+            _ => panic!("Expected ping message!"),
+        }
+    }
+}
+
+#[test]
+fn large_message() {
+    for encoding in ENCODINGS {
+        let server = TestServer::start(encoding, ServerConfig::default());
+        let mut stream = server.connect(encoding);
+        let mut decoder = Decoder::default();
+        decoder.set_framing(encoding.framing);
+        decoder.set_codec(encoding.codec);
+
+        assert_eq!(None, login(&mut stream, &mut decoder, encoding, "large"));
+
+        // fragmented message below the limit is reassembled and broadcast
+        let message = "0123456789".repeat(DEFAULT_MAX_MESSAGE_SIZE / 20);
+        let publish_message = PublishGlobalChatMessage {
+            message: message.clone(),
+        };
+        write_message(
+            &mut stream,
+            encoding,
+            Message::new(publish_message, encoding.codec),
+        );
+
+        match read_frame(&mut stream, &mut decoder) {
+            Frame::GlobalChat(global_chat_message) => {
+                assert_eq!(message, global_chat_message.message)
+            }
+            _ => panic!("Expected global chat message!"),
+        }
+
+        // message below the limit is rejected if the broadcast would exceed it
+        let publish_message = PublishGlobalChatMessage {
+            message: "0".repeat(DEFAULT_MAX_MESSAGE_SIZE - 20),
+        };
+        write_message(
+            &mut stream,
+            encoding,
+            Message::new(publish_message, encoding.codec),
+        );
+
+        match read_frame(&mut stream, &mut decoder) {
+            Frame::Error(error_message) => {
+                assert_eq!(ErrorCode::PayloadTooLarge, error_message.code)
+            }
+            _ => panic!("Expected error message!"),
+        }
+
+        // message above the limit is rejected with an error
+        let publish_message = PublishGlobalChatMessage {
+            message: "0".repeat(DEFAULT_MAX_MESSAGE_SIZE),
+        };
+        write_message(
+            &mut stream,
+            encoding,
+            Message::new(publish_message, encoding.codec),
+        );
+
+        match read_frame(&mut stream, &mut decoder) {
+            Frame::Error(error_message) => {
+                assert_eq!(ErrorCode::PayloadTooLarge, error_message.code)
+            }
+            _ => panic!("Expected error message!"),
+        }
+    }
+}
+
+#[test]
+fn incompatible_version() {
+    let encoding = HANDSHAKE_ENCODING;
+    let server = TestServer::start(encoding, ServerConfig::default());
+
+    let mut stream = server.connect(encoding);
+    let mut decoder = Decoder::default();
+
+    let hello_message = HelloMessage {
+        version: PROTOCOL_VERSION + 1,
+        client_name: "performance_test".to_string(),
+        capabilities: Vec::new(),
+        framings: vec![Framing::Binary],
+        codecs: vec![Codec::Json],
+        compressions: Vec::new(),
+    };
+    write_message(
+        &mut stream,
+        encoding,
+        Message::new(hello_message, encoding.codec),
+    );
+
+    match read_frame(&mut stream, &mut decoder) {
+        Frame::Error(error_message) => {
+            assert_eq!(ErrorCode::IncompatibleVersion, error_message.code)
+        }
+        _ => panic!("Expected error message!"),
+    }
+
+    // the server closes the connection after the error
+    let mut buffer = [0; 16];
+    assert_eq!(0, stream.read(&mut buffer).expect("Error while read!"));
+}
+
+#[test]
+fn decompression_bomb() {
+    for encoding in ENCODINGS
+        .into_iter()
+        .filter(|encoding| encoding.compression.is_some())
+    {
+        let server = TestServer::start(encoding, ServerConfig::default());
+        let mut stream = server.connect(encoding);
+        let mut decoder = Decoder::default();
+        decoder.set_framing(encoding.framing);
+        decoder.set_codec(encoding.codec);
+
+        assert_eq!(None, login(&mut stream, &mut decoder, encoding, "bomb"));
+
+        // a few kilobytes on the wire, far above the limit after decompression
+        let publish_message = PublishGlobalChatMessage {
+            message: "0".repeat(DEFAULT_MAX_MESSAGE_SIZE * 64),
+        };
+        write_message(
+            &mut stream,
+            encoding,
+            Message::new(publish_message, encoding.codec),
+        );
+
+        match read_frame(&mut stream, &mut decoder) {
+            Frame::Error(error_message) => {
+                assert_eq!(ErrorCode::PayloadTooLarge, error_message.code)
+            }
+            _ => panic!("Expected error message!"),
+        }
+    }
+}
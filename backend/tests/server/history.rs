@@ -0,0 +1,570 @@
+use crate::common::{
+    fetch_history, hello, login, login_with_auth, read_error_code, read_frame, read_history,
+    read_room_message, room_action, send_private_message, temp_path, texts, write_message,
+    TestServer, TestStream, HANDSHAKE_ENCODING,
+};
+use rust_chat::net::config::ServerConfig;
+use rust_chat_protocol::{
+    frame::Frame,
+    framing::Decoder,
+    message::Message,
+    messages::{
+        Auth, Capability, DeleteMessage, DeliveryStatus, EditMessage, ErrorCode,
+        FetchHistoryMessage, HistoryScope, JoinRoomMessage, PingMessage, PublishGlobalChatMessage,
+        PublishPrivateChatMessage, PublishRoomMessage,
+    },
+};
+use std::fs;
+
+#[test]
+fn history() {
+    let encoding = HANDSHAKE_ENCODING;
+    let history_path = temp_path("history.jsonl");
+    let server = TestServer::start(
+        encoding,
+        ServerConfig {
+            history_path: Some(history_path.clone()),
+            max_history_fetch: 2,
+            ..ServerConfig::default()
+        },
+    );
+
+    // without the capability there is no replay
+    let mut alice = server.connect(encoding);
+    let mut alice_decoder = Decoder::default();
+    assert_eq!(
+        None,
+        login(&mut alice, &mut alice_decoder, encoding, "alice")
+    );
+
+    for message in ["0", "1", "2"] {
+        let publish_global_chat_message = PublishGlobalChatMessage {
+            message: message.to_string(),
+        };
+        write_message(
+            &mut alice,
+            encoding,
+            Message::new(publish_global_chat_message, encoding.codec),
+        );
+        match read_frame(&mut alice, &mut alice_decoder) {
+            Frame::GlobalChat(global_chat_message) => {
+                assert_eq!(message, global_chat_message.message)
+            }
+            _ => panic!("Expected global chat message!"),
+        }
+    }
+
+    let join_room_message = JoinRoomMessage {
+        room: "lobby".to_string(),
+    };
+    assert_eq!(
+        None,
+        room_action(&mut alice, &mut alice_decoder, encoding, join_room_message)
+    );
+    let publish_room_message = PublishRoomMessage {
+        room: "lobby".to_string(),
+        message: "r".to_string(),
+    };
+    write_message(
+        &mut alice,
+        encoding,
+        Message::new(publish_room_message, encoding.codec),
+    );
+    read_room_message(&mut alice, &mut alice_decoder);
+
+    // the newest global messages after the login
+    let mut bob = server.connect(encoding);
+    let mut bob_decoder = Decoder::default();
+    hello(&mut bob, encoding, vec![Capability::History]);
+    assert_eq!(None, login(&mut bob, &mut bob_decoder, encoding, "bob"));
+    let replay = read_history(&mut bob, &mut bob_decoder, HistoryScope::Global);
+    assert_eq!(vec!["0", "1", "2"], texts(&replay));
+    assert!(replay.iter().all(|entry| entry.user_name == "alice"));
+    assert!(replay
+        .windows(2)
+        .all(|entries| entries[0].message_id < entries[1].message_id));
+
+    // pages of at most `max_history_fetch` messages
+    let page = fetch_history(
+        &mut bob,
+        &mut bob_decoder,
+        encoding,
+        HistoryScope::Global,
+        None,
+    );
+    assert_eq!(vec!["1", "2"], texts(&page));
+    let page = fetch_history(
+        &mut bob,
+        &mut bob_decoder,
+        encoding,
+        HistoryScope::Global,
+        Some(page[0].message_id),
+    );
+    assert_eq!(vec!["0"], texts(&page));
+
+    // rooms only for members
+    let fetch_history_message = FetchHistoryMessage {
+        scope: HistoryScope::Room("lobby".to_string()),
+        before_id: None,
+        limit: 100,
+    };
+    write_message(
+        &mut bob,
+        encoding,
+        Message::new(fetch_history_message, encoding.codec),
+    );
+    assert_eq!(
+        ErrorCode::NotInRoom,
+        read_error_code(&mut bob, &mut bob_decoder)
+    );
+    let page = fetch_history(
+        &mut alice,
+        &mut alice_decoder,
+        encoding,
+        HistoryScope::Room("lobby".to_string()),
+        None,
+    );
+    assert_eq!(vec!["r"], texts(&page));
+
+    // private messages only for both users
+    let publish_private_chat_message = PublishPrivateChatMessage {
+        to_user_name: "bob".to_string(),
+        message: "secret".to_string(),
+    };
+    write_message(
+        &mut alice,
+        encoding,
+        Message::new(publish_private_chat_message, encoding.codec),
+    );
+    match read_frame(&mut alice, &mut alice_decoder) {
+        Frame::PrivateChatResult(private_chat_result_message) => {
+            assert_eq!(
+                DeliveryStatus::Delivered,
+                private_chat_result_message.status
+            )
+        }
+        _ => panic!("Expected private chat result message!"),
+    }
+    match read_frame(&mut bob, &mut bob_decoder) {
+        Frame::PrivateChat(_) => {}
+        _ => panic!("Expected private chat message!"),
+    }
+    let page = fetch_history(
+        &mut bob,
+        &mut bob_decoder,
+        encoding,
+        HistoryScope::User("alice".to_string()),
+        None,
+    );
+    assert_eq!(vec!["secret"], texts(&page));
+    assert_eq!("alice", page[0].user_name);
+    let page = fetch_history(
+        &mut alice,
+        &mut alice_decoder,
+        encoding,
+        HistoryScope::User("bob".to_string()),
+        None,
+    );
+    assert_eq!(vec!["secret"], texts(&page));
+
+    let mut carol = server.connect(encoding);
+    let mut carol_decoder = Decoder::default();
+    assert_eq!(
+        None,
+        login(&mut carol, &mut carol_decoder, encoding, "carol")
+    );
+    let page = fetch_history(
+        &mut carol,
+        &mut carol_decoder,
+        encoding,
+        HistoryScope::User("alice".to_string()),
+        None,
+    );
+    assert!(page.is_empty());
+
+    // the second server loads the history of the first one
+    drop(server);
+    let server = TestServer::start(
+        encoding,
+        ServerConfig {
+            history_path: Some(history_path.clone()),
+            ..ServerConfig::default()
+        },
+    );
+
+    let mut dave = server.connect(encoding);
+    let mut dave_decoder = Decoder::default();
+    hello(&mut dave, encoding, vec![Capability::History]);
+    assert_eq!(None, login(&mut dave, &mut dave_decoder, encoding, "dave"));
+    let replay = read_history(&mut dave, &mut dave_decoder, HistoryScope::Global);
+    assert_eq!(vec!["0", "1", "2"], texts(&replay));
+
+    // ids go on after the stored messages
+    let publish_global_chat_message = PublishGlobalChatMessage {
+        message: "3".to_string(),
+    };
+    write_message(
+        &mut dave,
+        encoding,
+        Message::new(publish_global_chat_message, encoding.codec),
+    );
+    match read_frame(&mut dave, &mut dave_decoder) {
+        Frame::GlobalChat(global_chat_message) => {
+            assert!(global_chat_message.message_id > replay[2].message_id)
+        }
+        _ => panic!("Expected global chat message!"),
+    }
+
+    let _ = fs::remove_file(history_path);
+}
+
+#[test]
+fn history_large_pages() {
+    let encoding = HANDSHAKE_ENCODING;
+    let server = TestServer::start(
+        encoding,
+        ServerConfig {
+            history_replay: 30,
+            ..ServerConfig::default()
+        },
+    );
+
+    let mut alice = server.connect(encoding);
+    let mut alice_decoder = Decoder::default();
+    assert_eq!(
+        None,
+        login(&mut alice, &mut alice_decoder, encoding, "alice")
+    );
+
+    // 30 messages of 3 KB do not fit into one page
+    let messages: Vec<String> = (0..30)
+        .map(|index| format!("{:02}{}", index, "x".repeat(3000)))
+        .collect();
+    for message in &messages {
+        let publish_global_chat_message = PublishGlobalChatMessage {
+            message: message.clone(),
+        };
+        write_message(
+            &mut alice,
+            encoding,
+            Message::new(publish_global_chat_message, encoding.codec),
+        );
+        match read_frame(&mut alice, &mut alice_decoder) {
+            Frame::GlobalChat(global_chat_message) => {
+                assert_eq!(*message, global_chat_message.message)
+            }
+            _ => panic!("Expected global chat message!"),
+        }
+    }
+
+    // the replay holds the newest messages that fit
+    let mut bob = server.connect(encoding);
+    let mut bob_decoder = Decoder::default();
+    hello(&mut bob, encoding, vec![Capability::History]);
+    assert_eq!(None, login(&mut bob, &mut bob_decoder, encoding, "bob"));
+    let replay = read_history(&mut bob, &mut bob_decoder, HistoryScope::Global);
+    assert!(!replay.is_empty() && replay.len() < messages.len());
+    assert_eq!(messages[messages.len() - replay.len()..], texts(&replay));
+
+    // the pages are cut, the older messages follow with the next fetch
+    let mut fetched = Vec::new();
+    let mut before_id = None;
+    loop {
+        let page = fetch_history(
+            &mut bob,
+            &mut bob_decoder,
+            encoding,
+            HistoryScope::Global,
+            before_id,
+        );
+        if page.is_empty() {
+            break;
+        }
+        assert!(page.len() < messages.len());
+        before_id = Some(page[0].message_id);
+        fetched.splice(0..0, page);
+    }
+    assert_eq!(messages, texts(&fetched));
+}
+
+#[test]
+fn edit_and_delete() {
+    let encoding = HANDSHAKE_ENCODING;
+    let history_path = temp_path("edit_history.jsonl");
+    let server = TestServer::start(
+        encoding,
+        ServerConfig {
+            history_path: Some(history_path.clone()),
+            operators: vec!["root".to_string()],
+            ..ServerConfig::default()
+        },
+    );
+
+    let edit = |message_id: u64, message: &str| {
+        Message::new(
+            EditMessage {
+                message_id,
+                message: message.to_string(),
+            },
+            encoding.codec,
+        )
+    };
+    let delete = |message_id: u64| Message::new(DeleteMessage { message_id }, encoding.codec);
+    let read_edited =
+        |stream: &mut TestStream, decoder: &mut Decoder| match read_frame(stream, decoder) {
+            Frame::MessageEdited(message_edited_message) => (
+                message_edited_message.message_id,
+                message_edited_message.message,
+                message_edited_message.user_name,
+            ),
+            _ => panic!("Expected message edited message!"),
+        };
+    let read_deleted =
+        |stream: &mut TestStream, decoder: &mut Decoder| match read_frame(stream, decoder) {
+            Frame::MessageDeleted(message_deleted_message) => (
+                message_deleted_message.message_id,
+                message_deleted_message.user_name,
+            ),
+            _ => panic!("Expected message deleted message!"),
+        };
+
+    let mut alice = server.connect(encoding);
+    let mut alice_decoder = Decoder::default();
+    hello(&mut alice, encoding, vec![Capability::Edit]);
+    let mut bob = server.connect(encoding);
+    let mut bob_decoder = Decoder::default();
+    hello(&mut bob, encoding, vec![Capability::Edit]);
+    let mut root = server.connect(encoding);
+    let mut root_decoder = Decoder::default();
+    hello(&mut root, encoding, vec![Capability::Edit]);
+
+    // owners and operators need an account
+    let password = || Some(Auth::Password("correct horse".to_string()));
+    let login_result = login_with_auth(
+        &mut alice,
+        &mut alice_decoder,
+        encoding,
+        "alice",
+        password(),
+    );
+    assert_eq!(None, login_result.error);
+    assert_eq!(None, login(&mut bob, &mut bob_decoder, encoding, "bob"));
+    let login_result = login_with_auth(&mut root, &mut root_decoder, encoding, "root", password());
+    assert_eq!(None, login_result.error);
+
+    for message in ["0", "1"] {
+        let publish_global_chat_message = PublishGlobalChatMessage {
+            message: message.to_string(),
+        };
+        write_message(
+            &mut alice,
+            encoding,
+            Message::new(publish_global_chat_message, encoding.codec),
+        );
+    }
+    let mut message_ids = Vec::new();
+    for (stream, decoder) in [
+        (&mut alice, &mut alice_decoder),
+        (&mut bob, &mut bob_decoder),
+        (&mut root, &mut root_decoder),
+    ] {
+        message_ids.clear();
+        for _ in 0..2 {
+            match read_frame(stream, decoder) {
+                Frame::GlobalChat(global_chat_message) => {
+                    message_ids.push(global_chat_message.message_id)
+                }
+                _ => panic!("Expected global chat message!"),
+            }
+        }
+    }
+
+    // only the author or a moderator
+    write_message(&mut bob, encoding, edit(message_ids[0], "bob was here"));
+    assert_eq!(
+        ErrorCode::NotPermitted,
+        read_error_code(&mut bob, &mut bob_decoder)
+    );
+    write_message(&mut alice, encoding, edit(message_ids[1] + 100, "?"));
+    assert_eq!(
+        ErrorCode::UnknownMessageId,
+        read_error_code(&mut alice, &mut alice_decoder)
+    );
+
+    write_message(&mut alice, encoding, edit(message_ids[0], "zero"));
+    for (stream, decoder) in [
+        (&mut alice, &mut alice_decoder),
+        (&mut bob, &mut bob_decoder),
+        (&mut root, &mut root_decoder),
+    ] {
+        assert_eq!(
+            (message_ids[0], "zero".to_string(), "alice".to_string()),
+            read_edited(stream, decoder)
+        );
+    }
+    write_message(&mut root, encoding, delete(message_ids[1]));
+    for (stream, decoder) in [
+        (&mut alice, &mut alice_decoder),
+        (&mut bob, &mut bob_decoder),
+        (&mut root, &mut root_decoder),
+    ] {
+        assert_eq!(
+            (message_ids[1], "root".to_string()),
+            read_deleted(stream, decoder)
+        );
+    }
+
+    let history = fetch_history(
+        &mut alice,
+        &mut alice_decoder,
+        encoding,
+        HistoryScope::Global,
+        None,
+    );
+    assert_eq!(vec!["zero"], texts(&history));
+    assert!(history[0].edited);
+
+    // the owner of the room moderates it, only its members are notified
+    for (stream, decoder) in [
+        (&mut alice, &mut alice_decoder),
+        (&mut bob, &mut bob_decoder),
+    ] {
+        let join_room_message = JoinRoomMessage {
+            room: "lobby".to_string(),
+        };
+        assert_eq!(
+            None,
+            room_action(stream, decoder, encoding, join_room_message)
+        );
+    }
+    let publish_room_message = PublishRoomMessage {
+        room: "lobby".to_string(),
+        message: "spam".to_string(),
+    };
+    write_message(
+        &mut bob,
+        encoding,
+        Message::new(publish_room_message, encoding.codec),
+    );
+    let mut room_message_id = 0;
+    for (stream, decoder) in [
+        (&mut alice, &mut alice_decoder),
+        (&mut bob, &mut bob_decoder),
+    ] {
+        match read_frame(stream, decoder) {
+            Frame::Room(room_message) => room_message_id = room_message.message_id,
+            _ => panic!("Expected room message!"),
+        }
+    }
+    write_message(&mut alice, encoding, delete(room_message_id));
+    for (stream, decoder) in [
+        (&mut alice, &mut alice_decoder),
+        (&mut bob, &mut bob_decoder),
+    ] {
+        assert_eq!(
+            (room_message_id, "alice".to_string()),
+            read_deleted(stream, decoder)
+        );
+    }
+
+    // private messages are changed only by their authors
+    let result = send_private_message(&mut alice, &mut alice_decoder, encoding, "bob", "secret");
+    let private_message_id = result.message_id.expect("Expected message id!");
+    match read_frame(&mut bob, &mut bob_decoder) {
+        Frame::PrivateChat(private_chat_message) => {
+            assert_eq!(private_message_id, private_chat_message.message_id)
+        }
+        _ => panic!("Expected private chat message!"),
+    }
+    for (stream, decoder) in [(&mut bob, &mut bob_decoder), (&mut root, &mut root_decoder)] {
+        write_message(stream, encoding, delete(private_message_id));
+        assert_eq!(ErrorCode::NotPermitted, read_error_code(stream, decoder));
+    }
+    write_message(&mut alice, encoding, edit(private_message_id, "public"));
+    write_message(&mut alice, encoding, delete(private_message_id));
+    for (stream, decoder) in [
+        (&mut alice, &mut alice_decoder),
+        (&mut bob, &mut bob_decoder),
+    ] {
+        assert_eq!(
+            (
+                private_message_id,
+                "public".to_string(),
+                "alice".to_string()
+            ),
+            read_edited(stream, decoder)
+        );
+        assert_eq!(
+            (private_message_id, "alice".to_string()),
+            read_deleted(stream, decoder)
+        );
+    }
+
+    // root got nothing of the room and the private chat
+    let ping_message = PingMessage {
+        nonce: 1,
+        reply: false,
+    };
+    write_message(
+        &mut root,
+        encoding,
+        Message::new(ping_message, encoding.codec),
+    );
+    match read_frame(&mut root, &mut root_decoder) {
+        Frame::Ping(ping_message) => assert!(ping_message.reply),
+        _ => panic!("Expected ping message!"),
+    }
+
+    // the second server applies the edits and deletes of the first one
+    drop(server);
+    let server = TestServer::start(
+        encoding,
+        ServerConfig {
+            history_path: Some(history_path.clone()),
+            ..ServerConfig::default()
+        },
+    );
+
+    let mut alice = server.connect(encoding);
+    let mut alice_decoder = Decoder::default();
+    assert_eq!(
+        None,
+        login(&mut alice, &mut alice_decoder, encoding, "alice")
+    );
+
+    let history = fetch_history(
+        &mut alice,
+        &mut alice_decoder,
+        encoding,
+        HistoryScope::Global,
+        None,
+    );
+    assert_eq!(vec!["zero"], texts(&history));
+    assert!(history[0].edited);
+    let private_history = fetch_history(
+        &mut alice,
+        &mut alice_decoder,
+        encoding,
+        HistoryScope::User("bob".to_string()),
+        None,
+    );
+    assert!(private_history.is_empty());
+
+    // ids of deleted messages are not used again, three messages followed the edited one
+    let publish_global_chat_message = PublishGlobalChatMessage {
+        message: "4".to_string(),
+    };
+    write_message(
+        &mut alice,
+        encoding,
+        Message::new(publish_global_chat_message, encoding.codec),
+    );
+    match read_frame(&mut alice, &mut alice_decoder) {
+        Frame::GlobalChat(global_chat_message) => {
+            assert!(global_chat_message.message_id > history[0].message_id + 3)
+        }
+        _ => panic!("Expected global chat message!"),
+    }
+
+    let _ = fs::remove_file(history_path);
+}
@@ -0,0 +1,170 @@
+use crate::common::{
+    login, login_with_auth, read_frame, write_message, TestServer, HANDSHAKE_ENCODING,
+};
+use rust_chat::net::config::ServerConfig;
+use rust_chat_protocol::{
+    frame::Frame,
+    framing::Decoder,
+    message::Message,
+    messages::{Auth, ListUsersMessage, LoginError, LoginMessage},
+};
+use std::{thread, time::Duration};
+
+#[test]
+fn guest_login() {
+    let encoding = HANDSHAKE_ENCODING;
+    let server = TestServer::start(encoding, ServerConfig::default());
+
+    let mut stream = server.connect(encoding);
+    let mut decoder = Decoder::default();
+
+    assert_eq!(
+        Some(LoginError::InvalidName),
+        login(&mut stream, &mut decoder, encoding, "")
+    );
+    assert_eq!(
+        Some(LoginError::InvalidName),
+        login(&mut stream, &mut decoder, encoding, "white space")
+    );
+    assert_eq!(
+        Some(LoginError::InvalidName),
+        login(&mut stream, &mut decoder, encoding, &"x".repeat(33))
+    );
+    assert_eq!(
+        Some(LoginError::ReservedName),
+        login(&mut stream, &mut decoder, encoding, "Admin")
+    );
+    assert_eq!(None, login(&mut stream, &mut decoder, encoding, "alice"));
+    assert_eq!(
+        Some(LoginError::AlreadyLoggedIn),
+        login(&mut stream, &mut decoder, encoding, "bob")
+    );
+
+    // the name is taken until the first connection is closed
+    let mut other_stream = server.connect(encoding);
+    let mut other_decoder = Decoder::default();
+    assert_eq!(
+        Some(LoginError::NameTaken),
+        login(&mut other_stream, &mut other_decoder, encoding, "alice")
+    );
+
+    drop(stream);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(
+        None,
+        login(&mut other_stream, &mut other_decoder, encoding, "alice")
+    );
+}
+
+#[test]
+fn authentication() {
+    let encoding = HANDSHAKE_ENCODING;
+    let server = TestServer::start(encoding, ServerConfig::default());
+
+    let password = || Some(Auth::Password("correct horse".to_string()));
+    let wrong_password = || Some(Auth::Password("wrong password".to_string()));
+
+    // the first login with a password creates the account
+    let mut stream = server.connect(encoding);
+    let mut decoder = Decoder::default();
+    let short_password = Some(Auth::Password("short".to_string()));
+    let login_result =
+        login_with_auth(&mut stream, &mut decoder, encoding, "carol", short_password);
+    assert_eq!(Some(LoginError::WeakPassword), login_result.error);
+    let login_result = login_with_auth(&mut stream, &mut decoder, encoding, "carol", password());
+    assert_eq!(None, login_result.error);
+    let session_token = login_result.session_token.expect("Expected session token!");
+    drop(stream);
+    thread::sleep(Duration::from_millis(100));
+
+    let mut stream = server.connect(encoding);
+    let mut decoder = Decoder::default();
+
+    // guests can not use the name of an account
+    assert_eq!(
+        Some(LoginError::AuthRequired),
+        login(&mut stream, &mut decoder, encoding, "carol")
+    );
+
+    let wrong_token = Some(Auth::Token("0".repeat(64)));
+    let login_result = login_with_auth(&mut stream, &mut decoder, encoding, "carol", wrong_token);
+    assert_eq!(Some(LoginError::InvalidCredentials), login_result.error);
+
+    // reconnecting with the session token needs no password
+    let token = Some(Auth::Token(session_token.clone()));
+    let login_result = login_with_auth(&mut stream, &mut decoder, encoding, "carol", token);
+    assert_eq!(None, login_result.error);
+    assert_eq!(Some(session_token), login_result.session_token);
+    drop(stream);
+    thread::sleep(Duration::from_millis(100));
+
+    // a name in use gets no account
+    let mut guest = server.connect(encoding);
+    let mut guest_decoder = Decoder::default();
+    assert_eq!(
+        None,
+        login(&mut guest, &mut guest_decoder, encoding, "dave")
+    );
+    let mut stream = server.connect(encoding);
+    let mut decoder = Decoder::default();
+    let login_result = login_with_auth(&mut stream, &mut decoder, encoding, "dave", password());
+    assert_eq!(Some(LoginError::NameTaken), login_result.error);
+    drop(guest);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(None, login(&mut stream, &mut decoder, encoding, "dave"));
+
+    // new accounts are rate limited per address, carol has the first
+    for index in 1..ServerConfig::default().max_account_creations {
+        let mut stream = server.connect(encoding);
+        let mut decoder = Decoder::default();
+        let user_name = format!("user{}", index);
+        let login_message = LoginMessage {
+            user_name: user_name.clone(),
+            auth: password(),
+        };
+        write_message(
+            &mut stream,
+            encoding,
+            Message::new(login_message, encoding.codec),
+        );
+        // frames after the login wait for the password check
+        write_message(
+            &mut stream,
+            encoding,
+            Message::new(ListUsersMessage {}, encoding.codec),
+        );
+        match read_frame(&mut stream, &mut decoder) {
+            Frame::LoginResult(login_result_message) => {
+                assert_eq!(None, login_result_message.error)
+            }
+            _ => panic!("Expected login result message!"),
+        }
+        match read_frame(&mut stream, &mut decoder) {
+            Frame::UserList(user_list_message) => {
+                assert!(user_list_message.user_names.contains(&user_name))
+            }
+            _ => panic!("Expected user list message!"),
+        }
+    }
+    let mut stream = server.connect(encoding);
+    let mut decoder = Decoder::default();
+    let login_result = login_with_auth(&mut stream, &mut decoder, encoding, "erin", password());
+    assert_eq!(Some(LoginError::TooManyAttempts), login_result.error);
+    drop(stream);
+
+    // wrong credentials are rate limited per address, the token above was the first
+    let mut stream = server.connect(encoding);
+    let mut decoder = Decoder::default();
+    for _ in 1..ServerConfig::default().max_failed_logins {
+        let login_result = login_with_auth(
+            &mut stream,
+            &mut decoder,
+            encoding,
+            "carol",
+            wrong_password(),
+        );
+        assert_eq!(Some(LoginError::InvalidCredentials), login_result.error);
+    }
+    let login_result = login_with_auth(&mut stream, &mut decoder, encoding, "carol", password());
+    assert_eq!(Some(LoginError::TooManyAttempts), login_result.error);
+}
@@ -0,0 +1,7 @@
+mod common;
+mod encoding;
+mod history;
+mod login;
+mod messages;
+mod presence;
+mod rooms;
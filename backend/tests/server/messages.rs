@@ -0,0 +1,206 @@
+use crate::common::{
+    login, login_with_auth, read_frame, send_private_message, temp_path, write_message, TestServer,
+    HANDSHAKE_ENCODING,
+};
+use rust_chat::net::config::ServerConfig;
+use rust_chat_protocol::{
+    frame::Frame,
+    framing::Decoder,
+    message::Message,
+    messages::{Auth, DeliveryStatus, PublishGlobalChatMessage, PublishPrivateChatMessage},
+};
+use std::{fs, thread, time::Duration};
+
+#[test]
+fn message_ids() {
+    const MESSAGES: usize = 100;
+
+    let encoding = HANDSHAKE_ENCODING;
+    let server = TestServer::start(encoding, ServerConfig::default());
+
+    let mut alice = server.connect(encoding);
+    let mut alice_decoder = Decoder::default();
+    let mut bob = server.connect(encoding);
+    let mut bob_decoder = Decoder::default();
+    assert_eq!(
+        None,
+        login(&mut alice, &mut alice_decoder, encoding, "alice")
+    );
+    assert_eq!(None, login(&mut bob, &mut bob_decoder, encoding, "bob"));
+
+    // both publish at the same time, the connections are on different threads
+    for i in 0..MESSAGES {
+        for stream in [&mut alice, &mut bob] {
+            let publish_global_chat_message = PublishGlobalChatMessage {
+                message: i.to_string(),
+            };
+            write_message(
+                stream,
+                encoding,
+                Message::new(publish_global_chat_message, encoding.codec),
+            );
+        }
+    }
+
+    // all clients get the messages in the order of their ids
+    let mut received = Vec::new();
+    for (stream, decoder) in [
+        (&mut alice, &mut alice_decoder),
+        (&mut bob, &mut bob_decoder),
+    ] {
+        let mut stamps: Vec<(u64, u64)> = Vec::new();
+        for _ in 0..2 * MESSAGES {
+            match read_frame(stream, decoder) {
+                Frame::GlobalChat(global_chat_message) => stamps.push((
+                    global_chat_message.message_id,
+                    global_chat_message.timestamp,
+                )),
+                _ => panic!("Expected global chat message!"),
+            }
+        }
+        assert!(stamps.windows(2).all(|stamps| stamps[0].0 < stamps[1].0));
+        assert!(stamps.windows(2).all(|stamps| stamps[0].1 <= stamps[1].1));
+        assert!(stamps.iter().all(|(_, timestamp)| *timestamp > 0));
+        received.push(stamps);
+    }
+    assert_eq!(received[0], received[1]);
+
+    // ids are shared by all chats
+    let last_message_id = received[0][2 * MESSAGES - 1].0;
+    let publish_private_chat_message = PublishPrivateChatMessage {
+        to_user_name: "bob".to_string(),
+        message: "hello".to_string(),
+    };
+    write_message(
+        &mut alice,
+        encoding,
+        Message::new(publish_private_chat_message, encoding.codec),
+    );
+    match read_frame(&mut bob, &mut bob_decoder) {
+        Frame::PrivateChat(private_chat_message) => {
+            assert!(private_chat_message.message_id > last_message_id)
+        }
+        _ => panic!("Expected private chat message!"),
+    }
+}
+
+#[test]
+fn offline_messages() {
+    let encoding = HANDSHAKE_ENCODING;
+    let offline_messages_path = temp_path("offline_messages.jsonl");
+    let credentials_path = temp_path("offline_accounts.jsonl");
+    let server = TestServer::start(
+        encoding,
+        ServerConfig {
+            offline_messages_path: Some(offline_messages_path.clone()),
+            credentials_path: Some(credentials_path.clone()),
+            max_offline_messages: 2,
+            ..ServerConfig::default()
+        },
+    );
+
+    let password = || Some(Auth::Password("correct horse".to_string()));
+
+    // bob and carol have accounts and log out
+    for user_name in ["bob", "carol"] {
+        let mut stream = server.connect(encoding);
+        let mut decoder = Decoder::default();
+        let login_result =
+            login_with_auth(&mut stream, &mut decoder, encoding, user_name, password());
+        assert_eq!(None, login_result.error);
+    }
+    thread::sleep(Duration::from_millis(100));
+
+    let mut alice = server.connect(encoding);
+    let mut alice_decoder = Decoder::default();
+    assert_eq!(
+        None,
+        login(&mut alice, &mut alice_decoder, encoding, "alice")
+    );
+
+    let mut message_ids = Vec::new();
+    for message in ["0", "1"] {
+        let result = send_private_message(&mut alice, &mut alice_decoder, encoding, "bob", message);
+        assert_eq!(DeliveryStatus::Deferred, result.status);
+        message_ids.push(result.message_id.expect("Expected message id!"));
+    }
+    let result = send_private_message(&mut alice, &mut alice_decoder, encoding, "bob", "2");
+    assert_eq!(DeliveryStatus::QueueFull, result.status);
+    assert_eq!(None, result.message_id);
+
+    // guests are not waited for
+    let result = send_private_message(&mut alice, &mut alice_decoder, encoding, "dave", "3");
+    assert_eq!(DeliveryStatus::UserNotFound, result.status);
+    assert_eq!(None, result.message_id);
+
+    // carol gets the message after the restart
+    let result = send_private_message(&mut alice, &mut alice_decoder, encoding, "carol", "4");
+    assert_eq!(DeliveryStatus::Deferred, result.status);
+
+    let mut bob = server.connect(encoding);
+    let mut bob_decoder = Decoder::default();
+    let login_result = login_with_auth(&mut bob, &mut bob_decoder, encoding, "bob", password());
+    assert_eq!(None, login_result.error);
+    for (message, message_id) in ["0", "1"].into_iter().zip(message_ids) {
+        match read_frame(&mut bob, &mut bob_decoder) {
+            Frame::PrivateChat(private_chat_message) => {
+                assert_eq!("alice", private_chat_message.from_user_name);
+                assert_eq!(message, private_chat_message.message);
+                assert_eq!(message_id, private_chat_message.message_id);
+            }
+            _ => panic!("Expected private chat message!"),
+        }
+    }
+
+    // the queue is empty again, online users get their messages right away
+    let result = send_private_message(&mut alice, &mut alice_decoder, encoding, "bob", "5");
+    assert_eq!(DeliveryStatus::Delivered, result.status);
+    match read_frame(&mut bob, &mut bob_decoder) {
+        Frame::PrivateChat(private_chat_message) => assert_eq!("5", private_chat_message.message),
+        _ => panic!("Expected private chat message!"),
+    }
+
+    // the second server delivers the queue of the first one
+    drop(server);
+    let server = TestServer::start(
+        encoding,
+        ServerConfig {
+            offline_messages_path: Some(offline_messages_path.clone()),
+            credentials_path: Some(credentials_path.clone()),
+            ..ServerConfig::default()
+        },
+    );
+
+    let mut carol = server.connect(encoding);
+    let mut carol_decoder = Decoder::default();
+    let password = Some(Auth::Password("correct horse".to_string()));
+    let login_result = login_with_auth(&mut carol, &mut carol_decoder, encoding, "carol", password);
+    assert_eq!(None, login_result.error);
+    let queued_message_id = match read_frame(&mut carol, &mut carol_decoder) {
+        Frame::PrivateChat(private_chat_message) => {
+            assert_eq!("alice", private_chat_message.from_user_name);
+            assert_eq!("4", private_chat_message.message);
+            private_chat_message.message_id
+        }
+        _ => panic!("Expected private chat message!"),
+    };
+
+    // without history the ids go on after the queued messages
+    let publish_global_chat_message = PublishGlobalChatMessage {
+        message: "5".to_string(),
+    };
+    write_message(
+        &mut carol,
+        encoding,
+        Message::new(publish_global_chat_message, encoding.codec),
+    );
+    match read_frame(&mut carol, &mut carol_decoder) {
+        Frame::GlobalChat(global_chat_message) => {
+            assert!(global_chat_message.message_id > queued_message_id)
+        }
+        _ => panic!("Expected global chat message!"),
+    }
+
+    let _ = fs::remove_file(offline_messages_path);
+    let _ = fs::remove_file(credentials_path);
+}
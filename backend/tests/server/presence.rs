@@ -0,0 +1,201 @@
+use crate::common::{
+    hello, login, read_error_code, read_frame, read_room_message, room_action, write_message,
+    TestServer, HANDSHAKE_ENCODING,
+};
+use rust_chat::net::config::ServerConfig;
+use rust_chat_protocol::{
+    frame::Frame,
+    framing::Decoder,
+    message::Message,
+    messages::{
+        Capability, Conversation, DeliveryStatus, ErrorCode, JoinRoomMessage, ListUsersMessage,
+        PublishPrivateChatMessage, PublishRoomMessage, ReadMessage, TypingMessage,
+    },
+};
+
+#[test]
+fn presence() {
+    let encoding = HANDSHAKE_ENCODING;
+    let server = TestServer::start(encoding, ServerConfig::default());
+
+    let mut alice = server.connect(encoding);
+    let mut alice_decoder = Decoder::default();
+    let welcome_message = hello(&mut alice, encoding, vec![Capability::Presence]);
+    assert_eq!(vec![Capability::Presence], welcome_message.capabilities);
+
+    // clients without the capability get no presence
+    let mut bob = server.connect(encoding);
+    let mut bob_decoder = Decoder::default();
+
+    assert_eq!(
+        None,
+        login(&mut alice, &mut alice_decoder, encoding, "alice")
+    );
+    match read_frame(&mut alice, &mut alice_decoder) {
+        Frame::UserJoined(user_joined_message) => {
+            assert_eq!("alice", user_joined_message.user_name)
+        }
+        _ => panic!("Expected user joined message!"),
+    }
+
+    assert_eq!(None, login(&mut bob, &mut bob_decoder, encoding, "bob"));
+    match read_frame(&mut alice, &mut alice_decoder) {
+        Frame::UserJoined(user_joined_message) => assert_eq!("bob", user_joined_message.user_name),
+        _ => panic!("Expected user joined message!"),
+    }
+
+    write_message(
+        &mut bob,
+        encoding,
+        Message::new(ListUsersMessage {}, encoding.codec),
+    );
+    match read_frame(&mut bob, &mut bob_decoder) {
+        Frame::UserList(user_list_message) => {
+            assert_eq!(vec!["alice", "bob"], user_list_message.user_names)
+        }
+        _ => panic!("Expected user list message!"),
+    }
+
+    drop(bob);
+    match read_frame(&mut alice, &mut alice_decoder) {
+        Frame::UserLeft(user_left_message) => assert_eq!("bob", user_left_message.user_name),
+        _ => panic!("Expected user left message!"),
+    }
+}
+
+#[test]
+fn typing() {
+    let encoding = HANDSHAKE_ENCODING;
+    let server = TestServer::start(encoding, ServerConfig::default());
+
+    let typing = |to: Conversation| Message::new(TypingMessage { to }, encoding.codec);
+
+    let mut alice = server.connect(encoding);
+    let mut alice_decoder = Decoder::default();
+    hello(&mut alice, encoding, vec![Capability::Typing]);
+    let mut bob = server.connect(encoding);
+    let mut bob_decoder = Decoder::default();
+    hello(&mut bob, encoding, vec![Capability::Typing]);
+    // clients without the capability get no indicators
+    let mut carol = server.connect(encoding);
+    let mut carol_decoder = Decoder::default();
+
+    for (stream, decoder, user_name) in [
+        (&mut alice, &mut alice_decoder, "alice"),
+        (&mut bob, &mut bob_decoder, "bob"),
+        (&mut carol, &mut carol_decoder, "carol"),
+    ] {
+        assert_eq!(None, login(stream, decoder, encoding, user_name));
+        let join_room_message = JoinRoomMessage {
+            room: "lobby".to_string(),
+        };
+        assert_eq!(
+            None,
+            room_action(stream, decoder, encoding, join_room_message)
+        );
+    }
+
+    // only to the other members with the capability
+    write_message(
+        &mut alice,
+        encoding,
+        typing(Conversation::Room("lobby".to_string())),
+    );
+    match read_frame(&mut bob, &mut bob_decoder) {
+        Frame::UserTyping(user_typing_message) => {
+            assert_eq!("alice", user_typing_message.user_name);
+            assert_eq!(Some("lobby".to_string()), user_typing_message.room);
+        }
+        _ => panic!("Expected user typing message!"),
+    }
+
+    // too soon after the last indicator, dropped
+    write_message(
+        &mut alice,
+        encoding,
+        typing(Conversation::Room("lobby".to_string())),
+    );
+    let publish_room_message = PublishRoomMessage {
+        room: "lobby".to_string(),
+        message: "hi".to_string(),
+    };
+    write_message(
+        &mut alice,
+        encoding,
+        Message::new(publish_room_message, encoding.codec),
+    );
+    for (stream, decoder) in [
+        (&mut alice, &mut alice_decoder),
+        (&mut bob, &mut bob_decoder),
+        (&mut carol, &mut carol_decoder),
+    ] {
+        assert_eq!(
+            ("lobby".to_string(), "hi".to_string()),
+            read_room_message(stream, decoder)
+        );
+    }
+
+    write_message(
+        &mut bob,
+        encoding,
+        typing(Conversation::User("alice".to_string())),
+    );
+    match read_frame(&mut alice, &mut alice_decoder) {
+        Frame::UserTyping(user_typing_message) => {
+            assert_eq!("bob", user_typing_message.user_name);
+            assert_eq!(None, user_typing_message.room);
+        }
+        _ => panic!("Expected user typing message!"),
+    }
+
+    write_message(
+        &mut carol,
+        encoding,
+        typing(Conversation::User("dave".to_string())),
+    );
+    assert_eq!(
+        ErrorCode::UnknownUser,
+        read_error_code(&mut carol, &mut carol_decoder)
+    );
+
+    // read receipts for private messages
+    let publish_private_chat_message = PublishPrivateChatMessage {
+        to_user_name: "bob".to_string(),
+        message: "hello".to_string(),
+    };
+    write_message(
+        &mut alice,
+        encoding,
+        Message::new(publish_private_chat_message, encoding.codec),
+    );
+    let message_id = match read_frame(&mut bob, &mut bob_decoder) {
+        Frame::PrivateChat(private_chat_message) => {
+            assert_eq!("alice", private_chat_message.from_user_name);
+            private_chat_message.message_id
+        }
+        _ => panic!("Expected private chat message!"),
+    };
+    match read_frame(&mut alice, &mut alice_decoder) {
+        Frame::PrivateChatResult(private_chat_result_message) => {
+            assert_eq!(
+                DeliveryStatus::Delivered,
+                private_chat_result_message.status
+            );
+            assert_eq!(Some(message_id), private_chat_result_message.message_id);
+        }
+        _ => panic!("Expected private chat result message!"),
+    }
+
+    write_message(
+        &mut bob,
+        encoding,
+        Message::new(ReadMessage { message_id }, encoding.codec),
+    );
+    match read_frame(&mut alice, &mut alice_decoder) {
+        Frame::MessageRead(message_read_message) => {
+            assert_eq!(message_id, message_read_message.message_id);
+            assert_eq!("bob", message_read_message.user_name);
+        }
+        _ => panic!("Expected message read message!"),
+    }
+}
@@ -0,0 +1,460 @@
+use crate::common::{
+    list_rooms, login, login_with_auth, moderate, read_error_code, read_frame, read_room_message,
+    room_action, write_message, TestServer, TestStream, HANDSHAKE_ENCODING,
+};
+use rust_chat::net::config::ServerConfig;
+use rust_chat_protocol::{
+    frame::Frame,
+    framing::Decoder,
+    message::Message,
+    messages::{
+        Auth, ErrorCode, JoinRoomMessage, LeaveRoomMessage, ModerationAction, ModerationError,
+        PublishRoomMessage, Role, RoomError, RoomInfo, SetTopicMessage,
+    },
+};
+use std::{io::Read, thread, time::Duration};
+
+#[test]
+fn rooms() {
+    let encoding = HANDSHAKE_ENCODING;
+    let server = TestServer::start(encoding, ServerConfig::default());
+
+    let join = |room: &str| JoinRoomMessage {
+        room: room.to_string(),
+    };
+    let leave = |room: &str| LeaveRoomMessage {
+        room: room.to_string(),
+    };
+    let publish = |room: &str, message: &str| {
+        Message::new(
+            PublishRoomMessage {
+                room: room.to_string(),
+                message: message.to_string(),
+            },
+            encoding.codec,
+        )
+    };
+
+    let mut alice = server.connect(encoding);
+    let mut alice_decoder = Decoder::default();
+    let mut bob = server.connect(encoding);
+    let mut bob_decoder = Decoder::default();
+    let mut carol = server.connect(encoding);
+    let mut carol_decoder = Decoder::default();
+
+    // rooms need a login
+    write_message(
+        &mut alice,
+        encoding,
+        Message::new(join("lobby"), encoding.codec),
+    );
+    match read_frame(&mut alice, &mut alice_decoder) {
+        Frame::Error(error_message) => assert_eq!(ErrorCode::NotLoggedIn, error_message.code),
+        _ => panic!("Expected error message!"),
+    }
+
+    assert_eq!(
+        None,
+        login(&mut alice, &mut alice_decoder, encoding, "alice")
+    );
+    assert_eq!(None, login(&mut bob, &mut bob_decoder, encoding, "bob"));
+    assert_eq!(
+        None,
+        login(&mut carol, &mut carol_decoder, encoding, "carol")
+    );
+
+    // only members can publish
+    write_message(&mut alice, encoding, publish("lobby", "too early"));
+    match read_frame(&mut alice, &mut alice_decoder) {
+        Frame::Error(error_message) => assert_eq!(ErrorCode::NotInRoom, error_message.code),
+        _ => panic!("Expected error message!"),
+    }
+
+    assert_eq!(
+        Some(RoomError::InvalidName),
+        room_action(
+            &mut alice,
+            &mut alice_decoder,
+            encoding,
+            join("white space")
+        )
+    );
+    assert_eq!(
+        None,
+        room_action(&mut alice, &mut alice_decoder, encoding, join("lobby"))
+    );
+    assert_eq!(
+        Some(RoomError::AlreadyMember),
+        room_action(&mut alice, &mut alice_decoder, encoding, join("lobby"))
+    );
+    assert_eq!(
+        None,
+        room_action(&mut bob, &mut bob_decoder, encoding, join("lobby"))
+    );
+    assert_eq!(
+        None,
+        room_action(&mut carol, &mut carol_decoder, encoding, join("other"))
+    );
+
+    assert_eq!(
+        vec![
+            RoomInfo {
+                name: "lobby".to_string(),
+                members: 2,
+            },
+            RoomInfo {
+                name: "other".to_string(),
+                members: 1,
+            },
+        ],
+        list_rooms(&mut carol, &mut carol_decoder, encoding)
+    );
+
+    // the members get the message, including the author
+    write_message(&mut alice, encoding, publish("lobby", "hello"));
+    assert_eq!(
+        ("lobby".to_string(), "hello".to_string()),
+        read_room_message(&mut alice, &mut alice_decoder)
+    );
+    assert_eq!(
+        ("lobby".to_string(), "hello".to_string()),
+        read_room_message(&mut bob, &mut bob_decoder)
+    );
+
+    // carol is not a member, the first room message she gets is the one of her room
+    write_message(&mut carol, encoding, publish("other", "hi"));
+    assert_eq!(
+        ("other".to_string(), "hi".to_string()),
+        read_room_message(&mut carol, &mut carol_decoder)
+    );
+
+    assert_eq!(
+        None,
+        room_action(&mut bob, &mut bob_decoder, encoding, leave("lobby"))
+    );
+    assert_eq!(
+        Some(RoomError::NotMember),
+        room_action(&mut bob, &mut bob_decoder, encoding, leave("lobby"))
+    );
+
+    // the room is removed with its last member
+    drop(alice);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(
+        vec![RoomInfo {
+            name: "other".to_string(),
+            members: 1,
+        }],
+        list_rooms(&mut bob, &mut bob_decoder, encoding)
+    );
+}
+
+#[test]
+fn moderation() {
+    let encoding = HANDSHAKE_ENCODING;
+    let server = TestServer::start(
+        encoding,
+        ServerConfig {
+            operators: vec!["root".to_string()],
+            ..ServerConfig::default()
+        },
+    );
+
+    let join = |room: &str| JoinRoomMessage {
+        room: room.to_string(),
+    };
+    let publish = |message: &str| {
+        Message::new(
+            PublishRoomMessage {
+                room: "lobby".to_string(),
+                message: message.to_string(),
+            },
+            encoding.codec,
+        )
+    };
+    let read_moderated =
+        |stream: &mut TestStream, decoder: &mut Decoder| match read_frame(stream, decoder) {
+            Frame::Moderated(moderated_message) => moderated_message.action,
+            _ => panic!("Expected moderated message!"),
+        };
+
+    let mut alice = server.connect(encoding);
+    let mut alice_decoder = Decoder::default();
+    let mut bob = server.connect(encoding);
+    let mut bob_decoder = Decoder::default();
+    let mut carol = server.connect(encoding);
+    let mut carol_decoder = Decoder::default();
+
+    // roles need an account, carol is a guest
+    let password = || Some(Auth::Password("correct horse".to_string()));
+    let login_result = login_with_auth(
+        &mut alice,
+        &mut alice_decoder,
+        encoding,
+        "alice",
+        password(),
+    );
+    assert_eq!(None, login_result.error);
+    let login_result = login_with_auth(&mut bob, &mut bob_decoder, encoding, "bob", password());
+    assert_eq!(None, login_result.error);
+    assert_eq!(
+        None,
+        login(&mut carol, &mut carol_decoder, encoding, "carol")
+    );
+
+    // a guest creating a room does not own it
+    assert_eq!(
+        None,
+        room_action(&mut carol, &mut carol_decoder, encoding, join("guests"))
+    );
+    assert_eq!(
+        None,
+        room_action(&mut bob, &mut bob_decoder, encoding, join("guests"))
+    );
+    assert_eq!(
+        Some(ModerationError::NotPermitted),
+        moderate(
+            &mut carol,
+            &mut carol_decoder,
+            encoding,
+            Some("guests"),
+            "bob",
+            ModerationAction::Kick
+        )
+    );
+
+    // alice creates the room and owns it
+    for (stream, decoder) in [
+        (&mut alice, &mut alice_decoder),
+        (&mut bob, &mut bob_decoder),
+        (&mut carol, &mut carol_decoder),
+    ] {
+        assert_eq!(None, room_action(stream, decoder, encoding, join("lobby")));
+    }
+
+    // members can not moderate
+    assert_eq!(
+        Some(ModerationError::NotPermitted),
+        moderate(
+            &mut bob,
+            &mut bob_decoder,
+            encoding,
+            Some("lobby"),
+            "carol",
+            ModerationAction::Kick
+        )
+    );
+
+    assert_eq!(
+        None,
+        moderate(
+            &mut alice,
+            &mut alice_decoder,
+            encoding,
+            Some("lobby"),
+            "bob",
+            ModerationAction::SetRole(Role::Operator)
+        )
+    );
+    assert_eq!(
+        ModerationAction::SetRole(Role::Operator),
+        read_moderated(&mut bob, &mut bob_decoder)
+    );
+
+    // operators moderate members, but not the owner
+    assert_eq!(
+        Some(ModerationError::NotPermitted),
+        moderate(
+            &mut bob,
+            &mut bob_decoder,
+            encoding,
+            Some("lobby"),
+            "alice",
+            ModerationAction::Kick
+        )
+    );
+
+    assert_eq!(
+        None,
+        moderate(
+            &mut bob,
+            &mut bob_decoder,
+            encoding,
+            Some("lobby"),
+            "carol",
+            ModerationAction::Mute
+        )
+    );
+    assert_eq!(
+        ModerationAction::Mute,
+        read_moderated(&mut carol, &mut carol_decoder)
+    );
+    write_message(&mut carol, encoding, publish("muted"));
+    assert_eq!(
+        ErrorCode::Muted,
+        read_error_code(&mut carol, &mut carol_decoder)
+    );
+
+    // the topic goes to all members
+    let set_topic_message = SetTopicMessage {
+        room: "lobby".to_string(),
+        topic: "moderated".to_string(),
+    };
+    write_message(
+        &mut carol,
+        encoding,
+        Message::new(set_topic_message.clone(), encoding.codec),
+    );
+    assert_eq!(
+        ErrorCode::NotPermitted,
+        read_error_code(&mut carol, &mut carol_decoder)
+    );
+    write_message(
+        &mut bob,
+        encoding,
+        Message::new(set_topic_message, encoding.codec),
+    );
+    for (stream, decoder) in [
+        (&mut alice, &mut alice_decoder),
+        (&mut bob, &mut bob_decoder),
+        (&mut carol, &mut carol_decoder),
+    ] {
+        match read_frame(stream, decoder) {
+            Frame::RoomTopic(room_topic_message) => {
+                assert_eq!("moderated", room_topic_message.topic)
+            }
+            _ => panic!("Expected room topic message!"),
+        }
+    }
+
+    // a kicked user is no member anymore, a banned user can not join again
+    assert_eq!(
+        None,
+        moderate(
+            &mut bob,
+            &mut bob_decoder,
+            encoding,
+            Some("lobby"),
+            "carol",
+            ModerationAction::Kick
+        )
+    );
+    assert_eq!(
+        ModerationAction::Kick,
+        read_moderated(&mut carol, &mut carol_decoder)
+    );
+    write_message(&mut carol, encoding, publish("kicked"));
+    assert_eq!(
+        ErrorCode::NotInRoom,
+        read_error_code(&mut carol, &mut carol_decoder)
+    );
+
+    assert_eq!(
+        None,
+        moderate(
+            &mut bob,
+            &mut bob_decoder,
+            encoding,
+            Some("lobby"),
+            "carol",
+            ModerationAction::Ban
+        )
+    );
+    assert_eq!(
+        ModerationAction::Ban,
+        read_moderated(&mut carol, &mut carol_decoder)
+    );
+    assert_eq!(
+        Some(RoomError::Banned),
+        room_action(&mut carol, &mut carol_decoder, encoding, join("lobby"))
+    );
+
+    assert_eq!(
+        None,
+        moderate(
+            &mut bob,
+            &mut bob_decoder,
+            encoding,
+            Some("lobby"),
+            "carol",
+            ModerationAction::Unban
+        )
+    );
+    assert_eq!(
+        ModerationAction::Unban,
+        read_moderated(&mut carol, &mut carol_decoder)
+    );
+    assert_eq!(
+        None,
+        room_action(&mut carol, &mut carol_decoder, encoding, join("lobby"))
+    );
+    match read_frame(&mut carol, &mut carol_decoder) {
+        Frame::RoomTopic(room_topic_message) => assert_eq!("moderated", room_topic_message.topic),
+        _ => panic!("Expected room topic message!"),
+    }
+
+    // only global operators moderate the whole server
+    assert_eq!(
+        Some(ModerationError::NotPermitted),
+        moderate(
+            &mut alice,
+            &mut alice_decoder,
+            encoding,
+            None,
+            "carol",
+            ModerationAction::Kick
+        )
+    );
+
+    let mut root = server.connect(encoding);
+    let mut root_decoder = Decoder::default();
+    assert_eq!(
+        None,
+        login_with_auth(
+            &mut root,
+            &mut root_decoder,
+            encoding,
+            "root",
+            Some(Auth::Password("correct horse".to_string()))
+        )
+        .error
+    );
+    assert_eq!(
+        Some(ModerationError::NotPermitted),
+        moderate(
+            &mut alice,
+            &mut alice_decoder,
+            encoding,
+            Some("lobby"),
+            "root",
+            ModerationAction::Mute
+        )
+    );
+
+    // a ban on the whole server closes the connection and rejects the address
+    assert_eq!(
+        None,
+        moderate(
+            &mut root,
+            &mut root_decoder,
+            encoding,
+            None,
+            "carol",
+            ModerationAction::Ban
+        )
+    );
+    assert_eq!(
+        ModerationAction::Ban,
+        read_moderated(&mut carol, &mut carol_decoder)
+    );
+    assert!(matches!(
+        carol.read(carol_decoder.unfilled()),
+        Ok(0) | Err(_)
+    ));
+
+    let mut banned = server.connect(encoding);
+    assert!(matches!(
+        banned.read(Decoder::default().unfilled()),
+        Ok(0) | Err(_)
+    ));
+}
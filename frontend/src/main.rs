@@ -1,8 +1,7 @@
 mod net;
 
 use crate::net::client::{Client, ClientStop};
-use crate::net::message::Message;
-use crate::net::messages::{LoginMessage, PublishGlobalChatMessage, PublishPrivateChatMessage};
+use crate::net::transport::TlsConnector;
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
     execute,
@@ -10,9 +9,17 @@ use crossterm::{
         disable_raw_mode, enable_raw_mode, size, EnterAlternateScreen, LeaveAlternateScreen,
     },
 };
+use rust_chat_protocol::messages::{
+    Auth, Conversation, DeleteMessage, DeliveryStatus, EditMessage, FetchHistoryMessage,
+    HistoryScope, JoinRoomMessage, LeaveRoomMessage, ListRoomsMessage, ListUsersMessage,
+    LoginMessage, ModerationAction, ModerationMessage, PublishGlobalChatMessage,
+    PublishPrivateChatMessage, PublishRoomMessage, Role, SetTopicMessage, TypingMessage,
+};
+use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::mpsc::{channel, Receiver};
-use std::time::Duration;
-use std::{error::Error, io};
+use std::time::{Duration, Instant};
+use std::{env, error::Error, io, path::Path, process};
 use tui::{
     backend::{Backend, CrosstermBackend},
     layout::{Constraint, Direction, Layout},
@@ -22,18 +29,51 @@ use tui::{
     Frame, Terminal,
 };
 
+/// Messages per `history` command.
+const HISTORY_LIMIT: usize = 50;
+/// Typing indicators are sent at most once per this duration while typing.
+const TYPING_INTERVAL: Duration = Duration::from_secs(3);
+/// Typing indicators of others are shown this long.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Clone)]
 enum InputMode {
     Normal,
     Editing,
     Username,
+    Password,
     PrivateUsername,
     PrivateMessaging,
 }
 
+/// Id of a chat message and where its text starts in the line, edits replace the text.
+#[derive(Clone, Copy)]
+pub struct ChatText {
+    pub message_id: u64,
+    pub start: usize,
+}
+
 pub enum MessageType {
-    Public,
-    Private,
+    Public(Option<ChatText>),
+    Private(Option<ChatText>),
+    Room(Option<ChatText>),
+    Presence,
+    Error,
+    /// Shown in the title of the messages for `TYPING_TIMEOUT`.
+    Typing,
+    /// Result of the oldest own private message to the user, with id if it was delivered or
+    /// queued for the offline user.
+    PrivateResult {
+        to_user_name: String,
+        status: DeliveryStatus,
+        message_id: Option<u64>,
+    },
+    /// The own private message with the id was read.
+    PrivateRead(u64),
+    /// The chat message with the id has the text of the console message now.
+    Edited(u64),
+    /// The chat message with the id was deleted.
+    Deleted(u64),
 }
 
 type ConsoleMessage = (MessageType, String);
@@ -45,6 +85,13 @@ struct App {
     input_mode: InputMode,
     /// History of recorded messages
     console_messages: Vec<ConsoleMessage>,
+    /// Index in `console_messages`, recipient and id of own private messages, the id is known
+    /// once the message is delivered or queued. Removed once read.
+    sent_private_messages: Vec<(usize, String, Option<u64>)>,
+    /// Last typing indicator of another user and when it was received
+    typing: Option<(String, Instant)>,
+    /// Message id -> index in `console_messages` and range of the text in the line
+    chat_texts: HashMap<u64, (usize, Range<usize>)>,
 }
 
 impl Default for App {
@@ -53,11 +100,141 @@ impl Default for App {
             input: String::new(),
             input_mode: InputMode::Username,
             console_messages: Vec::new(),
+            sent_private_messages: Vec::new(),
+            typing: None,
+            chat_texts: HashMap::new(),
+        }
+    }
+}
+
+impl App {
+    /// Shows an own private message, it is marked once delivered or queued and again once read.
+    fn sent_private_message(&mut self, to_user_name: &str, message: &str) {
+        self.sent_private_messages.push((
+            self.console_messages.len(),
+            to_user_name.to_string(),
+            None,
+        ));
+        self.console_messages.push((
+            MessageType::Private(None),
+            format!("{}{}", sent_prefix(to_user_name), message),
+        ));
+    }
+
+    /// Replaces the text of the chat message, the rest of the line stays.
+    fn replace_text(&mut self, message_id: u64, text: &str) {
+        if let Some((index, range)) = self.chat_texts.get_mut(&message_id) {
+            self.console_messages[*index]
+                .1
+                .replace_range(range.clone(), text);
+            range.end = range.start + text.len();
+        }
+    }
+
+    fn receive(&mut self, console_message: ConsoleMessage) {
+        match console_message.0 {
+            MessageType::Typing => self.typing = Some((console_message.1, Instant::now())),
+            MessageType::PrivateResult {
+                to_user_name,
+                status,
+                message_id,
+            } => {
+                // the results arrive in the order the messages were sent
+                let index = self
+                    .sent_private_messages
+                    .iter()
+                    .position(|(_, to, id)| *to == to_user_name && id.is_none());
+                match (index, message_id) {
+                    (Some(index), Some(message_id)) => {
+                        let sent = &mut self.sent_private_messages[index];
+                        sent.2 = Some(message_id);
+
+                        // the id is shown to edit the message
+                        let line = &mut self.console_messages[sent.0].1;
+                        let id = format!("[#{}] ", message_id);
+                        line.insert_str(0, &id);
+                        let start = id.len() + sent_prefix(&to_user_name).len();
+                        self.chat_texts
+                            .insert(message_id, (sent.0, start..line.len()));
+
+                        let mark = match status {
+                            DeliveryStatus::Deferred => " (queued) ",
+                            _ => " ✓",
+                        };
+                        line.push_str(mark);
+                    }
+                    (Some(index), None) => {
+                        self.sent_private_messages.remove(index);
+                    }
+                    (None, _) => {}
+                }
+            }
+            MessageType::PrivateRead(message_id) => {
+                if let Some(index) = self
+                    .sent_private_messages
+                    .iter()
+                    .position(|(_, _, id)| *id == Some(message_id))
+                {
+                    let (console_index, _, _) = self.sent_private_messages.remove(index);
+                    self.console_messages[console_index].1.push('✓');
+                }
+            }
+            MessageType::Edited(message_id) => {
+                self.replace_text(message_id, &format!("{} (edited)", console_message.1))
+            }
+            MessageType::Deleted(message_id) => {
+                self.replace_text(message_id, "[deleted]");
+                self.chat_texts.remove(&message_id);
+            }
+            _ => {
+                if let MessageType::Public(Some(text))
+                | MessageType::Private(Some(text))
+                | MessageType::Room(Some(text)) = console_message.0
+                {
+                    let range = text.start..console_message.1.len();
+                    self.chat_texts
+                        .insert(text.message_id, (self.console_messages.len(), range));
+                }
+                self.console_messages.push(console_message)
+            }
+        }
+    }
+
+    /// Where the user is typing, `None` for global messages and commands.
+    fn conversation(&self, private_username: &str) -> Option<Conversation> {
+        match self.input_mode {
+            InputMode::PrivateMessaging => Some(Conversation::User(private_username.to_string())),
+            InputMode::Editing => match self.input.splitn(3, ' ').collect::<Vec<&str>>()[..] {
+                ["room", room, _] => Some(Conversation::Room(room.to_string())),
+                ["private", user_name, _] => Some(Conversation::User(user_name.to_string())),
+                _ => None,
+            },
+            _ => None,
         }
     }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    // rust_chat_client [--tls <ca.pem> [<server name>]]
+    let args: Vec<String> = env::args().collect();
+    let tls_connector = match args.get(1).map(String::as_str) {
+        Some("--tls") if (3..=4).contains(&args.len()) => {
+            let server_name = args.get(3).map_or("localhost", String::as_str);
+            match TlsConnector::load(Path::new(&args[2]), server_name) {
+                Ok(tls_connector) => Some(tls_connector),
+                Err(err) => {
+                    eprintln!("Error while loading certificate: {}", err);
+                    process::exit(1);
+                }
+            }
+        }
+        None => None,
+        _ => {
+            eprintln!("Usage: rust_chat_client [--tls <ca.pem> [<server name>]]");
+            process::exit(1);
+        }
+    };
+
     // setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -67,7 +244,7 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     // create app and run it
     let app = App::default();
-    let res = run_app(&mut terminal, app);
+    let res = run_app(&mut terminal, app, tls_connector.as_ref());
 
     // restore terminal
     disable_raw_mode()?;
@@ -85,16 +262,22 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<()> {
+fn run_app<B: Backend>(
+    terminal: &mut Terminal<B>,
+    mut app: App,
+    tls_connector: Option<&TlsConnector>,
+) -> io::Result<()> {
     let address = "127.0.0.1:4444"
         .parse()
         .expect("Error while parsing address!");
 
     let (console_message_sender, console_message_receiver) = channel::<ConsoleMessage>();
 
-    let mut client = Client::new(address, console_message_sender);
+    let mut client = Client::new(address, tls_connector, console_message_sender);
     let client_stop: ClientStop = client.get_client_stop();
     let mut private_username: String = String::new();
+    let mut username: String = String::new();
+    let mut last_typing: Option<Instant> = None;
 
     print!("\x1B[2J\x1B[1;1H");
 
@@ -114,8 +297,13 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
                             return Ok(());
                         }
                         KeyCode::Char('p') => {
+                            // show who can be messaged
+                            client.send_message(ListUsersMessage {});
                             app.input_mode = InputMode::PrivateUsername;
                         }
+                        KeyCode::Char('l') => {
+                            app.input_mode = InputMode::Username;
+                        }
                         _ => {}
                     },
                     InputMode::Editing => match key.code {
@@ -129,23 +317,58 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
                                     to_user_name: split[1].to_string(),
                                     message: split[2..].join(" "),
                                 };
-                                client.send_message(Message::new(private_chat_message));
-
-                                app.console_messages.push((
-                                    MessageType::Private,
-                                    format!(
-                                        "[PRIVATE] [ME -> {}] {}",
-                                        split[1].to_string(),
-                                        split[2..].join(" ")
-                                    ),
-                                ));
+                                client.send_message(private_chat_message);
+
+                                app.sent_private_message(split[1], &split[2..].join(" "));
+                            } else if let Some(room) = message.strip_prefix("join ") {
+                                client.send_message(JoinRoomMessage {
+                                    room: room.to_string(),
+                                });
+                            } else if let Some(room) = message.strip_prefix("leave ") {
+                                client.send_message(LeaveRoomMessage {
+                                    room: room.to_string(),
+                                });
+                            } else if let Some(moderation_message) = parse_moderation(&message) {
+                                client.send_message(moderation_message);
+                            } else if message.starts_with("topic ") {
+                                let split = message.split(" ").collect::<Vec<&str>>();
+
+                                let set_topic_message = SetTopicMessage {
+                                    room: split[1].to_string(),
+                                    topic: split[2..].join(" "),
+                                };
+                                client.send_message(set_topic_message);
+                            } else if let Some(edit_message) = parse_edit(&message) {
+                                client.send_message(edit_message);
+                            } else if let Some(delete_message) = parse_delete(&message) {
+                                client.send_message(delete_message);
+                            } else if let Some(scope) = parse_history_scope(&message) {
+                                client.send_message(FetchHistoryMessage {
+                                    scope,
+                                    before_id: None,
+                                    limit: HISTORY_LIMIT,
+                                });
+                            } else if message == "users" {
+                                client.send_message(ListUsersMessage {});
+                            } else if message == "rooms" {
+                                client.send_message(ListRoomsMessage {});
+                            } else if message.starts_with("room ") {
+                                let split = message.split(" ").collect::<Vec<&str>>();
+
+                                // the server sends the message back to all members
+                                let room_message = PublishRoomMessage {
+                                    room: split[1].to_string(),
+                                    message: split[2..].join(" "),
+                                };
+                                client.send_message(room_message);
                             } else {
                                 let global_chat_message = PublishGlobalChatMessage { message };
-                                client.send_message(Message::new(global_chat_message));
+                                client.send_message(global_chat_message);
                             }
                         }
                         KeyCode::Char(c) => {
                             app.input.push(c);
+                            send_typing(&mut client, &app, &private_username, &mut last_typing);
                         }
                         KeyCode::Backspace => {
                             app.input.pop();
@@ -157,10 +380,27 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
                     },
                     InputMode::Username => match key.code {
                         KeyCode::Enter => {
-                            let name = app.input.drain(..).collect();
+                            username = app.input.drain(..).collect();
+                            app.input_mode = InputMode::Password;
+                        }
+                        KeyCode::Char(c) => {
+                            app.input.push(c);
+                        }
+                        KeyCode::Backspace => {
+                            app.input.pop();
+                        }
+                        _ => {}
+                    },
+                    InputMode::Password => match key.code {
+                        KeyCode::Enter => {
+                            let password: String = app.input.drain(..).collect();
 
-                            let login_message = LoginMessage { user_name: name };
-                            client.send_message(Message::new(login_message));
+                            // without password as guest
+                            let login_message = LoginMessage {
+                                user_name: username.clone(),
+                                auth: (!password.is_empty()).then_some(Auth::Password(password)),
+                            };
+                            client.send_message(login_message);
 
                             app.input_mode = InputMode::Normal;
                         }
@@ -199,19 +439,13 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
                                 to_user_name: split[1].to_string(),
                                 message: split[2..].join(" "),
                             };
-                            client.send_message(Message::new(private_chat_message));
-
-                            app.console_messages.push((
-                                MessageType::Private,
-                                format!(
-                                    "[PRIVATE] [ME -> {}] {}",
-                                    split[1].to_string(),
-                                    split[2..].join(" ")
-                                ),
-                            ));
+                            client.send_message(private_chat_message);
+
+                            app.sent_private_message(split[1], &split[2..].join(" "));
                         }
                         KeyCode::Char(c) => {
                             app.input.push(c);
+                            send_typing(&mut client, &app, &private_username, &mut last_typing);
                         }
                         KeyCode::Backspace => {
                             app.input.pop();
@@ -227,6 +461,84 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
     }
 }
 
+/// Tells the others that the user is typing, at most once per `TYPING_INTERVAL`.
+fn send_typing(
+    client: &mut Client,
+    app: &App,
+    private_username: &str,
+    last_typing: &mut Option<Instant>,
+) {
+    if last_typing.is_some_and(|last_typing| last_typing.elapsed() < TYPING_INTERVAL) {
+        return;
+    }
+
+    if let Some(to) = app.conversation(private_username) {
+        client.send_message(TypingMessage { to });
+        *last_typing = Some(Instant::now());
+    }
+}
+
+/// Start of the line of an own private message.
+fn sent_prefix(to_user_name: &str) -> String {
+    format!("[PRIVATE] [ME -> {}] ", to_user_name)
+}
+
+/// `edit <id> <text>`, the id is shown with the message.
+fn parse_edit(message: &str) -> Option<EditMessage> {
+    match message.splitn(3, ' ').collect::<Vec<&str>>()[..] {
+        ["edit", message_id, text] => Some(EditMessage {
+            message_id: message_id.parse().ok()?,
+            message: text.to_string(),
+        }),
+        _ => None,
+    }
+}
+
+/// `delete <id>`.
+fn parse_delete(message: &str) -> Option<DeleteMessage> {
+    match message.split(' ').collect::<Vec<&str>>()[..] {
+        ["delete", message_id] => Some(DeleteMessage {
+            message_id: message_id.parse().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+/// `history`, `history room <room>` or `history private <user>`.
+fn parse_history_scope(message: &str) -> Option<HistoryScope> {
+    match message.split(" ").collect::<Vec<&str>>()[..] {
+        ["history"] => Some(HistoryScope::Global),
+        ["history", "room", room] => Some(HistoryScope::Room(room.to_string())),
+        ["history", "private", user_name] => Some(HistoryScope::User(user_name.to_string())),
+        _ => None,
+    }
+}
+
+/// `<command> <user> [<room>]`, e.g. `kick alice lobby`, without room on the whole server.
+fn parse_moderation(message: &str) -> Option<ModerationMessage> {
+    let split = message.split(" ").collect::<Vec<&str>>();
+    if !(2..=3).contains(&split.len()) {
+        return None;
+    }
+
+    let action = match split[0] {
+        "kick" => ModerationAction::Kick,
+        "ban" => ModerationAction::Ban,
+        "unban" => ModerationAction::Unban,
+        "mute" => ModerationAction::Mute,
+        "unmute" => ModerationAction::Unmute,
+        "op" => ModerationAction::SetRole(Role::Operator),
+        "deop" => ModerationAction::SetRole(Role::Member),
+        _ => return None,
+    };
+
+    Some(ModerationMessage {
+        room: split.get(2).map(|room| room.to_string()),
+        user_name: split[1].to_string(),
+        action,
+    })
+}
+
 fn ui<B: Backend>(
     f: &mut Frame<B>,
     app: &mut App,
@@ -264,7 +576,9 @@ fn ui<B: Backend>(
                         .add_modifier(Modifier::BOLD)
                         .fg(Color::LightMagenta),
                 ),
-                Span::raw(" to start private messaging."),
+                Span::raw(" to start private messaging, "),
+                Span::styled("l", Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(" to login."),
             ],
             Style::default().add_modifier(Modifier::RAPID_BLINK),
         ),
@@ -292,6 +606,14 @@ fn ui<B: Backend>(
             ],
             Style::default(),
         ),
+        InputMode::Password => (
+            vec![
+                Span::raw("Enter "),
+                Span::styled("Password", Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(", empty to login as guest"),
+            ],
+            Style::default(),
+        ),
         InputMode::PrivateUsername => (
             vec![
                 Span::raw("Enter "),
@@ -328,11 +650,17 @@ fn ui<B: Backend>(
     let help_message = Paragraph::new(text);
     f.render_widget(help_message, chunks[0]);
 
-    let input = Paragraph::new(app.input.as_ref())
+    // the password is not shown
+    let input_text = match app.input_mode {
+        InputMode::Password => "*".repeat(app.input.len()),
+        _ => app.input.clone(),
+    };
+    let input = Paragraph::new(input_text)
         .style(match app.input_mode {
             InputMode::Normal => Style::default(),
             InputMode::Editing => Style::default().fg(Color::Yellow),
             InputMode::Username => Style::default().fg(Color::Green),
+            InputMode::Password => Style::default().fg(Color::Green),
             InputMode::PrivateUsername => Style::default().fg(Color::LightMagenta),
             InputMode::PrivateMessaging => Style::default().fg(Color::LightMagenta),
         })
@@ -352,7 +680,7 @@ fn ui<B: Backend>(
                 chunks[1].y + 1,
             )
         }
-        InputMode::Username => {
+        InputMode::Username | InputMode::Password => {
             // Make the cursor visible and ask tui-rs to put it at the specified coordinates after rendering
             f.set_cursor(
                 // Put cursor past the end of the input text
@@ -382,21 +710,28 @@ fn ui<B: Backend>(
     }
 
     for console_message in console_message_receiver.try_iter() {
-        app.console_messages.push(console_message);
+        app.receive(console_message);
     }
 
     let mut messages: Vec<ListItem> = app
         .console_messages
         .iter()
-        .enumerate()
-        .map(|(_i, m)| {
+        .map(|m| {
             let color = match m.0 {
-                MessageType::Public => Color::Yellow,
-                MessageType::Private => Color::LightMagenta,
+                MessageType::Public(_) => Color::Yellow,
+                MessageType::Private(_) => Color::LightMagenta,
+                MessageType::Room(_) => Color::LightGreen,
+                MessageType::Presence | MessageType::Typing => Color::Gray,
+                MessageType::Error => Color::Red,
+                // only change other messages
+                MessageType::PrivateResult { .. }
+                | MessageType::PrivateRead(_)
+                | MessageType::Edited(_)
+                | MessageType::Deleted(_) => Color::LightMagenta,
             };
 
             let content = vec![Spans::from(Span::styled(
-                format!("{}", m.1),
+                m.1.to_string(),
                 Style::default().fg(color),
             ))];
 
@@ -404,10 +739,7 @@ fn ui<B: Backend>(
         })
         .collect();
 
-    let mut size = match size() {
-        Ok(x) => x,
-        Err(_) => (0, 0),
-    };
+    let mut size = size().unwrap_or_default();
 
     if size.1 > 9 {
         size.1 -= 10;
@@ -417,8 +749,14 @@ fn ui<B: Backend>(
         messages.remove(0);
     }
 
+    let title = match &app.typing {
+        Some((typing, received)) if received.elapsed() < TYPING_TIMEOUT => {
+            format!("Messages - {}", typing)
+        }
+        _ => "Messages".to_string(),
+    };
     let messages = List::new(messages)
-        .block(Block::default().borders(Borders::ALL).title("Messages"))
+        .block(Block::default().borders(Borders::ALL).title(title))
         .style(Style::default().fg(Color::LightCyan));
     f.render_widget(messages, chunks[2]);
 }
@@ -1,7 +1,14 @@
 use crate::net::connection::Connection;
-use crate::net::message::Message;
+use crate::net::transport::{new_transport, TlsConnector};
 use crate::ConsoleMessage;
 use mio::{net::TcpStream, Events, Interest, Poll, Token, Waker};
+use rust_chat_protocol::{
+    codec::Codec,
+    compression::Compression,
+    frame::Frame,
+    framing::Framing,
+    messages::{Capability, HelloMessage, PROTOCOL_VERSION},
+};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Sender};
 use std::{
@@ -31,7 +38,7 @@ impl ClientStop {
     }
 
     pub fn should_stop(&self) -> bool {
-        return self.should_stop.load(Ordering::Relaxed);
+        self.should_stop.load(Ordering::Relaxed)
     }
 }
 
@@ -46,12 +53,17 @@ impl Clone for ClientStop {
 pub struct Client {
     thread_handle: JoinHandle<()>,
     waker: Waker,
-    message_sender: Sender<Message>,
+    message_sender: Sender<Frame>,
     client_stop: ClientStop,
 }
 
 impl Client {
-    pub fn new(address: SocketAddr, console_messages_sender: Sender<ConsoleMessage>) -> Self {
+    /// Encrypts the connection with TLS if `tls_connector` is set.
+    pub fn new(
+        address: SocketAddr,
+        tls_connector: Option<&TlsConnector>,
+        console_messages_sender: Sender<ConsoleMessage>,
+    ) -> Self {
         // Create a poll instance.
         let mut poll = Poll::new().expect("Error while creating poll!");
 
@@ -73,9 +85,11 @@ impl Client {
         poll.registry()
             .register(&mut tcp_stream, TOKEN, Interest::READABLE)
             .expect("Error while registering client!");
+        let transport =
+            new_transport(tcp_stream, tls_connector).expect("Error while starting TLS!");
 
         // create channel
-        let (message_sender, message_receiver) = channel::<Message>();
+        let (message_sender, message_receiver) = channel::<Frame>();
 
         let client_stop = ClientStop::new();
 
@@ -93,11 +107,27 @@ impl Client {
                     );
 
                     let mut connection = Connection::new(
-                        tcp_stream,
+                        transport,
                         Rc::clone(&registry),
                         TOKEN,
                         console_messages_sender,
                     );
+                    connection.send_hello(HelloMessage {
+                        version: PROTOCOL_VERSION,
+                        client_name: concat!("rust_chat_client ", env!("CARGO_PKG_VERSION"))
+                            .to_string(),
+                        capabilities: vec![
+                            Capability::Compression,
+                            Capability::Rooms,
+                            Capability::Presence,
+                            Capability::Typing,
+                            Capability::History,
+                            Capability::Edit,
+                        ],
+                        framings: vec![Framing::Binary, Framing::Ascii],
+                        codecs: vec![Codec::MessagePack, Codec::Cbor, Codec::Json],
+                        compressions: vec![Compression::Zstd, Compression::Deflate],
+                    });
 
                     loop {
                         let poll_result = poll.poll(&mut events, duration);
@@ -116,7 +146,9 @@ impl Client {
                                 WAKER_TOKEN => {
                                     // check for broadcast messages
                                     for message in message_receiver.try_iter() {
-                                        connection.send_message(message);
+                                        if connection.send_message(message) {
+                                            return;
+                                        }
                                     }
                                 }
                                 TOKEN => {
@@ -126,10 +158,8 @@ impl Client {
                                         remove_connection = connection.send();
                                     }
 
-                                    if !remove_connection {
-                                        if event.is_readable() {
-                                            remove_connection = connection.read();
-                                        }
+                                    if !remove_connection && event.is_readable() {
+                                        remove_connection = connection.read();
                                     }
 
                                     if remove_connection {
@@ -156,9 +186,9 @@ impl Client {
         }
     }
 
-    pub fn send_message(&mut self, message: Message) {
+    pub fn send_message<T: Into<Frame>>(&mut self, message: T) {
         self.message_sender
-            .send(message)
+            .send(message.into())
             .expect("Error while sending message!");
 
         self.waker.wake().expect("Error while wake!")
@@ -1,90 +1,63 @@
-use crate::net::message::{Message, MessageTrait};
-use crate::net::messages::{
-    GlobalChatMessage, LoginMessage, PingMessage, PrivateChatMessage, PublishGlobalChatMessage,
-    PublishPrivateChatMessage,
+use crate::net::message::MessageTrait;
+use crate::net::transport::Transport;
+use crate::{ConsoleMessage, MessageType};
+use mio::{Interest, Registry, Token};
+use rust_chat_protocol::codec::Codec;
+use rust_chat_protocol::error::{DecodeError, EncodeError};
+use rust_chat_protocol::frame::Frame;
+use rust_chat_protocol::framing::{Decoder, Encoder, DEFAULT_MAX_MESSAGE_SIZE};
+use rust_chat_protocol::message::Message;
+use rust_chat_protocol::messages::{
+    Capability, HelloMessage, WelcomeMessage, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
 };
-use crate::ConsoleMessage;
-use mio::{net::TcpStream, Interest, Registry, Token};
 use std::rc::Rc;
 use std::sync::mpsc::Sender;
 use std::{
     collections::VecDeque,
     io::{self, Read, Write},
-    str::from_utf8,
 };
 
-/*
-    Message Header:
-    bytes   name               description
-    8       magic number       Magic number to identify the application
-    3       message number     Message number to identify the message type
-    5       payload size       Payload size, maximum is MAX_PAYLOAD
-    total size: 16 bytes
-*/
-
-const MAGIC: &[u8; 8] = b"RustChat";
-const HEADER_SIZE: usize = 16;
-const MAX_PAYLOAD: usize = 1024;
-const MAX_PACKET_SIZE: usize = HEADER_SIZE + MAX_PAYLOAD;
-
-macro_rules! ProcessMessage {
-    ($message_struct:ident, $utf8_payload:expr, $connection:expr) => {
-        match serde_json::from_str::<$message_struct>($utf8_payload) {
-            Ok(message) => message.process($connection),
-            Err(_) => return false,
-        }
-    };
-}
-
-#[derive(PartialEq, Eq)]
-enum MessageDecodeState {
-    WaitingForHeader,
-    HeaderSuccessfulRead,
-    WaitingForPayload,
-    PayloadSuccessfulRead,
-}
-
 pub struct Connection {
-    pub tcp_stream: TcpStream,
+    transport: Transport,
     registry: Rc<Registry>,
     token: Token,
-    message_queue: VecDeque<Message>, // has no limit!!!
-    out_buffer: Box<[u8; MAX_PAYLOAD]>,
+    message_queue: VecDeque<Frame>, // has no limit!!!
+    out_buffer: Vec<u8>,
     out_buffer_pos: usize,
-    out_buffer_size: usize,
-    in_buffer: Box<[u8; MAX_PACKET_SIZE]>,
-    in_buffer_pos: usize,
+    encoder: Encoder,
+    decoder: Decoder,
+    codec: Codec,
     send_interest: bool,
-    // current message decode data/state begin
-    state: MessageDecodeState,
-    message_number: usize,
-    payload_size: usize,
-    // current message decode data/state end
+    /// Queued messages are held back until the `WelcomeMessage` arrived.
+    handshake_pending: bool,
+    /// Capabilities supported by client and server.
+    pub capabilities: Vec<Capability>,
+    closing: bool,
     pub console_message_sender: Sender<ConsoleMessage>,
 }
 
 impl Connection {
     pub fn new(
-        tcp_stream: TcpStream,
+        transport: Transport,
         registry: Rc<Registry>,
         token: Token,
         console_message_sender: Sender<ConsoleMessage>,
     ) -> Self {
         Self {
-            tcp_stream,
+            transport,
             registry,
             token,
             console_message_sender,
             message_queue: VecDeque::new(),
-            out_buffer: Box::new([0; MAX_PAYLOAD]),
+            out_buffer: Vec::new(),
             out_buffer_pos: 0,
-            out_buffer_size: 0,
-            in_buffer: Box::new([0; MAX_PACKET_SIZE]),
-            in_buffer_pos: 0,
+            encoder: Encoder::new(DEFAULT_MAX_MESSAGE_SIZE),
+            decoder: Decoder::new(DEFAULT_MAX_MESSAGE_SIZE),
+            codec: Codec::Json,
             send_interest: false,
-            state: MessageDecodeState::WaitingForHeader,
-            message_number: 0,
-            payload_size: 0,
+            handshake_pending: false,
+            capabilities: Vec::new(),
+            closing: false,
         }
     }
 
@@ -92,10 +65,7 @@ impl Connection {
     pub fn read(&mut self) -> bool {
         // We can (maybe) read from the connection.
         loop {
-            match self
-                .tcp_stream
-                .read(&mut self.in_buffer[self.in_buffer_pos..])
-            {
+            match self.transport.read(self.decoder.unfilled()) {
                 Ok(0) => {
                     // Reading 0 bytes means the other side has closed the
                     // connection or is done writing, then so are we.
@@ -103,169 +73,107 @@ impl Connection {
                     return true;
                 }
                 Ok(n) => {
-                    self.in_buffer_pos += n;
+                    self.decoder.filled(n);
 
-                    if !self.decode() {
-                        println!("Invalid data, closing connection...",);
+                    if let Err(err) = self.decode() {
+                        println!("Invalid data ({}), closing connection...", err);
+                        return true;
+                    }
+
+                    if self.closing {
                         return true;
                     }
                 }
                 // Would block "errors" are the OS's way of saying that the
                 // connection is not actually ready to perform this I/O operation.
-                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                // A TLS handshake may have to be answered.
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return self.send(),
                 Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
                 // Other errors we'll consider fatal.
                 Err(_) => return true,
             }
         }
-
-        return false;
-    }
-
-    fn decode(&mut self) -> bool {
-        loop {
-            if !self.process_states() {
-                return false;
-            }
-
-            if self.state != MessageDecodeState::PayloadSuccessfulRead {
-                break;
-            }
-        }
-
-        return true;
-    }
-
-    fn process_states(&mut self) -> bool {
-        loop {
-            match self.state {
-                MessageDecodeState::WaitingForHeader
-                | MessageDecodeState::PayloadSuccessfulRead => {
-                    if !self.decode_header() {
-                        return false;
-                    }
-                }
-                MessageDecodeState::HeaderSuccessfulRead
-                | MessageDecodeState::WaitingForPayload => {
-                    if !self.decode_payload() {
-                        return false;
-                    }
-                }
-            }
-
-            if self.state == MessageDecodeState::WaitingForHeader
-                || self.state == MessageDecodeState::WaitingForPayload
-                || self.state == MessageDecodeState::PayloadSuccessfulRead
-            {
-                return true;
-            }
-        }
     }
 
-    fn decode_header(&mut self) -> bool {
-        // check if the read data length is HEADER_SIZE or more
-        if self.in_buffer_pos < HEADER_SIZE {
-            self.state = MessageDecodeState::WaitingForHeader;
-            return true; // just wait for more data
-        }
-
-        // load and check magic
-        for i in 0..8 {
-            if self.in_buffer[i] != MAGIC[i] {
-                return false; // invalid data read
-            }
+    fn decode(&mut self) -> Result<(), DecodeError> {
+        while let Some(frame) = self.decoder.decode()? {
+            frame.process(self);
         }
 
-        // load message number
-        match self.get_usize(8, 11) {
-            Some(message_number) => self.message_number = message_number,
-            None => return false,
-        }
-
-        // load and check payload size
-        match self.get_usize(11, 16) {
-            Some(payload_size) => self.payload_size = payload_size,
-            None => return false,
-        }
-        if self.payload_size <= 0 || self.payload_size > MAX_PAYLOAD {
-            return false;
-        }
-
-        self.state = MessageDecodeState::HeaderSuccessfulRead;
-        return true;
+        Ok(())
     }
 
-    fn decode_payload(&mut self) -> bool {
-        // check if the read data length is HEADER_SIZE plus payload size or more
-        if self.in_buffer_pos < self.payload_size + HEADER_SIZE {
-            self.state = MessageDecodeState::WaitingForPayload;
-            return true; // just wait for more data
-        }
-
-        // parse json and check message number
-        match from_utf8(&self.in_buffer[HEADER_SIZE..self.payload_size + HEADER_SIZE]) {
-            Ok(utf8_payload) => match self.message_number {
-                0 => ProcessMessage!(PingMessage, utf8_payload, self),
-                1 => ProcessMessage!(LoginMessage, utf8_payload, self),
-                2 => ProcessMessage!(PublishGlobalChatMessage, utf8_payload, self),
-                3 => ProcessMessage!(GlobalChatMessage, utf8_payload, self),
-                4 => ProcessMessage!(PublishPrivateChatMessage, utf8_payload, self),
-                5 => ProcessMessage!(PrivateChatMessage, utf8_payload, self),
-                _ => return false,
-            },
-            Err(_) => return false,
-        }
-
-        // remove packet from buffer
-        let mut temp_buffer_pos: usize = 0;
-        for i in self.payload_size + HEADER_SIZE..self.in_buffer_pos {
-            self.in_buffer[temp_buffer_pos] = self.in_buffer[i];
-            temp_buffer_pos += 1;
+    /// Offers framings, codecs and capabilities to the server, must be called before any other
+    /// message is sent.
+    pub fn send_hello(&mut self, hello: HelloMessage) {
+        let hello = Message::new(hello, Codec::Json);
+        if let Err(err) = self.encoder.encode(&hello, &mut self.out_buffer) {
+            eprintln!("Error while encode hello: {}", err);
+            return;
         }
 
-        self.state = MessageDecodeState::PayloadSuccessfulRead;
-        self.in_buffer_pos = temp_buffer_pos;
-        return true;
+        self.handshake_pending = true;
+        self.send();
     }
 
-    fn get_usize(&self, buffer_start_pos: usize, buffer_end_pos: usize) -> Option<usize> {
-        let mut message_number_string = String::new();
-        for i in buffer_start_pos..buffer_end_pos {
-            message_number_string.push(self.in_buffer[i] as char);
-        }
-
-        match message_number_string.trim_end().parse::<usize>() {
-            Ok(read_usize) => Some(read_usize),
-            Err(_) => None,
-        }
+    /// Completes the handshake and sends the held back messages.
+    pub fn welcome(&mut self, welcome: WelcomeMessage) {
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&welcome.version) {
+            self.console_message_sender
+                .send((
+                    MessageType::Error,
+                    format!(
+                        "[ERROR] Protocol version {} of {} is not supported",
+                        welcome.version, welcome.server_name
+                    ),
+                ))
+                .unwrap();
+            self.closing = true;
+            return;
+        }
+
+        self.encoder.set_framing(welcome.framing);
+        self.encoder
+            .set_compression(welcome.compression, welcome.compression_threshold);
+        self.decoder.set_framing(welcome.framing);
+        self.decoder.set_codec(welcome.codec);
+        self.codec = welcome.codec;
+        self.capabilities = welcome.capabilities;
+        self.handshake_pending = false;
+        self.send();
     }
 
-    pub fn send_message(&mut self, message: Message) {
+    /// Returns `true` if the connection should be removed and closed, a failed send while
+    /// reading closes the connection after the read.
+    pub fn send_message(&mut self, message: Frame) -> bool {
         self.message_queue.push_back(message);
-        // return value of send is ignored!!!!
-        self.send();
+        if self.send() {
+            self.closing = true;
+        }
+        self.closing
     }
 
     /// Returns `true` if the connection should be removed and closed.
     pub fn send(&mut self) -> bool {
         loop {
-            if self.out_buffer_size == 0 {
-                match self.message_queue.pop_front() {
-                    Some(message) => {
-                        if !self.encode(message.message, message.number) {
-                            eprintln!("Error while encode message!");
-                            return true;
+            if self.out_buffer.is_empty() {
+                match self.next_message() {
+                    Some(frame) => {
+                        if let Err(err) = self.encode(&frame) {
+                            self.console_message_sender
+                                .send((MessageType::Error, format!("[ERROR] {}", err)))
+                                .unwrap();
+                            continue;
                         }
                     }
                     None => {
-                        if self.send_interest {
-                            // disable send interest
-                            self.send_interest = false;
-                            self.registry
-                                .reregister(&mut self.tcp_stream, self.token, Interest::READABLE)
-                                .expect("Error while regegister!");
+                        // the encrypted data of a TLS connection may not be written yet
+                        if self.transport.flush_tls().is_err() {
+                            return true;
                         }
 
+                        let send_interest = self.transport.wants_write();
+                        self.set_send_interest(send_interest);
                         return false;
                     }
                 }
@@ -275,15 +183,15 @@ impl Connection {
 
             // We can (maybe) write to the connection.
             match self
-                .tcp_stream
-                .write(&self.out_buffer[self.out_buffer_pos..self.out_buffer_size])
+                .transport
+                .write(&self.out_buffer[self.out_buffer_pos..])
             {
                 Ok(n) => {
-                    if n != self.out_buffer_size - self.out_buffer_pos {
-                        self.out_buffer_pos = n;
+                    if n != self.out_buffer.len() - self.out_buffer_pos {
+                        self.out_buffer_pos += n;
                         set_send_interest = true;
                     } else {
-                        self.out_buffer_size = 0;
+                        self.out_buffer.clear();
                         self.out_buffer_pos = 0;
                         // we dont return because we can try to write more messages
                     }
@@ -299,62 +207,40 @@ impl Connection {
 
             // enable send interest if not already set
             if set_send_interest {
-                if !self.send_interest {
-                    // enable send interest
-                    self.send_interest = true;
-                    self.registry
-                        .reregister(
-                            &mut self.tcp_stream,
-                            self.token,
-                            Interest::READABLE.add(Interest::WRITABLE),
-                        )
-                        .expect("Error while regegister!");
-                }
-
+                self.set_send_interest(true);
                 return false;
             }
         }
     }
 
-    pub fn encode(&mut self, message: String, message_number: u32) -> bool {
-        let message = message.into_bytes();
-
-        if message.len() > MAX_PAYLOAD {
-            return false;
+    fn set_send_interest(&mut self, send_interest: bool) {
+        if self.send_interest == send_interest {
+            return;
         }
 
-        // write magic
-        for i in 0..8 {
-            self.out_buffer[i] = MAGIC[i];
-        }
-
-        // write message number
-        let message_number = message_number.to_string().into_bytes();
-        for i in 0..3 {
-            if i < message_number.len() {
-                self.out_buffer[8 + i] = message_number[i];
-            } else {
-                self.out_buffer[8 + i] = b' ';
-            }
-        }
+        self.send_interest = send_interest;
+        let interest = if send_interest {
+            Interest::READABLE.add(Interest::WRITABLE)
+        } else {
+            Interest::READABLE
+        };
+        self.registry
+            .reregister(self.transport.socket_mut(), self.token, interest)
+            .expect("Error while regegister!");
+    }
 
-        // write payload size
-        let payload_size = message.len().to_string().into_bytes();
-        for i in 0..5 {
-            if i < payload_size.len() {
-                self.out_buffer[11 + i] = payload_size[i];
-            } else {
-                self.out_buffer[11 + i] = b' ';
-            }
+    fn next_message(&mut self) -> Option<Frame> {
+        if self.handshake_pending {
+            return None;
         }
 
-        // write payload
-        for i in 0..message.len() {
-            self.out_buffer[16 + i] = message[i];
-        }
+        self.message_queue.pop_front()
+    }
 
-        self.out_buffer_size = HEADER_SIZE + message.len();
+    /// Frames are encoded just before sending, after the handshake the negotiated codec is known.
+    pub fn encode(&mut self, frame: &Frame) -> Result<(), EncodeError> {
         self.out_buffer_pos = 0;
-        return true;
+        let message = frame.encode(self.codec)?;
+        self.encoder.encode(&message, &mut self.out_buffer)
     }
 }
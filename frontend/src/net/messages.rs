@@ -1,12 +1,61 @@
 use crate::net::connection::Connection;
-use crate::net::message::{Message, MessageTrait};
-use crate::MessageType;
-use serde::{Deserialize, Serialize};
+use crate::net::message::MessageTrait;
+use crate::{ChatText, ConsoleMessage, MessageType};
+use rust_chat_protocol::frame::Frame;
+use rust_chat_protocol::messages::{
+    Capability, DeleteMessage, DeliveryStatus, EditMessage, ErrorMessage, FetchHistoryMessage,
+    GlobalChatMessage, HelloMessage, HistoryMessage, HistoryScope, JoinRoomMessage,
+    LeaveRoomMessage, ListRoomsMessage, ListUsersMessage, LoginMessage, LoginResultMessage,
+    MessageDeletedMessage, MessageEditedMessage, MessageReadMessage, ModeratedMessage,
+    ModerationMessage, ModerationResultMessage, PingMessage, PrivateChatMessage,
+    PrivateChatResultMessage, PublishGlobalChatMessage, PublishPrivateChatMessage,
+    PublishRoomMessage, ReadMessage, RoomAction, RoomListMessage, RoomMessage, RoomResultMessage,
+    RoomTopicMessage, SetTopicMessage, TypingMessage, UserJoinedMessage, UserLeftMessage,
+    UserListMessage, UserTypingMessage, WelcomeMessage,
+};
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct PingMessage {
-    pub nonce: u32,
-    pub reply: bool,
+impl MessageTrait for Frame {
+    fn process(self, connection: &mut Connection) {
+        match self {
+            Frame::Ping(message) => message.process(connection),
+            Frame::Login(message) => message.process(connection),
+            Frame::PublishGlobalChat(message) => message.process(connection),
+            Frame::GlobalChat(message) => message.process(connection),
+            Frame::PublishPrivateChat(message) => message.process(connection),
+            Frame::PrivateChat(message) => message.process(connection),
+            Frame::Error(message) => message.process(connection),
+            Frame::Hello(message) => message.process(connection),
+            Frame::Welcome(message) => message.process(connection),
+            Frame::PrivateChatResult(message) => message.process(connection),
+            Frame::LoginResult(message) => message.process(connection),
+            Frame::JoinRoom(message) => message.process(connection),
+            Frame::LeaveRoom(message) => message.process(connection),
+            Frame::RoomResult(message) => message.process(connection),
+            Frame::PublishRoom(message) => message.process(connection),
+            Frame::Room(message) => message.process(connection),
+            Frame::ListRooms(message) => message.process(connection),
+            Frame::RoomList(message) => message.process(connection),
+            Frame::Moderation(message) => message.process(connection),
+            Frame::ModerationResult(message) => message.process(connection),
+            Frame::Moderated(message) => message.process(connection),
+            Frame::SetTopic(message) => message.process(connection),
+            Frame::RoomTopic(message) => message.process(connection),
+            Frame::UserJoined(message) => message.process(connection),
+            Frame::UserLeft(message) => message.process(connection),
+            Frame::ListUsers(message) => message.process(connection),
+            Frame::UserList(message) => message.process(connection),
+            Frame::Typing(message) => message.process(connection),
+            Frame::UserTyping(message) => message.process(connection),
+            Frame::Read(message) => message.process(connection),
+            Frame::MessageRead(message) => message.process(connection),
+            Frame::FetchHistory(message) => message.process(connection),
+            Frame::History(message) => message.process(connection),
+            Frame::Edit(message) => message.process(connection),
+            Frame::Delete(message) => message.process(connection),
+            Frame::MessageEdited(message) => message.process(connection),
+            Frame::MessageDeleted(message) => message.process(connection),
+        }
+    }
 }
 
 impl MessageTrait for PingMessage {
@@ -16,95 +65,441 @@ impl MessageTrait for PingMessage {
                 nonce: self.nonce,
                 reply: true,
             };
-            connection.send_message(Message::new(reply));
+            connection.send_message(reply.into());
         }
     }
+}
 
-    fn number(&self) -> u32 {
-        0
-    }
+impl MessageTrait for LoginMessage {
+    fn process(self, _connection: &mut Connection) {}
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct LoginMessage {
-    pub user_name: String,
+impl MessageTrait for LoginResultMessage {
+    fn process(self, connection: &mut Connection) {
+        if let Some(error) = self.error {
+            connection
+                .console_message_sender
+                .send((
+                    MessageType::Error,
+                    format!(
+                        "[ERROR] Login as {} failed ({:?}), press l to try again",
+                        self.user_name, error
+                    ),
+                ))
+                .unwrap();
+        }
+    }
 }
 
-impl MessageTrait for LoginMessage {
-    fn process(self, _connection: &mut Connection) {}
+impl MessageTrait for UserJoinedMessage {
+    fn process(self, connection: &mut Connection) {
+        connection
+            .console_message_sender
+            .send((
+                MessageType::Presence,
+                format!("{} is online", self.user_name),
+            ))
+            .unwrap();
+    }
+}
 
-    fn number(&self) -> u32 {
-        1
+impl MessageTrait for UserLeftMessage {
+    fn process(self, connection: &mut Connection) {
+        connection
+            .console_message_sender
+            .send((
+                MessageType::Presence,
+                format!("{} is offline", self.user_name),
+            ))
+            .unwrap();
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct PublishGlobalChatMessage {
-    pub message: String,
+impl MessageTrait for ListUsersMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for UserListMessage {
+    fn process(self, connection: &mut Connection) {
+        connection
+            .console_message_sender
+            .send((
+                MessageType::Presence,
+                format!("Online: {}", self.user_names.join(", ")),
+            ))
+            .unwrap();
+    }
 }
 
 impl MessageTrait for PublishGlobalChatMessage {
     fn process(self, _connection: &mut Connection) {}
+}
 
-    fn number(&self) -> u32 {
-        2
+impl MessageTrait for GlobalChatMessage {
+    fn process(self, connection: &mut Connection) {
+        let prefix = format!(
+            "[{} #{}] {}: ",
+            time(self.timestamp),
+            self.message_id,
+            self.user_name
+        );
+        connection
+            .console_message_sender
+            .send(chat_line(
+                MessageType::Public,
+                self.message_id,
+                prefix,
+                &self.message,
+            ))
+            .unwrap();
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct GlobalChatMessage {
-    pub user_name: String,
-    pub message: String,
+impl MessageTrait for PublishPrivateChatMessage {
+    fn process(self, _connection: &mut Connection) {}
 }
 
-impl MessageTrait for GlobalChatMessage {
+impl MessageTrait for PrivateChatMessage {
     fn process(self, connection: &mut Connection) {
+        let prefix = format!(
+            "[{} #{}] [PRIVATE] [{} -> ME] ",
+            time(self.timestamp),
+            self.message_id,
+            self.from_user_name
+        );
+        connection
+            .console_message_sender
+            .send(chat_line(
+                MessageType::Private,
+                self.message_id,
+                prefix,
+                &self.message,
+            ))
+            .unwrap();
+
+        // the message is shown right away, so it is read
+        if connection.capabilities.contains(&Capability::Typing) {
+            connection.send_message(
+                ReadMessage {
+                    message_id: self.message_id,
+                }
+                .into(),
+            );
+        }
+    }
+}
+
+impl MessageTrait for PrivateChatResultMessage {
+    fn process(self, connection: &mut Connection) {
+        // the own message is already shown, it is marked as delivered or queued
         connection
             .console_message_sender
             .send((
-                MessageType::Public,
-                format!("{}: {}", self.user_name, self.message),
+                MessageType::PrivateResult {
+                    to_user_name: self.to_user_name.clone(),
+                    status: self.status,
+                    message_id: self.message_id,
+                },
+                String::new(),
             ))
             .unwrap();
+
+        let error = match self.status {
+            DeliveryStatus::UserNotFound => format!("[ERROR] User {} not found", self.to_user_name),
+            DeliveryStatus::QueueFull => format!(
+                "[ERROR] Too many messages queued for {}, try again later",
+                self.to_user_name
+            ),
+            DeliveryStatus::Delivered | DeliveryStatus::Deferred => return,
+        };
+        connection
+            .console_message_sender
+            .send((MessageType::Error, error))
+            .unwrap();
     }
+}
 
-    fn number(&self) -> u32 {
-        3
+impl MessageTrait for FetchHistoryMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for EditMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for DeleteMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for MessageEditedMessage {
+    fn process(self, connection: &mut Connection) {
+        connection
+            .console_message_sender
+            .send((MessageType::Edited(self.message_id), self.message))
+            .unwrap();
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct PublishPrivateChatMessage {
-    pub to_user_name: String,
-    pub message: String,
+impl MessageTrait for MessageDeletedMessage {
+    fn process(self, connection: &mut Connection) {
+        connection
+            .console_message_sender
+            .send((MessageType::Deleted(self.message_id), String::new()))
+            .unwrap();
+    }
 }
 
-impl MessageTrait for PublishPrivateChatMessage {
+impl MessageTrait for HistoryMessage {
+    fn process(self, connection: &mut Connection) {
+        if self.messages.is_empty() {
+            connection
+                .console_message_sender
+                .send((MessageType::Presence, "[HISTORY] No messages".to_string()))
+                .unwrap();
+        }
+
+        for entry in self.messages {
+            let stamp = format!("{} #{}", time(entry.timestamp), entry.message_id);
+            let (message_type, prefix): (fn(Option<ChatText>) -> MessageType, String) =
+                match &self.scope {
+                    HistoryScope::Global => (
+                        MessageType::Public,
+                        format!("[{}] {}: ", stamp, entry.user_name),
+                    ),
+                    HistoryScope::Room(room) => (
+                        MessageType::Room,
+                        format!("[{}] [{}] {}: ", stamp, room, entry.user_name),
+                    ),
+                    HistoryScope::User(user_name) if *user_name == entry.user_name => (
+                        MessageType::Private,
+                        format!("[{}] [PRIVATE] [{} -> ME] ", stamp, entry.user_name),
+                    ),
+                    HistoryScope::User(user_name) => (
+                        MessageType::Private,
+                        format!("[{}] [PRIVATE] [ME -> {}] ", stamp, user_name),
+                    ),
+                };
+            let message = if entry.edited {
+                format!("{} (edited)", entry.message)
+            } else {
+                entry.message
+            };
+            connection
+                .console_message_sender
+                .send(chat_line(message_type, entry.message_id, prefix, &message))
+                .unwrap();
+        }
+    }
+}
+
+impl MessageTrait for TypingMessage {
     fn process(self, _connection: &mut Connection) {}
+}
 
-    fn number(&self) -> u32 {
-        4
+impl MessageTrait for UserTypingMessage {
+    fn process(self, connection: &mut Connection) {
+        let text = match self.room {
+            Some(room) => format!("{} is typing in {}…", self.user_name, room),
+            None => format!("{} is typing…", self.user_name),
+        };
+        connection
+            .console_message_sender
+            .send((MessageType::Typing, text))
+            .unwrap();
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct PrivateChatMessage {
-    pub from_user_name: String,
-    pub message: String,
+impl MessageTrait for ReadMessage {
+    fn process(self, _connection: &mut Connection) {}
 }
 
-impl MessageTrait for PrivateChatMessage {
+impl MessageTrait for MessageReadMessage {
+    fn process(self, connection: &mut Connection) {
+        connection
+            .console_message_sender
+            .send((MessageType::PrivateRead(self.message_id), String::new()))
+            .unwrap();
+    }
+}
+
+impl MessageTrait for JoinRoomMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for LeaveRoomMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for RoomResultMessage {
+    fn process(self, connection: &mut Connection) {
+        let console_message = match (self.action, self.error) {
+            (RoomAction::Join, None) => (
+                MessageType::Room(None),
+                format!("[ROOM] Joined {}", self.room),
+            ),
+            (RoomAction::Leave, None) => (
+                MessageType::Room(None),
+                format!("[ROOM] Left {}", self.room),
+            ),
+            (action, Some(error)) => (
+                MessageType::Error,
+                format!(
+                    "[ERROR] {:?} room {} failed ({:?})",
+                    action, self.room, error
+                ),
+            ),
+        };
+        connection
+            .console_message_sender
+            .send(console_message)
+            .unwrap();
+    }
+}
+
+impl MessageTrait for PublishRoomMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for RoomMessage {
+    fn process(self, connection: &mut Connection) {
+        let prefix = format!(
+            "[{} #{}] [{}] {}: ",
+            time(self.timestamp),
+            self.message_id,
+            self.room,
+            self.user_name
+        );
+        connection
+            .console_message_sender
+            .send(chat_line(
+                MessageType::Room,
+                self.message_id,
+                prefix,
+                &self.message,
+            ))
+            .unwrap();
+    }
+}
+
+impl MessageTrait for ListRoomsMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for RoomListMessage {
+    fn process(self, connection: &mut Connection) {
+        let rooms = if self.rooms.is_empty() {
+            "none".to_string()
+        } else {
+            self.rooms
+                .iter()
+                .map(|room| format!("{} ({})", room.name, room.members))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        connection
+            .console_message_sender
+            .send((MessageType::Room(None), format!("[ROOM] Rooms: {}", rooms)))
+            .unwrap();
+    }
+}
+
+impl MessageTrait for ModerationMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for ModerationResultMessage {
     fn process(self, connection: &mut Connection) {
+        let scope = self.room.unwrap_or_else(|| "server".to_string());
+        let console_message = match self.error {
+            None => (
+                MessageType::Room(None),
+                format!("[{}] {:?} {} done", scope, self.action, self.user_name),
+            ),
+            Some(error) => (
+                MessageType::Error,
+                format!(
+                    "[ERROR] {:?} {} in {} failed ({:?})",
+                    self.action, self.user_name, scope, error
+                ),
+            ),
+        };
+        connection
+            .console_message_sender
+            .send(console_message)
+            .unwrap();
+    }
+}
+
+impl MessageTrait for ModeratedMessage {
+    fn process(self, connection: &mut Connection) {
+        let scope = self.room.unwrap_or_else(|| "server".to_string());
         connection
             .console_message_sender
             .send((
-                MessageType::Private,
-                format!("[PRIVATE] [{} -> ME] {}", self.from_user_name, self.message),
+                MessageType::Error,
+                format!(
+                    "[{}] {} applied {:?} to you",
+                    scope, self.by_user_name, self.action
+                ),
             ))
             .unwrap();
     }
+}
 
-    fn number(&self) -> u32 {
-        5
+impl MessageTrait for SetTopicMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for RoomTopicMessage {
+    fn process(self, connection: &mut Connection) {
+        connection
+            .console_message_sender
+            .send((
+                MessageType::Room(None),
+                format!("[{}] Topic: {}", self.room, self.topic),
+            ))
+            .unwrap();
+    }
+}
+
+impl MessageTrait for ErrorMessage {
+    fn process(self, connection: &mut Connection) {
+        connection
+            .console_message_sender
+            .send((
+                MessageType::Error,
+                format!("[ERROR] [{:?}] {}", self.code, self.reason),
+            ))
+            .unwrap();
     }
 }
+
+impl MessageTrait for HelloMessage {
+    fn process(self, _connection: &mut Connection) {}
+}
+
+impl MessageTrait for WelcomeMessage {
+    fn process(self, connection: &mut Connection) {
+        connection.welcome(self);
+    }
+}
+
+/// Line of a chat message, edits replace the text after `prefix`.
+fn chat_line(
+    message_type: fn(Option<ChatText>) -> MessageType,
+    message_id: u64,
+    prefix: String,
+    message: &str,
+) -> ConsoleMessage {
+    let text = ChatText {
+        message_id,
+        start: prefix.len(),
+    };
+    (message_type(Some(text)), prefix + message)
+}
+
+/// `HH:MM` of a timestamp of the server, in UTC.
+fn time(timestamp: u64) -> String {
+    let minutes = timestamp / 1000 / 60;
+    format!("{:02}:{:02}", minutes / 60 % 24, minutes % 60)
+}
@@ -1,5 +1,6 @@
 pub mod client;
-pub mod message;
-pub mod messages;
+pub mod transport;
 
 mod connection;
+mod message;
+mod messages;
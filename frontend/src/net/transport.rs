@@ -0,0 +1,60 @@
+use mio::net::TcpStream;
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, ServerName},
+    ClientConfig, ClientConnection, RootCertStore,
+};
+use std::{path::Path, sync::Arc};
+
+/// Trusted certificates and the expected name of the server.
+pub struct TlsConnector {
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+}
+
+impl TlsConnector {
+    /// Trusts the certificates of `ca_cert_path`, e.g. the self-signed certificate of the server.
+    pub fn load(ca_cert_path: &Path, server_name: &str) -> Result<Self, String> {
+        let mut root_store = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(ca_cert_path)
+            .map_err(|err| format!("{}: {}", ca_cert_path.display(), err))?
+        {
+            let cert = cert.map_err(|err| format!("{}: {}", ca_cert_path.display(), err))?;
+            root_store.add(cert).map_err(|err| err.to_string())?;
+        }
+
+        let config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(|err| err.to_string())?
+                .with_root_certificates(root_store)
+                .with_no_client_auth();
+        let server_name = ServerName::try_from(server_name.to_string())
+            .map_err(|err| format!("{}: {}", server_name, err))?;
+
+        Ok(Self {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+}
+
+/// Socket to the server, optionally encrypted with TLS.
+pub type Transport = rust_chat_protocol::transport::Transport<TcpStream>;
+
+/// Starts the TLS handshake if `tls_connector` is set.
+pub fn new_transport(
+    socket: TcpStream,
+    tls_connector: Option<&TlsConnector>,
+) -> Result<Transport, String> {
+    match tls_connector {
+        Some(tls_connector) => {
+            let tls = ClientConnection::new(
+                Arc::clone(&tls_connector.config),
+                tls_connector.server_name.clone(),
+            )
+            .map_err(|err| err.to_string())?;
+            Ok(Transport::tls(socket, tls))
+        }
+        None => Ok(Transport::Plain(socket)),
+    }
+}
@@ -0,0 +1,38 @@
+use crate::error::EncodeError;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Serialization format of the payloads, negotiated per connection with the `HelloMessage`.
+/// The handshake itself always uses `Codec::Json`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Codec {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Codec {
+    pub fn serialize<T: Serialize>(self, message: &T) -> Result<Vec<u8>, EncodeError> {
+        match self {
+            Codec::Json => serde_json::to_vec(message).map_err(|err| err.to_string()),
+            // named fields, so messages stay readable if fields are added
+            Codec::MessagePack => rmp_serde::to_vec_named(message).map_err(|err| err.to_string()),
+            Codec::Cbor => {
+                let mut payload = Vec::new();
+                ciborium::into_writer(message, &mut payload)
+                    .map(|_| payload)
+                    .map_err(|err| err.to_string())
+            }
+        }
+        .map_err(EncodeError::Serialize)
+    }
+
+    /// Returns the reason as error, the caller knows which message failed.
+    pub fn deserialize<T: DeserializeOwned>(self, payload: &[u8]) -> Result<T, String> {
+        match self {
+            Codec::Json => serde_json::from_slice(payload).map_err(|err| err.to_string()),
+            Codec::MessagePack => rmp_serde::from_slice(payload).map_err(|err| err.to_string()),
+            Codec::Cbor => ciborium::from_reader(payload).map_err(|err| err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::error::{DecodeError, EncodeError};
+use crate::framing::{FLAG_DEFLATE, FLAG_ZSTD};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+
+/// Payloads smaller than this are not worth compressing.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 512;
+
+/// Payload compression, only supported by the binary framing.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+    Deflate,
+    Zstd,
+}
+
+/// Payload sizes of the compressed messages before and after compression.
+#[derive(Default, Clone, Copy)]
+pub struct CompressionStats {
+    pub uncompressed: usize,
+    pub compressed: usize,
+}
+
+impl Compression {
+    pub fn flag(self) -> u8 {
+        match self {
+            Compression::Deflate => FLAG_DEFLATE,
+            Compression::Zstd => FLAG_ZSTD,
+        }
+    }
+
+    pub fn from_flags(flags: u8) -> Result<Option<Compression>, DecodeError> {
+        match (flags & FLAG_DEFLATE != 0, flags & FLAG_ZSTD != 0) {
+            (false, false) => Ok(None),
+            (true, false) => Ok(Some(Compression::Deflate)),
+            (false, true) => Ok(Some(Compression::Zstd)),
+            (true, true) => Err(DecodeError::InvalidFlags(flags)),
+        }
+    }
+
+    pub fn compress(self, payload: &[u8]) -> Result<Vec<u8>, EncodeError> {
+        match self {
+            Compression::Deflate => {
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(payload).and_then(|_| encoder.finish())
+            }
+            Compression::Zstd => zstd::encode_all(payload, zstd::DEFAULT_COMPRESSION_LEVEL),
+        }
+        .map_err(|err| EncodeError::Compress(err.to_string()))
+    }
+
+    /// Decompresses at most `max_size` bytes, so a small payload can not blow up the memory.
+    pub fn decompress(self, payload: &[u8], max_size: usize) -> Result<Vec<u8>, DecodeError> {
+        let mut decompressed = Vec::new();
+        match self {
+            Compression::Deflate => read_limited(
+                flate2::read::DeflateDecoder::new(payload),
+                max_size,
+                &mut decompressed,
+            ),
+            Compression::Zstd => zstd::stream::read::Decoder::new(payload)
+                .and_then(|decoder| read_limited(decoder, max_size, &mut decompressed)),
+        }
+        .map_err(|err| DecodeError::InvalidCompression(err.to_string()))?;
+
+        if decompressed.len() > max_size {
+            return Err(DecodeError::PayloadTooLarge(decompressed.len()));
+        }
+
+        Ok(decompressed)
+    }
+}
+
+impl CompressionStats {
+    pub fn add(&mut self, uncompressed: usize, compressed: usize) {
+        self.uncompressed += uncompressed;
+        self.compressed += compressed;
+    }
+}
+
+/// Reads one byte more than `max_size`, enough to detect a too large payload.
+fn read_limited<R: Read>(reader: R, max_size: usize, out: &mut Vec<u8>) -> io::Result<()> {
+    reader
+        .take(max_size as u64 + 1)
+        .read_to_end(out)
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::FLAG_FRAGMENT;
+
+    const COMPRESSIONS: [Compression; 2] = [Compression::Deflate, Compression::Zstd];
+
+    #[test]
+    fn round_trip() {
+        let payload = b"hello hello hello hello hello hello".repeat(10);
+        for compression in COMPRESSIONS {
+            let compressed = compression.compress(&payload).unwrap();
+            assert!(compressed.len() < payload.len());
+            assert_eq!(
+                compression.decompress(&compressed, payload.len()).unwrap(),
+                payload
+            );
+        }
+    }
+
+    #[test]
+    fn decompress_limit() {
+        for compression in COMPRESSIONS {
+            let compressed = compression.compress(&[0; 1000]).unwrap();
+            assert_eq!(
+                compression.decompress(&compressed, 1000).unwrap().len(),
+                1000
+            );
+            // stops reading one byte after the limit
+            assert_eq!(
+                compression.decompress(&compressed, 999),
+                Err(DecodeError::PayloadTooLarge(1000))
+            );
+            assert_eq!(
+                compression.decompress(&compressed, 10),
+                Err(DecodeError::PayloadTooLarge(11))
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_data() {
+        let payload = b"hello hello hello hello hello hello".repeat(10);
+        for compression in COMPRESSIONS {
+            let compressed = compression.compress(&payload).unwrap();
+            let truncated = &compressed[..compressed.len() / 2];
+            assert!(
+                matches!(
+                    compression.decompress(truncated, payload.len()),
+                    Err(DecodeError::InvalidCompression(_))
+                ),
+                "{:?}",
+                compression
+            );
+        }
+    }
+
+    #[test]
+    fn flags() {
+        for compression in COMPRESSIONS {
+            assert_eq!(
+                Compression::from_flags(compression.flag() | FLAG_FRAGMENT),
+                Ok(Some(compression))
+            );
+        }
+        assert_eq!(Compression::from_flags(FLAG_FRAGMENT), Ok(None));
+        assert_eq!(
+            Compression::from_flags(FLAG_DEFLATE | FLAG_ZSTD),
+            Err(DecodeError::InvalidFlags(FLAG_DEFLATE | FLAG_ZSTD))
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use crate::messages::ErrorCode;
+use std::{error::Error, fmt};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    InvalidMagic,
+    InvalidVersion(u8),
+    InvalidFlags(u8),
+    InvalidMessageNumber,
+    InvalidPayloadSize,
+    PayloadTooLarge(usize),
+    UnknownMessage(u32),
+    InvalidPayload { number: u32, reason: String },
+    InvalidCompression(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::InvalidMagic => write!(f, "invalid magic number"),
+            DecodeError::InvalidVersion(version) => {
+                write!(f, "invalid framing version {}", version)
+            }
+            DecodeError::InvalidFlags(flags) => write!(f, "invalid flags {:#010b}", flags),
+            DecodeError::InvalidMessageNumber => write!(f, "invalid message number"),
+            DecodeError::InvalidPayloadSize => write!(f, "invalid payload size"),
+            DecodeError::PayloadTooLarge(payload_size) => {
+                write!(f, "payload of {} bytes is too large", payload_size)
+            }
+            DecodeError::UnknownMessage(number) => write!(f, "unknown message number {}", number),
+            DecodeError::InvalidPayload { number, reason } => {
+                write!(f, "invalid payload for message {}: {}", number, reason)
+            }
+            DecodeError::InvalidCompression(reason) => {
+                write!(f, "invalid compressed payload: {}", reason)
+            }
+        }
+    }
+}
+
+impl DecodeError {
+    /// The error code reported to the other side.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            DecodeError::InvalidMagic
+            | DecodeError::InvalidVersion(_)
+            | DecodeError::InvalidFlags(_)
+            | DecodeError::InvalidMessageNumber
+            | DecodeError::InvalidPayloadSize => ErrorCode::InvalidData,
+            DecodeError::PayloadTooLarge(_) => ErrorCode::PayloadTooLarge,
+            DecodeError::UnknownMessage(_) => ErrorCode::UnknownMessage,
+            DecodeError::InvalidPayload { .. } | DecodeError::InvalidCompression(_) => {
+                ErrorCode::InvalidPayload
+            }
+        }
+    }
+
+    /// The message number of the rejected frame, if the header could be read.
+    pub fn message_number(&self) -> Option<u32> {
+        match self {
+            DecodeError::UnknownMessage(number) | DecodeError::InvalidPayload { number, .. } => {
+                Some(*number)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EncodeError {
+    PayloadTooLarge(usize),
+    Serialize(String),
+    Compress(String),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodeError::PayloadTooLarge(payload_size) => {
+                write!(f, "payload of {} bytes is too large", payload_size)
+            }
+            EncodeError::Serialize(reason) => write!(f, "error while serialize: {}", reason),
+            EncodeError::Compress(reason) => write!(f, "error while compress: {}", reason),
+        }
+    }
+}
+
+impl Error for EncodeError {}
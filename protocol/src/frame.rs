@@ -0,0 +1,124 @@
+use crate::codec::Codec;
+use crate::error::{DecodeError, EncodeError};
+use crate::framing::{Header, FRAGMENT_NUMBER};
+use crate::message::Message;
+use crate::messages::{
+    DeleteMessage, EditMessage, ErrorMessage, FetchHistoryMessage, GlobalChatMessage, HelloMessage,
+    HistoryMessage, JoinRoomMessage, LeaveRoomMessage, ListRoomsMessage, ListUsersMessage,
+    LoginMessage, LoginResultMessage, MessageDeletedMessage, MessageEditedMessage,
+    MessageReadMessage, ModeratedMessage, ModerationMessage, ModerationResultMessage, PingMessage,
+    PrivateChatMessage, PrivateChatResultMessage, PublishGlobalChatMessage,
+    PublishPrivateChatMessage, PublishRoomMessage, ReadMessage, RoomListMessage, RoomMessage,
+    RoomResultMessage, RoomTopicMessage, SetTopicMessage, TypingMessage, UserJoinedMessage,
+    UserLeftMessage, UserListMessage, UserTypingMessage, WelcomeMessage,
+};
+use serde::{Deserialize, Serialize};
+
+/// Message numbers must fit into the 3 ascii digits of the header.
+const MESSAGE_NUMBER_LIMIT: u32 = 1000;
+
+macro_rules! Frames {
+    ($($number:literal => $variant:ident($message_struct:ident),)+) => {
+
+        #[derive(Serialize, Deserialize, Clone)]
+        #[serde(tag = "type")]
+        pub enum Frame {
+            $($variant($message_struct),)+
+        }
+
+        impl Frame {
+            pub fn number(&self) -> u32 {
+                match self {
+                    $(Frame::$variant(_) => $number,)+
+                }
+            }
+
+            pub fn decode(header: &Header, payload: &[u8], codec: Codec) -> Result<Frame, DecodeError> {
+                // a message number used twice is an unreachable pattern and fails to compile
+                #[deny(unreachable_patterns)]
+                match header.number {
+                    $($number => codec.deserialize::<$message_struct>(payload)
+                        .map(Frame::$variant)
+                        .map_err(|reason| DecodeError::InvalidPayload {
+                            number: header.number,
+                            reason,
+                        }),)+
+                    number => Err(DecodeError::UnknownMessage(number)),
+                }
+            }
+
+            pub fn encode(&self, codec: Codec) -> Result<Message, EncodeError> {
+                let payload = match self {
+                    $(Frame::$variant(message) => codec.serialize(message),)+
+                }?;
+
+                Ok(Message {
+                    payload,
+                    number: self.number(),
+                    critical: self.is_critical(),
+                })
+            }
+        }
+
+        $(
+            const _: () = assert!($number < MESSAGE_NUMBER_LIMIT && $number != FRAGMENT_NUMBER);
+
+            impl From<$message_struct> for Frame {
+                fn from(message: $message_struct) -> Self {
+                    Frame::$variant(message)
+                }
+            }
+        )+
+
+    };
+}
+
+impl Frame {
+    /// Critical frames are not dropped from a full outbound queue.
+    pub fn is_critical(&self) -> bool {
+        !matches!(
+            self,
+            Frame::GlobalChat(_) | Frame::Room(_) | Frame::UserTyping(_)
+        )
+    }
+}
+
+Frames!(
+    0 => Ping(PingMessage),
+    1 => Login(LoginMessage),
+    2 => PublishGlobalChat(PublishGlobalChatMessage),
+    3 => GlobalChat(GlobalChatMessage),
+    4 => PublishPrivateChat(PublishPrivateChatMessage),
+    5 => PrivateChat(PrivateChatMessage),
+    6 => Error(ErrorMessage),
+    7 => Hello(HelloMessage),
+    8 => Welcome(WelcomeMessage),
+    9 => PrivateChatResult(PrivateChatResultMessage),
+    10 => LoginResult(LoginResultMessage),
+    11 => JoinRoom(JoinRoomMessage),
+    12 => LeaveRoom(LeaveRoomMessage),
+    13 => RoomResult(RoomResultMessage),
+    14 => PublishRoom(PublishRoomMessage),
+    15 => Room(RoomMessage),
+    16 => ListRooms(ListRoomsMessage),
+    17 => RoomList(RoomListMessage),
+    18 => Moderation(ModerationMessage),
+    19 => ModerationResult(ModerationResultMessage),
+    20 => Moderated(ModeratedMessage),
+    21 => SetTopic(SetTopicMessage),
+    22 => RoomTopic(RoomTopicMessage),
+    23 => UserJoined(UserJoinedMessage),
+    24 => UserLeft(UserLeftMessage),
+    25 => ListUsers(ListUsersMessage),
+    26 => UserList(UserListMessage),
+    27 => Typing(TypingMessage),
+    28 => UserTyping(UserTypingMessage),
+    29 => Read(ReadMessage),
+    30 => MessageRead(MessageReadMessage),
+    31 => FetchHistory(FetchHistoryMessage),
+    32 => History(HistoryMessage),
+    33 => Edit(EditMessage),
+    34 => Delete(DeleteMessage),
+    35 => MessageEdited(MessageEditedMessage),
+    36 => MessageDeleted(MessageDeletedMessage),
+);
@@ -0,0 +1,810 @@
+use crate::codec::Codec;
+use crate::compression::{Compression, CompressionStats};
+use crate::error::{DecodeError, EncodeError};
+use crate::frame::Frame;
+use crate::message::Message;
+use serde::{Deserialize, Serialize};
+
+/*
+    Message Header (Framing::Ascii, protocol v1):
+    bytes   name               description
+    8       magic number       Magic number to identify the application
+    3       message number     Message number to identify the message type
+    5       payload size       Payload size, maximum is MAX_PAYLOAD
+    total size: 16 bytes
+
+    Message Header (Framing::Binary, protocol v2):
+    bytes   name               description
+    2       magic number       Magic number to identify the application
+    1       version            Framing version, always BINARY_VERSION
+    2       message number     Message number, u16 little-endian
+    4       payload size       Payload size, u32 little-endian, maximum is MAX_PAYLOAD
+    1       flags              Bit set of the FLAG_* constants
+    total size: 10 bytes
+
+    Every connection starts with the ascii framing and json payloads. A client
+    can offer the binary framing and other codecs with a HelloMessage, after
+    the WelcomeMessage both sides use the framing and codec chosen by the
+    server.
+
+    Fragmentation:
+    A message with a payload larger than MAX_PAYLOAD is split into chunks of
+    MAX_PAYLOAD bytes. Every chunk except the last one is marked as fragment,
+    the receiver concatenates the chunks until the last one arrived. The ascii
+    framing has no flags, there a fragment is sent with the message number
+    FRAGMENT_NUMBER instead of the real one.
+
+    Compression:
+    With the binary framing a payload can be compressed, marked by the
+    FLAG_DEFLATE or FLAG_ZSTD flag on every fragment. The payload is compressed
+    before it is split into fragments and decompressed after reassembly.
+*/
+
+pub const MAGIC: &[u8; 8] = b"RustChat";
+pub const HEADER_SIZE: usize = 16;
+pub const BINARY_MAGIC: &[u8; 2] = b"RC";
+pub const BINARY_VERSION: u8 = 2;
+pub const BINARY_HEADER_SIZE: usize = 10;
+pub const MAX_PAYLOAD: usize = 1024;
+pub const MAX_PACKET_SIZE: usize = HEADER_SIZE + MAX_PAYLOAD;
+/// Size of the input buffer of a `Decoder`, room for several packets per read.
+pub const IN_BUFFER_SIZE: usize = 16 * MAX_PACKET_SIZE;
+pub const FRAGMENT_NUMBER: u32 = 999;
+/// Default limit for the payload size of a reassembled message.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// More fragments of the same message follow.
+pub const FLAG_FRAGMENT: u8 = 0b0000_0001;
+/// The payload is deflate compressed.
+pub const FLAG_DEFLATE: u8 = 0b0000_0010;
+/// The payload is zstd compressed.
+pub const FLAG_ZSTD: u8 = 0b0000_0100;
+const KNOWN_FLAGS: u8 = FLAG_FRAGMENT | FLAG_DEFLATE | FLAG_ZSTD;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Framing {
+    Ascii,
+    Binary,
+}
+
+/// The decoded fields of a message header.
+pub struct Header {
+    pub number: u32,
+    pub payload_size: usize,
+    pub flags: u8,
+}
+
+impl Framing {
+    pub fn header_size(self) -> usize {
+        match self {
+            Framing::Ascii => HEADER_SIZE,
+            Framing::Binary => BINARY_HEADER_SIZE,
+        }
+    }
+
+    fn read_header(self, buffer: &[u8]) -> Result<Header, DecodeError> {
+        match self {
+            Framing::Ascii => {
+                // load and check magic
+                if &buffer[0..8] != MAGIC {
+                    return Err(DecodeError::InvalidMagic);
+                }
+
+                // load message number
+                let number =
+                    parse_ascii_usize(&buffer[8..11]).ok_or(DecodeError::InvalidMessageNumber)?;
+
+                // load payload size
+                let payload_size =
+                    parse_ascii_usize(&buffer[11..16]).ok_or(DecodeError::InvalidPayloadSize)?;
+
+                if number == FRAGMENT_NUMBER as usize {
+                    Ok(Header {
+                        number: FRAGMENT_NUMBER,
+                        payload_size,
+                        flags: FLAG_FRAGMENT,
+                    })
+                } else {
+                    Ok(Header {
+                        number: number as u32,
+                        payload_size,
+                        flags: 0,
+                    })
+                }
+            }
+            Framing::Binary => {
+                // load and check magic and version
+                if &buffer[0..2] != BINARY_MAGIC {
+                    return Err(DecodeError::InvalidMagic);
+                }
+                if buffer[2] != BINARY_VERSION {
+                    return Err(DecodeError::InvalidVersion(buffer[2]));
+                }
+
+                let flags = buffer[9];
+                if flags & !KNOWN_FLAGS != 0 {
+                    return Err(DecodeError::InvalidFlags(flags));
+                }
+
+                Ok(Header {
+                    number: u16::from_le_bytes([buffer[3], buffer[4]]) as u32,
+                    payload_size: u32::from_le_bytes([buffer[5], buffer[6], buffer[7], buffer[8]])
+                        as usize,
+                    flags,
+                })
+            }
+        }
+    }
+
+    fn write_header(self, header: &Header, out_buffer: &mut Vec<u8>) {
+        match self {
+            Framing::Ascii => {
+                let number = match header.flags & FLAG_FRAGMENT {
+                    0 => header.number,
+                    _ => FRAGMENT_NUMBER,
+                };
+
+                // write magic
+                out_buffer.extend_from_slice(MAGIC);
+
+                // write message number
+                write_padded(out_buffer, number.to_string().as_bytes(), 3);
+
+                // write payload size
+                write_padded(out_buffer, header.payload_size.to_string().as_bytes(), 5);
+            }
+            Framing::Binary => {
+                out_buffer.extend_from_slice(BINARY_MAGIC);
+                out_buffer.push(BINARY_VERSION);
+                out_buffer.extend_from_slice(&(header.number as u16).to_le_bytes());
+                out_buffer.extend_from_slice(&(header.payload_size as u32).to_le_bytes());
+                out_buffer.push(header.flags);
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum MessageDecodeState {
+    WaitingForHeader,
+    WaitingForPayload,
+}
+
+enum PayloadDecodeResult {
+    Frame(Frame),
+    Fragment,
+    WaitingForData,
+}
+
+/// Reassembles frames from the bytes read from a stream.
+///
+/// Frames are decoded in place between `in_buffer_start` and `in_buffer_end`, the
+/// remaining bytes are moved to the front at most once per read in `unfilled`.
+pub struct Decoder {
+    framing: Framing,
+    codec: Codec,
+    in_buffer: Box<[u8; IN_BUFFER_SIZE]>,
+    in_buffer_start: usize,
+    in_buffer_end: usize,
+    // current message decode data/state begin
+    state: MessageDecodeState,
+    header: Header,
+    // current message decode data/state end
+    fragments: Vec<u8>,
+    max_message_size: usize,
+    compression_stats: CompressionStats,
+}
+
+impl Decoder {
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            framing: Framing::Ascii,
+            codec: Codec::Json,
+            in_buffer: Box::new([0; IN_BUFFER_SIZE]),
+            in_buffer_start: 0,
+            in_buffer_end: 0,
+            state: MessageDecodeState::WaitingForHeader,
+            header: Header {
+                number: 0,
+                payload_size: 0,
+                flags: 0,
+            },
+            fragments: Vec::new(),
+            max_message_size,
+            compression_stats: CompressionStats::default(),
+        }
+    }
+
+    /// Switches the framing, applies to all frames after the last decoded one.
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    /// Switches the codec, applies to all frames after the last decoded one.
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    /// Returns the compression stats of the decoded frames since the last call.
+    pub fn take_compression_stats(&mut self) -> CompressionStats {
+        std::mem::take(&mut self.compression_stats)
+    }
+
+    /// The free part of the input buffer, read from the stream into this.
+    /// Never empty, a not yet complete packet always fits.
+    pub fn unfilled(&mut self) -> &mut [u8] {
+        if self.in_buffer_start == self.in_buffer_end {
+            self.in_buffer_start = 0;
+            self.in_buffer_end = 0;
+        } else if IN_BUFFER_SIZE - self.in_buffer_end < MAX_PACKET_SIZE {
+            // compact, only the start of an incomplete packet is left
+            self.in_buffer
+                .copy_within(self.in_buffer_start..self.in_buffer_end, 0);
+            self.in_buffer_end -= self.in_buffer_start;
+            self.in_buffer_start = 0;
+        }
+
+        &mut self.in_buffer[self.in_buffer_end..]
+    }
+
+    /// Marks `n` bytes of `unfilled` as read.
+    pub fn filled(&mut self, n: usize) {
+        self.in_buffer_end += n;
+    }
+
+    fn buffered(&self) -> &[u8] {
+        &self.in_buffer[self.in_buffer_start..self.in_buffer_end]
+    }
+
+    /// Returns the next complete frame, or `None` if more data is needed.
+    pub fn decode(&mut self) -> Result<Option<Frame>, DecodeError> {
+        loop {
+            if self.state == MessageDecodeState::WaitingForHeader && !self.decode_header()? {
+                return Ok(None);
+            }
+
+            match self.decode_payload()? {
+                PayloadDecodeResult::Frame(frame) => return Ok(Some(frame)),
+                PayloadDecodeResult::Fragment => continue,
+                PayloadDecodeResult::WaitingForData => return Ok(None),
+            }
+        }
+    }
+
+    fn decode_header(&mut self) -> Result<bool, DecodeError> {
+        // check if the read data length is the header size or more
+        if self.buffered().len() < self.framing.header_size() {
+            return Ok(false); // just wait for more data
+        }
+
+        self.header = self.framing.read_header(self.buffered())?;
+
+        // check payload size
+        if self.header.payload_size == 0 {
+            return Err(DecodeError::InvalidPayloadSize);
+        }
+        let message_size = self.fragments.len() + self.header.payload_size;
+        if self.header.payload_size > MAX_PAYLOAD || message_size > self.max_message_size {
+            return Err(DecodeError::PayloadTooLarge(message_size));
+        }
+
+        self.state = MessageDecodeState::WaitingForPayload;
+        Ok(true)
+    }
+
+    fn decode_payload(&mut self) -> Result<PayloadDecodeResult, DecodeError> {
+        let header_size = self.framing.header_size();
+        let packet_size = header_size + self.header.payload_size;
+
+        // check if the read data length is the header size plus payload size or more
+        if self.buffered().len() < packet_size {
+            return Ok(PayloadDecodeResult::WaitingForData);
+        }
+
+        let packet_start = self.in_buffer_start;
+        let payload = &self.in_buffer[packet_start + header_size..packet_start + packet_size];
+        let result = if self.header.flags & FLAG_FRAGMENT != 0 {
+            self.fragments.extend_from_slice(payload);
+            Ok(PayloadDecodeResult::Fragment)
+        } else if self.fragments.is_empty() {
+            decode_frame(
+                &self.header,
+                payload,
+                self.codec,
+                self.max_message_size,
+                &mut self.compression_stats,
+            )
+            .map(PayloadDecodeResult::Frame)
+        } else {
+            // last fragment, decode the reassembled payload
+            let mut fragments = std::mem::take(&mut self.fragments);
+            fragments.extend_from_slice(payload);
+            let header = Header {
+                number: self.header.number,
+                payload_size: fragments.len(),
+                flags: self.header.flags,
+            };
+            decode_frame(
+                &header,
+                &fragments,
+                self.codec,
+                self.max_message_size,
+                &mut self.compression_stats,
+            )
+            .map(PayloadDecodeResult::Frame)
+        };
+
+        // skip packet, the bytes are reused after the next compaction
+        self.in_buffer_start += packet_size;
+
+        self.state = MessageDecodeState::WaitingForHeader;
+        result
+    }
+}
+
+/// Decompresses the payload if needed, no more than `max_message_size` bytes.
+fn decode_frame(
+    header: &Header,
+    payload: &[u8],
+    codec: Codec,
+    max_message_size: usize,
+    compression_stats: &mut CompressionStats,
+) -> Result<Frame, DecodeError> {
+    match Compression::from_flags(header.flags)? {
+        Some(compression) => {
+            let decompressed = compression.decompress(payload, max_message_size)?;
+            compression_stats.add(decompressed.len(), payload.len());
+            Frame::decode(header, &decompressed, codec)
+        }
+        None => Frame::decode(header, payload, codec),
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_MESSAGE_SIZE)
+    }
+}
+
+/// Writes messages with header into an output buffer.
+pub struct Encoder {
+    framing: Framing,
+    max_message_size: usize,
+    compression: Option<Compression>,
+    compression_threshold: usize,
+    compression_stats: CompressionStats,
+}
+
+impl Encoder {
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            framing: Framing::Ascii,
+            max_message_size,
+            compression: None,
+            compression_threshold: usize::MAX,
+            compression_stats: CompressionStats::default(),
+        }
+    }
+
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    /// Compresses payloads of at least `threshold` bytes, ignored by the ascii framing.
+    pub fn set_compression(&mut self, compression: Option<Compression>, threshold: usize) {
+        self.compression = compression;
+        self.compression_threshold = threshold;
+    }
+
+    /// Returns the compression stats of the encoded messages since the last call.
+    pub fn take_compression_stats(&mut self) -> CompressionStats {
+        std::mem::take(&mut self.compression_stats)
+    }
+
+    /// Appends header and payload of `message` to `out_buffer`, split into fragments if needed.
+    pub fn encode(
+        &mut self,
+        message: &Message,
+        out_buffer: &mut Vec<u8>,
+    ) -> Result<(), EncodeError> {
+        let mut payload = &message.payload[..];
+
+        if payload.len() > self.max_message_size {
+            return Err(EncodeError::PayloadTooLarge(payload.len()));
+        }
+
+        let mut compression_flag = 0;
+        let compressed;
+        if let Some(compression) = self.compression {
+            if self.framing == Framing::Binary && payload.len() >= self.compression_threshold {
+                compressed = compression.compress(payload)?;
+                // incompressible payloads are sent as they are
+                if compressed.len() < payload.len() {
+                    self.compression_stats.add(payload.len(), compressed.len());
+                    compression_flag = compression.flag();
+                    payload = &compressed;
+                }
+            }
+        }
+
+        let mut chunks = payload.chunks(MAX_PAYLOAD).peekable();
+        while let Some(chunk) = chunks.next() {
+            let flags = match chunks.peek() {
+                Some(_) => FLAG_FRAGMENT | compression_flag,
+                None => compression_flag,
+            };
+            let header = Header {
+                number: message.number,
+                payload_size: chunk.len(),
+                flags,
+            };
+
+            self.framing.write_header(&header, out_buffer);
+            out_buffer.extend_from_slice(chunk);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_MESSAGE_SIZE)
+    }
+}
+
+/// Parses space padded ascii digits without allocating.
+fn parse_ascii_usize(field: &[u8]) -> Option<usize> {
+    let digits = match field.iter().position(|&byte| byte == b' ') {
+        Some(end) if field[end..].iter().all(|&byte| byte == b' ') => &field[..end],
+        Some(_) => return None,
+        None => field,
+    };
+
+    if digits.is_empty() {
+        return None;
+    }
+
+    digits.iter().try_fold(0usize, |value, &byte| match byte {
+        b'0'..=b'9' => Some(value * 10 + (byte - b'0') as usize),
+        _ => None,
+    })
+}
+
+fn write_padded(out_buffer: &mut Vec<u8>, value: &[u8], field_size: usize) {
+    out_buffer.extend_from_slice(value);
+    out_buffer.resize(out_buffer.len() + field_size - value.len(), b' ');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{PingMessage, PublishGlobalChatMessage};
+
+    const CODECS: [Codec; 3] = [Codec::Json, Codec::MessagePack, Codec::Cbor];
+    const FRAMINGS: [Framing; 2] = [Framing::Ascii, Framing::Binary];
+
+    /// Feeds `bytes` in pieces of at most `piece_size` bytes and decodes after every piece.
+    fn decode_all(
+        decoder: &mut Decoder,
+        bytes: &[u8],
+        piece_size: usize,
+    ) -> Result<Vec<Frame>, DecodeError> {
+        let mut frames = Vec::new();
+        for piece in bytes.chunks(piece_size) {
+            let mut piece = piece;
+            while !piece.is_empty() {
+                let unfilled = decoder.unfilled();
+                let n = unfilled.len().min(piece.len());
+                unfilled[..n].copy_from_slice(&piece[..n]);
+                decoder.filled(n);
+                piece = &piece[n..];
+
+                while let Some(frame) = decoder.decode()? {
+                    frames.push(frame);
+                }
+            }
+        }
+        Ok(frames)
+    }
+
+    fn publish(text: &str, codec: Codec) -> Message {
+        Message::new(
+            PublishGlobalChatMessage {
+                message: text.to_string(),
+            },
+            codec,
+        )
+    }
+
+    fn encode(framing: Framing, message: &Message) -> Vec<u8> {
+        let mut encoder = Encoder::default();
+        encoder.set_framing(framing);
+        let mut out_buffer = Vec::new();
+        encoder.encode(message, &mut out_buffer).unwrap();
+        out_buffer
+    }
+
+    fn published_text(frame: &Frame) -> &str {
+        match frame {
+            Frame::PublishGlobalChat(message) => &message.message,
+            _ => panic!("unexpected frame {}", frame.number()),
+        }
+    }
+
+    fn new_decoder(framing: Framing, codec: Codec, max_message_size: usize) -> Decoder {
+        let mut decoder = Decoder::new(max_message_size);
+        decoder.set_framing(framing);
+        decoder.set_codec(codec);
+        decoder
+    }
+
+    /// Pseudo random text of 16 letters, compresses to about half.
+    fn mixed_text(len: usize) -> String {
+        let mut seed = 1u32;
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (b'a' + (seed >> 16) as u8 % 16) as char
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decoder_rejects_invalid_headers() {
+        let cases: [(&[u8], DecodeError); 5] = [
+            (b"RustChad2  1    ", DecodeError::InvalidMagic),
+            (b"RustChat 2 1    ", DecodeError::InvalidMessageNumber),
+            (b"RustChat2  1 1  ", DecodeError::InvalidPayloadSize),
+            (b"RustChat2  0    ", DecodeError::InvalidPayloadSize),
+            (b"RustChat9982    {}", DecodeError::UnknownMessage(998)),
+        ];
+        for (bytes, err) in cases {
+            assert_eq!(
+                decode_all(&mut Decoder::default(), bytes, 16).err(),
+                Some(err)
+            );
+        }
+    }
+
+    #[test]
+    fn decoder_rejects_invalid_payload() {
+        let result = decode_all(&mut Decoder::default(), b"RustChat2  2    {}", 16);
+        assert!(matches!(
+            result,
+            Err(DecodeError::InvalidPayload { number: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn ascii_fragments_use_fragment_number() {
+        let message = publish(&"x".repeat(2 * MAX_PAYLOAD), Codec::Json);
+        let bytes = encode(Framing::Ascii, &message);
+
+        // 2 full fragments and the rest with the real number
+        let packets: Vec<&[u8]> = bytes.chunks(MAX_PACKET_SIZE).collect();
+        assert_eq!(packets.len(), 3);
+        assert_eq!(&packets[0][8..16], b"9991024 ");
+        assert_eq!(&packets[1][8..16], b"9991024 ");
+        assert_eq!(&packets[2][8..11], b"2  ");
+
+        let frames = decode_all(&mut Decoder::default(), &bytes, 100).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(published_text(&frames[0]), "x".repeat(2 * MAX_PAYLOAD));
+    }
+
+    #[test]
+    fn encoder_rejects_oversize_payload() {
+        let message = publish(&"x".repeat(200), Codec::Json);
+        let mut encoder = Encoder::new(100);
+        let mut out_buffer = Vec::new();
+
+        assert_eq!(
+            encoder.encode(&message, &mut out_buffer),
+            Err(EncodeError::PayloadTooLarge(message.payload.len()))
+        );
+        assert!(out_buffer.is_empty());
+    }
+
+    #[test]
+    fn decoder_rejects_oversize_packet() {
+        let result = decode_all(&mut Decoder::default(), b"RustChat2  1025 ", 16);
+        assert_eq!(
+            result.err(),
+            Some(DecodeError::PayloadTooLarge(MAX_PAYLOAD + 1))
+        );
+    }
+
+    #[test]
+    fn decoder_rejects_oversize_message() {
+        // the fragments are rejected before the whole message is buffered
+        let message = publish(&"x".repeat(3 * MAX_PAYLOAD), Codec::Json);
+        let bytes = encode(Framing::Ascii, &message);
+
+        let result = decode_all(&mut Decoder::new(2000), &bytes, bytes.len());
+        assert_eq!(
+            result.err(),
+            Some(DecodeError::PayloadTooLarge(2 * MAX_PAYLOAD))
+        );
+    }
+
+    #[test]
+    fn binary_fragments_use_fragment_flag() {
+        let message = publish(&"x".repeat(MAX_PAYLOAD), Codec::Json);
+        let bytes = encode(Framing::Binary, &message);
+
+        let packet_size = BINARY_HEADER_SIZE + MAX_PAYLOAD;
+        assert_eq!(
+            bytes.len(),
+            packet_size + BINARY_HEADER_SIZE + message.payload.len() - MAX_PAYLOAD
+        );
+        assert_eq!(&bytes[3..5], &2u16.to_le_bytes());
+        assert_eq!(bytes[9], FLAG_FRAGMENT);
+        assert_eq!(bytes[packet_size + 9], 0);
+    }
+
+    #[test]
+    fn decoder_rejects_invalid_binary_headers() {
+        let cases: [(&[u8], DecodeError); 3] = [
+            (
+                b"RC\x03\x02\x00\x01\x00\x00\x00\x00",
+                DecodeError::InvalidVersion(3),
+            ),
+            (
+                b"RC\x02\x02\x00\x01\x00\x00\x00\x08",
+                DecodeError::InvalidFlags(0b1000),
+            ),
+            (
+                b"RC\x02\x02\x00\xff\xff\xff\xff\x00",
+                DecodeError::PayloadTooLarge(u32::MAX as usize),
+            ),
+        ];
+        for (bytes, err) in cases {
+            let mut decoder = new_decoder(Framing::Binary, Codec::Json, DEFAULT_MAX_MESSAGE_SIZE);
+            assert_eq!(decode_all(&mut decoder, bytes, 16).err(), Some(err));
+        }
+    }
+
+    #[test]
+    fn round_trip_all_framings_and_codecs() {
+        let texts = ["hello", &"x".repeat(MAX_PAYLOAD), &mixed_text(5000)];
+        for framing in FRAMINGS {
+            for codec in CODECS {
+                let bytes: Vec<u8> = texts
+                    .iter()
+                    .flat_map(|text| encode(framing, &publish(text, codec)))
+                    .collect();
+
+                let frames = decode_all(&mut new_decoder(framing, codec, 64 * 1024), &bytes, 4096)
+                    .unwrap_or_else(|err| panic!("{:?} {:?}: {}", framing, codec, err));
+
+                assert_eq!(frames.len(), texts.len());
+                for (frame, text) in frames.iter().zip(texts) {
+                    assert_eq!(published_text(frame), text);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compressed_fragments_round_trip() {
+        let text = mixed_text(20_000);
+        for compression in [Compression::Deflate, Compression::Zstd] {
+            let message = publish(&text, Codec::MessagePack);
+            let mut encoder = Encoder::default();
+            encoder.set_framing(Framing::Binary);
+            encoder.set_compression(Some(compression), 0);
+            let mut bytes = Vec::new();
+            encoder.encode(&message, &mut bytes).unwrap();
+
+            // compressed before the split, every fragment has the compression flag
+            assert!(bytes.len() < message.payload.len());
+            assert!(bytes.len() > BINARY_HEADER_SIZE + MAX_PAYLOAD);
+            assert_eq!(bytes[9], FLAG_FRAGMENT | compression.flag());
+
+            let mut decoder = new_decoder(Framing::Binary, Codec::MessagePack, 64 * 1024);
+            let frames = decode_all(&mut decoder, &bytes, 1000).unwrap();
+            assert_eq!(frames.len(), 1);
+            assert_eq!(published_text(&frames[0]), text);
+            assert_eq!(
+                decoder.take_compression_stats().uncompressed,
+                message.payload.len()
+            );
+        }
+    }
+
+    #[test]
+    fn decoder_limits_decompressed_size() {
+        // a few hundred compressed bytes, more than the limit after decompression
+        let payload = Compression::Zstd.compress(&[b' '; 100_000]).unwrap();
+        assert!(payload.len() <= MAX_PAYLOAD);
+
+        let mut bytes = Vec::new();
+        Framing::Binary.write_header(
+            &Header {
+                number: 2,
+                payload_size: payload.len(),
+                flags: FLAG_ZSTD,
+            },
+            &mut bytes,
+        );
+        bytes.extend_from_slice(&payload);
+
+        let mut decoder = new_decoder(Framing::Binary, Codec::Json, DEFAULT_MAX_MESSAGE_SIZE);
+        assert_eq!(
+            decode_all(&mut decoder, &bytes, bytes.len()).err(),
+            Some(DecodeError::PayloadTooLarge(DEFAULT_MAX_MESSAGE_SIZE + 1))
+        );
+    }
+
+    #[test]
+    fn ascii_framing_never_compresses() {
+        let message = publish(&"x".repeat(2000), Codec::Json);
+        let mut encoder = Encoder::default();
+        encoder.set_compression(Some(Compression::Deflate), 0);
+        let mut bytes = Vec::new();
+        encoder.encode(&message, &mut bytes).unwrap();
+
+        assert_eq!(bytes.len(), 2 * HEADER_SIZE + message.payload.len());
+    }
+
+    #[test]
+    fn decoder_rejects_two_compressions() {
+        let mut decoder = new_decoder(Framing::Binary, Codec::Json, DEFAULT_MAX_MESSAGE_SIZE);
+        assert_eq!(
+            decode_all(&mut decoder, b"RC\x02\x02\x00\x01\x00\x00\x00\x06 ", 16).err(),
+            Some(DecodeError::InvalidFlags(FLAG_DEFLATE | FLAG_ZSTD))
+        );
+    }
+
+    #[test]
+    fn split_header() {
+        for framing in FRAMINGS {
+            let message = Message::new(
+                PingMessage {
+                    nonce: 7,
+                    reply: false,
+                },
+                Codec::Json,
+            );
+            let bytes = encode(framing, &message);
+            let mut decoder = new_decoder(framing, Codec::Json, DEFAULT_MAX_MESSAGE_SIZE);
+
+            // one byte per read, nothing is decoded before the last byte
+            for (i, byte) in bytes.iter().enumerate() {
+                decoder.unfilled()[0] = *byte;
+                decoder.filled(1);
+                let frame = decoder.decode().unwrap();
+                assert_eq!(
+                    frame.is_some(),
+                    i == bytes.len() - 1,
+                    "{:?} byte {}",
+                    framing,
+                    i
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn many_packets_in_one_read() {
+        // more packets than fit into the input buffer, it is compacted between reads
+        let bytes: Vec<u8> = (0..100)
+            .flat_map(|i| {
+                encode(
+                    Framing::Ascii,
+                    &publish(&i.to_string().repeat(200), Codec::Json),
+                )
+            })
+            .collect();
+        assert!(bytes.len() > IN_BUFFER_SIZE);
+
+        let frames = decode_all(&mut Decoder::default(), &bytes, bytes.len()).unwrap();
+        assert_eq!(frames.len(), 100);
+        assert_eq!(published_text(&frames[42]), "42".repeat(200));
+    }
+}
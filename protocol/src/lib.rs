@@ -0,0 +1,8 @@
+pub mod codec;
+pub mod compression;
+pub mod error;
+pub mod frame;
+pub mod framing;
+pub mod message;
+pub mod messages;
+pub mod transport;
@@ -0,0 +1,29 @@
+use crate::codec::Codec;
+use crate::frame::Frame;
+
+/// An encoded frame, ready to be written to one or more connections.
+pub struct Message {
+    pub payload: Vec<u8>,
+    pub number: u32,
+    /// See `Frame::is_critical`.
+    pub critical: bool,
+}
+
+impl Message {
+    pub fn new<T: Into<Frame>>(message: T, codec: Codec) -> Self {
+        message
+            .into()
+            .encode(codec)
+            .expect("Error while serialize message!")
+    }
+}
+
+impl Clone for Message {
+    fn clone(&self) -> Self {
+        Self {
+            payload: self.payload.clone(),
+            number: self.number,
+            critical: self.critical,
+        }
+    }
+}
@@ -0,0 +1,500 @@
+use crate::codec::Codec;
+use crate::compression::Compression;
+use crate::framing::Framing;
+use serde::{Deserialize, Serialize};
+
+/// Protocol version sent in the `HelloMessage` and `WelcomeMessage`.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest protocol version that is still compatible with `PROTOCOL_VERSION`.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// User names have 1 to this many characters, see `is_valid_user_name`.
+pub const MAX_USER_NAME_LENGTH: usize = 32;
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+/// Room names follow the rules of user names, see `is_valid_room_name`.
+pub const MAX_ROOM_NAME_LENGTH: usize = 32;
+pub const MAX_TOPIC_LENGTH: usize = 256;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PingMessage {
+    pub nonce: u32,
+    pub reply: bool,
+}
+
+/// Credentials of a `LoginMessage`.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum Auth {
+    /// Logs in to the account of the user, the account is created with the first login.
+    Password(String),
+    /// Session token of a previous `LoginResultMessage`.
+    Token(String),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LoginMessage {
+    pub user_name: String,
+    /// Guests log in without credentials, if the server allows it and the name has no account.
+    #[serde(default)]
+    pub auth: Option<Auth>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoginError {
+    /// The name is empty, too long or contains other characters than letters, digits, `_`,
+    /// `-` and `.`.
+    InvalidName,
+    ReservedName,
+    NameTaken,
+    /// A connection can only log in once.
+    AlreadyLoggedIn,
+    /// The name has an account or the server does not allow guests.
+    AuthRequired,
+    InvalidCredentials,
+    /// Passwords of new accounts need at least `MIN_PASSWORD_LENGTH` characters.
+    WeakPassword,
+    /// Too many failed logins or new accounts, try again later.
+    TooManyAttempts,
+    /// The name or the address is banned from the server.
+    Banned,
+}
+
+/// Reply to a `LoginMessage`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LoginResultMessage {
+    pub user_name: String,
+    /// `None` if the login succeeded.
+    pub error: Option<LoginError>,
+    /// Allows to log in again with `Auth::Token`, only for logins with credentials.
+    #[serde(default)]
+    pub session_token: Option<String>,
+}
+
+/// Sent to clients with `Capability::Presence` if a user logged in.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserJoinedMessage {
+    pub user_name: String,
+}
+
+/// Sent to clients with `Capability::Presence` if a user disconnected.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserLeftMessage {
+    pub user_name: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ListUsersMessage {}
+
+/// Reply to a `ListUsersMessage`, the logged in users sorted by name.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserListMessage {
+    pub user_names: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PublishGlobalChatMessage {
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GlobalChatMessage {
+    pub user_name: String,
+    pub message: String,
+    /// Assigned by the server, increasing over all chat messages.
+    #[serde(default)]
+    pub message_id: u64,
+    /// Milliseconds since the Unix epoch (UTC) when the server accepted the message.
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PublishPrivateChatMessage {
+    pub to_user_name: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PrivateChatMessage {
+    pub from_user_name: String,
+    pub message: String,
+    /// Assigned by the server like the id of a `GlobalChatMessage`, see `ReadMessage`.
+    #[serde(default)]
+    pub message_id: u64,
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeliveryStatus {
+    Delivered,
+    /// The user has an account but is not logged in, the message is delivered with the next
+    /// login.
+    Deferred,
+    UserNotFound,
+    /// Too many messages for the offline user are queued.
+    QueueFull,
+}
+
+/// Reply to a `PublishPrivateChatMessage`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PrivateChatResultMessage {
+    pub to_user_name: String,
+    pub status: DeliveryStatus,
+    /// Id of the delivered or deferred message, `None` if it was rejected.
+    #[serde(default)]
+    pub message_id: Option<u64>,
+}
+
+/// Chat of a `FetchHistoryMessage`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum HistoryScope {
+    Global,
+    /// Needs to be a member of the room.
+    Room(String),
+    /// Private messages between the user and the other user.
+    User(String),
+}
+
+/// Requests a page of the history, the reply is a `HistoryMessage`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FetchHistoryMessage {
+    pub scope: HistoryScope,
+    /// Only messages with a smaller id, `None` for the newest messages.
+    pub before_id: Option<u64>,
+    /// The server sends at most its own limit, and fewer if the page would exceed the maximum
+    /// message size.
+    pub limit: usize,
+}
+
+/// A chat message of the history, see `GlobalChatMessage` for the fields.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct HistoryEntry {
+    pub message_id: u64,
+    pub timestamp: u64,
+    pub user_name: String,
+    pub message: String,
+    /// `message` is the text of the last edit.
+    #[serde(default)]
+    pub edited: bool,
+}
+
+/// Reply to a `FetchHistoryMessage`, also sent with the newest global messages after a login
+/// to clients with `Capability::History`. The messages are sorted by id, the oldest first.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HistoryMessage {
+    pub scope: HistoryScope,
+    pub messages: Vec<HistoryEntry>,
+}
+
+/// Where a user is typing.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum Conversation {
+    Room(String),
+    /// Private messages to the user.
+    User(String),
+}
+
+/// The user is typing a message to `to`, sent again while the user keeps typing. The server
+/// forwards at most one per few seconds.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TypingMessage {
+    pub to: Conversation,
+}
+
+/// Sent to clients with `Capability::Typing` if `user_name` is typing in `room`, or to them
+/// without room.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserTypingMessage {
+    pub user_name: String,
+    pub room: Option<String>,
+}
+
+/// Marks a received `PrivateChatMessage` as read.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReadMessage {
+    pub message_id: u64,
+}
+
+/// Sent to the author of a private message with `Capability::Typing` if `user_name` read it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MessageReadMessage {
+    pub message_id: u64,
+    pub user_name: String,
+}
+
+/// Replaces the text of a chat message. Authors edit their own messages, operators of a room
+/// and global operators also the public messages of others.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EditMessage {
+    pub message_id: u64,
+    pub message: String,
+}
+
+/// Retracts a chat message, allowed like an `EditMessage`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DeleteMessage {
+    pub message_id: u64,
+}
+
+/// Sent to the users who received the edited message if they have `Capability::Edit`.
+/// `user_name` edited the message.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MessageEditedMessage {
+    pub message_id: u64,
+    pub message: String,
+    pub user_name: String,
+}
+
+/// Sent to the users who received the deleted message if they have `Capability::Edit`.
+/// `user_name` deleted the message.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MessageDeletedMessage {
+    pub message_id: u64,
+    pub user_name: String,
+}
+
+/// Joins the room, it is created if it does not exist.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JoinRoomMessage {
+    pub room: String,
+}
+
+/// Leaves the room, it is removed with its last member.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LeaveRoomMessage {
+    pub room: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RoomAction {
+    Join,
+    Leave,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RoomError {
+    /// The name is empty, too long or contains other characters than letters, digits, `_`,
+    /// `-` and `.`.
+    InvalidName,
+    AlreadyMember,
+    NotMember,
+    /// A user can be member of at most as many rooms as the server allows.
+    TooManyRooms,
+    /// The name or the address is banned from the room.
+    Banned,
+}
+
+/// Reply to a `JoinRoomMessage` or `LeaveRoomMessage`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RoomResultMessage {
+    pub room: String,
+    pub action: RoomAction,
+    /// `None` if the action succeeded.
+    pub error: Option<RoomError>,
+}
+
+/// Only members of the room can publish to it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PublishRoomMessage {
+    pub room: String,
+    pub message: String,
+}
+
+/// Sent to all members of the room, including the author.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RoomMessage {
+    pub room: String,
+    pub user_name: String,
+    pub message: String,
+    /// Assigned by the server like the id of a `GlobalChatMessage`.
+    #[serde(default)]
+    pub message_id: u64,
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ListRoomsMessage {}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct RoomInfo {
+    pub name: String,
+    pub members: usize,
+}
+
+/// Reply to a `ListRoomsMessage`, sorted by name.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RoomListMessage {
+    pub rooms: Vec<RoomInfo>,
+}
+
+/// Role of a user in a room, the user creating the room is its owner. Roles only count for users
+/// logged in with credentials, guests are members. A room with roles keeps them when it is
+/// empty, a user joining it later is a member.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Role {
+    Member,
+    Operator,
+    Owner,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ModerationAction {
+    /// Removes the user from the room, or from the server without room.
+    Kick,
+    /// Kicks the user and bans its name and address until `Unban`.
+    Ban,
+    Unban,
+    /// The user can not publish to or edit in the room. Without room the user can not publish
+    /// to or edit in any chat, including private chats.
+    Mute,
+    Unmute,
+    /// Only the owner of a room gives roles, the owner role can not be given.
+    SetRole(Role),
+}
+
+/// Moderates `user_name` in `room`, or on the whole server without room.
+///
+/// Operators and owners moderate users with a lower role in their room, global operators
+/// moderate everyone else in all rooms and on the whole server.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ModerationMessage {
+    pub room: Option<String>,
+    pub user_name: String,
+    pub action: ModerationAction,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ModerationError {
+    NotPermitted,
+    RoomNotFound,
+    /// `Kick` needs a logged in user.
+    UserNotFound,
+    /// The user is not in the room.
+    NotMember,
+}
+
+/// Reply to a `ModerationMessage`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ModerationResultMessage {
+    pub room: Option<String>,
+    pub user_name: String,
+    pub action: ModerationAction,
+    /// `None` if the action succeeded.
+    pub error: Option<ModerationError>,
+}
+
+/// Sent to the moderated user.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ModeratedMessage {
+    pub room: Option<String>,
+    pub action: ModerationAction,
+    pub by_user_name: String,
+}
+
+/// Needs the operator role in the room, an empty topic removes it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SetTopicMessage {
+    pub room: String,
+    pub topic: String,
+}
+
+/// Sent to all members if the topic changes, and to new members.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RoomTopicMessage {
+    pub room: String,
+    pub topic: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorCode {
+    InvalidData,
+    PayloadTooLarge,
+    UnknownMessage,
+    InvalidPayload,
+    InvalidHandshake,
+    IncompatibleVersion,
+    SlowConsumer,
+    NotLoggedIn,
+    UnknownUser,
+    /// A `PublishRoomMessage` for a room the user has not joined.
+    NotInRoom,
+    /// The role of the user does not allow the action.
+    NotPermitted,
+    /// The user is muted in the room or on the whole server.
+    Muted,
+    /// An edit or delete of a message that is not in the history.
+    UnknownMessageId,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ErrorMessage {
+    pub code: ErrorCode,
+    pub reason: String,
+    /// Message number of the rejected frame, if it could be read.
+    pub in_reply_to: Option<u32>,
+}
+
+/// Optional features, only enabled if both sides support them.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Capability {
+    Compression,
+    Rooms,
+    /// `UserTypingMessage` and `MessageReadMessage`.
+    Typing,
+    /// `HistoryMessage` after the login.
+    History,
+    /// `UserJoinedMessage` and `UserLeftMessage` for all users.
+    Presence,
+    /// `MessageEditedMessage` and `MessageDeletedMessage`.
+    Edit,
+}
+
+/// First message of a client, always sent with the ascii framing.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HelloMessage {
+    pub version: u32,
+    pub client_name: String,
+    pub capabilities: Vec<Capability>,
+    /// Supported framings, the preferred one first.
+    pub framings: Vec<Framing>,
+    /// Supported payload codecs, the preferred one first. `Codec::Json` if empty.
+    #[serde(default)]
+    pub codecs: Vec<Codec>,
+    /// Supported compressions, the preferred one first. Needs `Capability::Compression`.
+    #[serde(default)]
+    pub compressions: Vec<Compression>,
+}
+
+/// Reply to the `HelloMessage`, all following frames use `framing` and `codec`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WelcomeMessage {
+    pub version: u32,
+    pub server_name: String,
+    /// Capabilities supported by both sides.
+    pub capabilities: Vec<Capability>,
+    pub framing: Framing,
+    #[serde(default)]
+    pub codec: Codec,
+    /// Compression for payloads of at least `compression_threshold` bytes, if any.
+    #[serde(default)]
+    pub compression: Option<Compression>,
+    #[serde(default)]
+    pub compression_threshold: usize,
+}
+
+pub fn is_valid_user_name(user_name: &str) -> bool {
+    is_valid_name(user_name, MAX_USER_NAME_LENGTH)
+}
+
+pub fn is_valid_room_name(room: &str) -> bool {
+    is_valid_name(room, MAX_ROOM_NAME_LENGTH)
+}
+
+fn is_valid_name(name: &str, max_length: usize) -> bool {
+    !name.is_empty()
+        && name.chars().count() <= max_length
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
@@ -0,0 +1,131 @@
+use rustls::Connection;
+use std::io::{self, Read, Write};
+
+/// Socket of a connection, optionally encrypted with TLS. Used by server and client, `tls` is
+/// the server or the client side of the TLS connection.
+///
+/// `read` and `write` behave like the non-blocking socket, they work on the plaintext and return
+/// `WouldBlock` until the TLS handshake is done.
+pub enum Transport<S> {
+    Plain(S),
+    Tls(Box<TlsTransport<S>>),
+}
+
+pub struct TlsTransport<S> {
+    socket: S,
+    tls: Connection,
+}
+
+impl<S> Transport<S> {
+    /// Starts the TLS handshake, the first records are written with the next `flush_tls`.
+    pub fn tls<C: Into<Connection>>(socket: S, tls: C) -> Self {
+        Transport::Tls(Box::new(TlsTransport {
+            socket,
+            tls: tls.into(),
+        }))
+    }
+
+    pub fn socket(&self) -> &S {
+        match self {
+            Transport::Plain(socket) => socket,
+            Transport::Tls(transport) => &transport.socket,
+        }
+    }
+
+    pub fn socket_mut(&mut self) -> &mut S {
+        match self {
+            Transport::Plain(socket) => socket,
+            Transport::Tls(transport) => &mut transport.socket,
+        }
+    }
+
+    pub fn into_socket(self) -> S {
+        match self {
+            Transport::Plain(socket) => socket,
+            Transport::Tls(transport) => transport.socket,
+        }
+    }
+
+    /// Returns `true` if encrypted data waits for a writable socket.
+    pub fn wants_write(&self) -> bool {
+        match self {
+            Transport::Plain(_) => false,
+            Transport::Tls(transport) => transport.tls.wants_write(),
+        }
+    }
+}
+
+impl<S: Write> Transport<S> {
+    /// Writes the buffered encrypted data, as much as the socket takes.
+    pub fn flush_tls(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Plain(_) => Ok(()),
+            Transport::Tls(transport) => transport.flush_tls(),
+        }
+    }
+}
+
+impl<S: Write> TlsTransport<S> {
+    fn flush_tls(&mut self) -> io::Result<()> {
+        while self.tls.wants_write() {
+            match self.tls.write_tls(&mut self.socket) {
+                Ok(_) => {}
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Read for Transport<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let transport = match self {
+            Transport::Plain(socket) => return socket.read(buf),
+            Transport::Tls(transport) => transport,
+        };
+
+        loop {
+            // plaintext of already received records first
+            match transport.tls.reader().read(buf) {
+                Ok(n) => return Ok(n),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+
+            if transport.tls.read_tls(&mut transport.socket)? == 0 {
+                return Ok(0);
+            }
+
+            let result = transport.tls.process_new_packets();
+            // handshake messages and alerts are answered right away
+            transport.flush_tls()?;
+            result.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        }
+    }
+}
+
+impl<S: Write> Write for Transport<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let transport = match self {
+            Transport::Plain(socket) => return socket.write(buf),
+            Transport::Tls(transport) => transport,
+        };
+
+        let n = transport.tls.writer().write(buf)?;
+        transport.flush_tls()?;
+
+        // the buffer of rustls is full until the socket takes more
+        if n == 0 && !buf.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_tls()
+    }
+}